@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 // These flags are useful for debugging and hacking on Wren itself. They are not
 // intended to be used for production code. They default to off.
 
@@ -83,3 +85,8 @@ pub(crate) const MAX_FIELDS: i32 = 255;
 
 // assert!()
 // unreachable!()
+
+// The maximum length in bytes of a string built by repeating another one. This is
+// an arbitrary but enforced maximum so that a mistaken count like `"ab" * 1e20`
+// is a runtime error instead of exhausting the host's memory.
+pub(crate) const MAX_REPEATED_STRING: usize = 1 << 30;
@@ -1,6 +1,8 @@
-use crate::common::MAX_VARIABLE_NAME;
+use crate::common::{MAX_FIELDS, MAX_METHOD_NAME, MAX_PARAMETERS, MAX_VARIABLE_NAME};
+use crate::opcodes::Code;
+use crate::utils::{utf8_encode, utf8_encode_num_bytes, SymbolTable};
 use crate::value::*;
-use crate::vm::WrenVM;
+use crate::vm::{ErrorType, WrenVM};
 
 // This is written in bottom-up order, so the tokenization comes first, then
 // parsing/code generation. This minimizes the number of explicit forward
@@ -12,7 +14,8 @@ use crate::vm::WrenVM;
 //
 // Note that this limitation is also explicit in the bytecode. Since
 // `CODE_LOAD_LOCAL` and `CODE_STORE_LOCAL` use a single argument byte to
-// identify the local, only 256 can be in scope at one time.
+// identify the local, only 256 can be in scope at one time. Slot zero always
+// holds the receiver or the function itself, so code can declare 255 of them.
 pub(crate) const MAX_LOCALS: usize = 256;
 
 // The maximum number of upvalues (i.e. variables from enclosing functions)
@@ -22,7 +25,7 @@ pub(crate) const MAX_UPVALUES: usize = 256;
 // The maximum number of distinct constants that a function can contain. This
 // value is explicit in the bytecode since `CODE_CONSTANT` only takes a single
 // two-byte argument.
-pub(crate) const MAX_CONSTANTS: i32 = 1 << 16;
+pub(crate) const MAX_CONSTANTS: usize = 1 << 16;
 
 // The maximum distance a CODE_JUMP or CODE_JUMP_IF instruction can move the
 // instruction pointer.
@@ -34,14 +37,13 @@ pub(crate) const MAX_JUMP: i32 = 1 << 16;
 //      "outside %(one + "%(two + "%(three)")")"
 pub(crate) const MAX_INTERPOLATION_NESTING: usize = 8;
 
-// The buffer size used to format a compile error message, excluding the header
-// with the module name and error location. Using a hardcoded buffer for this
-// is kind of hairy, but fortunately we can control what the longest possible
-// message is and handle that. Ideally, we'd use `snprintf()`, but that's not
-// available in standard C++98.
-pub(crate) const ERROR_MESSAGE_SIZE: i32 = 80 + MAX_VARIABLE_NAME + 15;
+// The default maximum depth that expressions and statements can nest inside
+// each other. The parser is recursive, so without a limit, deeply nested
+// source would overflow the native stack.
+pub(crate) const MAX_NESTING: usize = 256;
 
-#[allow(dead_code, non_camel_case_types)]
+#[allow(dead_code, non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum TokenType {
     LEFT_PAREN,
     RIGHT_PAREN,
@@ -70,6 +72,7 @@ enum TokenType {
     BANG,
     TILDE,
     QUESTION,
+    QUESTIONDOT,
     EQ,
     LT,
     GT,
@@ -77,6 +80,10 @@ enum TokenType {
     GTEQ,
     EQEQ,
     BANGEQ,
+    PLUSEQ,
+    MINUSEQ,
+    STAREQ,
+    SLASHEQ,
 
     BREAK,
     CONTINUE,
@@ -134,12 +141,6 @@ struct Keyword {
     token_type: TokenType,
 }
 
-impl Keyword {
-    pub fn len(&self) -> usize {
-        self.identifier.len()
-    }
-}
-
 macro_rules! define_keyword {
     ($id:expr, $ty:tt) => {
         Keyword {
@@ -150,8 +151,7 @@ macro_rules! define_keyword {
 }
 
 // The table of reserved words and their associated token types.
-#[allow(dead_code)]
-static KEYWORDS: &'static [Keyword] = &[
+static KEYWORDS: &[Keyword] = &[
     define_keyword!("break", BREAK),
     define_keyword!("continue", CONTINUE),
     define_keyword!("class", CLASS),
@@ -173,17 +173,17 @@ static KEYWORDS: &'static [Keyword] = &[
     define_keyword!("true", TRUE),
     define_keyword!("var", VAR),
     define_keyword!("while", WHILE),
-    define_keyword!("", EOF), // @todo ??
 ];
 
+#[derive(Clone, Copy)]
 struct Token {
     ty: TokenType,
 
-    // The beginning of the token, pointing directly into the source.
-    start: String, // @todo ??
+    // The beginning of the token, as an offset into the source.
+    start: usize,
 
-    // The length of the token in characters.
-    length: i32,
+    // The length of the token in bytes.
+    length: usize,
 
     // The 1-based line where the token appears.
     line: i32,
@@ -192,14 +192,306 @@ struct Token {
     value: Value,
 }
 
-struct Parser {
-    vm: WrenVM,
+impl Token {
+    // Returns an empty token on [line], for the token fields the parser has
+    // before it has lexed anything.
+    //
+    // It is an error token rather than an EOF one, because next_token() stops
+    // lexing once it sees EOF, and error() stays quiet about error tokens.
+    fn placeholder(line: i32) -> Token {
+        Token {
+            ty: TokenType::ERROR,
+            start: 0,
+            length: 0,
+            line,
+            value: Value::Undefined,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Lowest,
+    Assignment,   // =
+    Conditional,  // ?:
+    LogicalOr,    // ||
+    LogicalAnd,   // &&
+    Equality,     // == !=
+    Is,           // is
+    Comparison,   // < > <= >=
+    BitwiseOr,    // |
+    BitwiseXor,   // ^
+    BitwiseAnd,   // &
+    BitwiseShift, // << >>
+    Range,        // .. ...
+    Term,         // + -
+    Factor,       // * / %
+    Unary,        // unary - ! ~
+    Call,         // . () []
+    Primary,
+}
+
+impl Precedence {
+    // Returns the precedence level that binds one step tighter than this one.
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Lowest,
+            Precedence::Lowest => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Conditional,
+            Precedence::Conditional => Precedence::LogicalOr,
+            Precedence::LogicalOr => Precedence::LogicalAnd,
+            Precedence::LogicalAnd => Precedence::Equality,
+            Precedence::Equality => Precedence::Is,
+            Precedence::Is => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitwiseOr,
+            Precedence::BitwiseOr => Precedence::BitwiseXor,
+            Precedence::BitwiseXor => Precedence::BitwiseAnd,
+            Precedence::BitwiseAnd => Precedence::BitwiseShift,
+            Precedence::BitwiseShift => Precedence::Range,
+            Precedence::Range => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type GrammarFn = fn(&mut Parser, bool);
+
+type SignatureFn = fn(&mut Parser, &mut Signature);
+
+struct GrammarRule {
+    prefix: Option<GrammarFn>,
+    infix: Option<GrammarFn>,
+    method: Option<SignatureFn>,
+    precedence: Precedence,
+}
+
+struct Local {
+    // The name of the local variable.
+    name: String,
+
+    // The depth in the scope chain that this variable was declared at. Zero is
+    // the outermost scope--parameters for a method, or the first local block in
+    // top level code. One is the scope within that, etc.
+    depth: i32,
+
+    // If this local variable is being used as an upvalue.
+    is_upvalue: bool,
+
+    // False while the variable's initializer is being compiled. It is in scope
+    // then, but reading it is an error.
+    is_initialized: bool,
+}
+
+struct CompilerUpvalue {
+    // True if this upvalue is capturing a local variable from the enclosing
+    // function. False if it's capturing an upvalue.
+    is_local: bool,
+
+    // The index of the local or upvalue being captured in the enclosing function.
+    index: i32,
+}
+
+// Bookkeeping information for the current loop being compiled.
+struct Loop {
+    // Index of the instruction that the loop should jump back to.
+    start: usize,
+
+    // Index of the argument for the CODE_JUMP_IF instruction used to exit the
+    // loop. Stored so we can patch it once we know where the loop ends.
+    exit_jump: usize,
+
+    // Index of the first instruction of the body of the loop.
+    body: usize,
+
+    // Depth of the scope(s) that need to be exited if a break is hit inside the
+    // loop.
+    scope_depth: i32,
+}
+
+// Whether the statements of a block being compiled can still be reached.
+#[derive(Clone, Copy)]
+enum Reachability {
+    Reachable,
+
+    // A previous statement unconditionally left the block using the keyword.
+    Exited(&'static str),
+
+    // The unreachable code in the block has already been reported.
+    Warned,
+}
+
+struct Compiler {
+    // The currently in scope local variables.
+    locals: Vec<Local>,
+
+    // The upvalues that this function has captured from outer scopes. The count
+    // of them is stored in the function's [num_upvalues] once it's finished.
+    upvalues: Vec<CompilerUpvalue>,
+
+    // The current level of block scope nesting, where zero is no nesting. A -1
+    // here means top-level code is being compiled and there is no block scope
+    // in effect at all. Any variables declared will be module-level.
+    scope_depth: i32,
+
+    // The current number of slots (locals and temporaries) in use.
+    //
+    // We use this and maxSlots to track the maximum number of additional slots
+    // a function may need while executing. When the function is called, the
+    // fiber will check to ensure its stack has enough room to cover that worst
+    // case and grow the stack if needed.
+    //
+    // This value here doesn't include parameters to the function. Since those
+    // are already pushed onto the stack by the caller and tracked there, we
+    // don't need to double count them here.
+    num_slots: i32,
+
+    // The loops currently being compiled in this function, innermost last.
+    loops: Vec<Loop>,
+
+    // The function being compiled.
+    fn_obj: ObjFn,
+
+    // If this is a compiler for a class body, the information about the class
+    // being compiled.
+    enclosing_class: Option<ClassInfo>,
+
+    // Whether this is a compiler for an initializer.
+    is_initializer: bool,
+}
+
+// Bookkeeping information for compiling a class definition.
+struct ClassInfo {
+    // The name of the class.
+    name: String,
+
+    // Symbol table for the fields of the class.
+    fields: SymbolTable,
+
+    // Symbols for the methods defined by the class. Used to detect duplicate
+    // method definitions.
+    methods: Vec<usize>,
+    static_methods: Vec<usize>,
+
+    // True if the current method being compiled is static.
+    in_static: bool,
+}
+
+// Describes where a variable is declared.
+#[derive(Clone, Copy)]
+enum Scope {
+    // A local variable in the current function.
+    Local,
+
+    // A local variable declared in an enclosing function.
+    Upvalue,
+
+    // A top-level module variable.
+    Module,
+}
+
+// A reference to a variable and the scope where it is defined. This contains
+// enough information to emit correct code to load or store the variable.
+#[derive(Clone, Copy)]
+struct Variable {
+    // The stack slot, upvalue slot, or module symbol defining the variable.
+    index: i32,
+
+    // Where the variable is declared.
+    scope: Scope,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SignatureType {
+    // A name followed by a (possibly empty) parenthesized parameter list. Also
+    // used for binary operators.
+    Method,
+
+    // Just a name. Also used for unary operators.
+    Getter,
+
+    // A name followed by "=".
+    Setter,
+
+    // A square bracketed parameter list.
+    Subscript,
+
+    // A square bracketed parameter list followed by "=".
+    SubscriptSetter,
+
+    // A constructor initializer function. This has a distinct signature to
+    // prevent it from being invoked directly outside of the constructor on the
+    // metaclass.
+    Initializer,
+}
+
+#[derive(Clone)]
+struct Signature {
+    name: String,
+    ty: SignatureType,
+    arity: i32,
+}
+
+impl Signature {
+    // Appends characters to [name] for the list of parameters in [signature].
+    fn parameter_list(name: &mut String, num_params: i32, left_bracket: char, right_bracket: char) {
+        name.push(left_bracket);
+        for i in 0..num_params {
+            if i > 0 {
+                name.push(',');
+            }
+            name.push('_');
+        }
+        name.push(right_bracket);
+    }
+
+    // Gets the full signature string for the signature, the way it is
+    // represented in the method symbol table.
+    fn to_full_name(&self) -> String {
+        // Build the full name from the signature.
+        let mut name = self.name.clone();
+
+        match self.ty {
+            SignatureType::Method => {
+                Signature::parameter_list(&mut name, self.arity, '(', ')');
+            }
+            SignatureType::Getter => {
+                // The signature is just the name.
+            }
+            SignatureType::Setter => {
+                name.push('=');
+                Signature::parameter_list(&mut name, 1, '(', ')');
+            }
+            SignatureType::Subscript => {
+                Signature::parameter_list(&mut name, self.arity, '[', ']');
+            }
+            SignatureType::SubscriptSetter => {
+                Signature::parameter_list(&mut name, self.arity - 1, '[', ']');
+                name.push('=');
+                Signature::parameter_list(&mut name, 1, '(', ')');
+            }
+            SignatureType::Initializer => {
+                name = format!("init {}", self.name);
+                Signature::parameter_list(&mut name, self.arity, '(', ')');
+            }
+        }
+
+        name
+    }
+}
+
+struct Parser<'a> {
+    vm: &'a mut WrenVM,
 
     // The module being parsed.
-    module: ObjModule,
+    module: ObjRef,
 
     // The source code being parsed.
-    source: String,
+    source: Vec<u8>,
 
     // The beginning of the currently-being-lexed token in [source].
     token_start: usize,
@@ -208,7 +500,7 @@ struct Parser {
     current_char_i: usize,
 
     // The 1-based line number of [currentChar].
-    current_line: usize,
+    current_line: i32,
 
     // The upcoming token.
     next: Token,
@@ -235,60 +527,502 @@ struct Parser {
     parens: [usize; MAX_INTERPOLATION_NESTING],
     num_parens: usize,
 
+    // The offset in the current function's bytecode where the left-hand operand
+    // of the infix expression being compiled begins.
+    operand_start: usize,
+
     // Whether compile errors should be printed to stderr or discarded.
     print_errors: bool,
 
+    // How deeply the expression or statement currently being compiled is nested.
+    nesting: usize,
+
+    // Whether line comments that document a declaration should be kept in
+    // [comments]. Only tools that tokenize source without compiling it need
+    // them.
+    collect_comments: bool,
+
+    // The line comments seen since the last token, with the line each is on.
+    pending_comments: Vec<(i32, String)>,
+
+    // The line comments directly above a declaration, in source order.
+    comments: Vec<(i32, String)>,
+
     // If a syntax or compile error has occurred.
     has_error: bool,
+
+    // Whether the parser is recovering from a syntax error. Until it gets back
+    // to the start of a statement, further errors are likely caused by the
+    // first one, so they aren't reported.
+    panic_mode: bool,
+
+    // The number of "{" consumed so far that haven't been closed yet.
+    brace_depth: usize,
+
+    // The [brace_depth] inside the innermost block whose statements are being
+    // compiled, or zero at the top level. Recovering from an error only resumes
+    // at a statement in that block, not in one nested inside it.
+    block_depth: usize,
+
+    // The compilers for the functions currently being compiled. The innermost
+    // one is last.
+    compilers: Vec<Compiler>,
+}
+
+// The result of lexing a source file without compiling it.
+pub struct Tokens {
+    // The line and source text of each token, in order. A newline token's text
+    // is "\n".
+    pub tokens: Vec<(i32, String)>,
+
+    // The text of each `//` comment on the lines directly above a `class`,
+    // `construct`, `foreign`, `static` or `var`, with the line it is on. Empty
+    // unless comments were collected.
+    pub comments: Vec<(i32, String)>,
+}
+
+fn is_name(c: u8) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_uppercase() || c == b'_'
+}
+
+fn is_digit(c: u8) -> bool {
+    c.is_ascii_digit()
+}
+
+// Converts the text of a number literal to its value. Returns `None` if a
+// hexadecimal literal is too large to fit in 64 bits.
+fn number_value(text: &str, is_hex: bool) -> Option<f64> {
+    if is_hex {
+        i64::from_str_radix(&text[2..], 16)
+            .ok()
+            .map(|value| value as f64)
+    } else {
+        // Literals outside the range of a double round to infinity or zero, as
+        // IEEE-754 specifies. The only text that fails to parse is an exponent
+        // with no digits, which read_number() has already reported.
+        Some(text.parse::<f64>().unwrap_or(0.0))
+    }
+}
+
+// Returns true if [text] is entirely a decimal number literal, following the
+// same rules as read_number().
+fn is_decimal_literal(text: &[u8]) -> bool {
+    let mut i = 0;
+    let skip_digits = |i: &mut usize| {
+        let start = *i;
+        while *i < text.len() && is_digit(text[*i]) {
+            *i += 1;
+        }
+        *i > start
+    };
+
+    if !skip_digits(&mut i) {
+        return false;
+    }
+
+    if i < text.len() && text[i] == b'.' {
+        i += 1;
+        if !skip_digits(&mut i) {
+            return false;
+        }
+    }
+
+    if i < text.len() && (text[i] == b'e' || text[i] == b'E') {
+        i += 1;
+        if i < text.len() && (text[i] == b'+' || text[i] == b'-') {
+            i += 1;
+        }
+        if !skip_digits(&mut i) {
+            return false;
+        }
+    }
+
+    i == text.len()
 }
 
-fn is_name(c: char) -> bool {
-    return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_';
+// Parses [text] as a number written the way the lexer reads number literals,
+// with an optional leading `-`. Returns `None` if any of [text] is not part of
+// the number, or if a hexadecimal literal is too large.
+pub(crate) fn wren_parse_number(text: &[u8]) -> Option<f64> {
+    let (is_negative, digits) = match text.strip_prefix(b"-") {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+
+    let is_hex = digits.starts_with(b"0x");
+    let is_valid = if is_hex {
+        digits.len() > 2 && digits[2..].iter().all(u8::is_ascii_hexdigit)
+    } else {
+        is_decimal_literal(digits)
+    };
+    if !is_valid {
+        return None;
+    }
+
+    // Only ASCII digits remain, so the text is always valid UTF-8.
+    let value = number_value(std::str::from_utf8(digits).ok()?, is_hex)?;
+    Some(if is_negative { -value } else { value })
 }
 
-fn is_digit(c: char) -> bool {
-    return c >= '0' && c <= '9';
+// Formats the "[kind] at '[text]'" label an error or warning starts with.
+fn error_label(kind: &str, text: &[u8]) -> String {
+    // Make sure we don't exceed the buffer with a very long name.
+    if text.len() <= MAX_VARIABLE_NAME as usize {
+        format!("{} at '{}'", kind, String::from_utf8_lossy(text))
+    } else {
+        let text = &text[..MAX_VARIABLE_NAME as usize];
+        format!("{} at '{}...'", kind, String::from_utf8_lossy(text))
+    }
 }
 
-impl Parser {
-    // @todo
-    //   configurable with args
-    fn print_error(&self, line: usize, label: &str, format: &str) {
-        unimplemented!()
+impl<'a> Parser<'a> {
+    fn new(vm: &'a mut WrenVM, module: ObjRef, source: &[u8], print_errors: bool) -> Parser<'a> {
+        // Skip the UTF-8 BOM if there is one.
+        let source = source.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(source);
+
+        // Zero-init the current token. This will get copied to previous when
+        // next_token() is first called.
+        let token = Token::placeholder(0);
+
+        Parser {
+            vm,
+            module,
+            source: source.to_vec(),
+            token_start: 0,
+            current_char_i: 0,
+            current_line: 1,
+            next: token,
+            current: token,
+            previous: token,
+            parens: [0; MAX_INTERPOLATION_NESTING],
+            num_parens: 0,
+            operand_start: 0,
+            print_errors,
+            nesting: 0,
+            collect_comments: false,
+            pending_comments: Vec::new(),
+            comments: Vec::new(),
+            has_error: false,
+            panic_mode: false,
+            brace_depth: 0,
+            block_depth: 0,
+            compilers: Vec::new(),
+        }
+    }
+
+    // Reports an error on [line]. If [span] is given, it is the start and length
+    // of the offending source code, which gets quoted under the message.
+    fn print_error(&mut self, line: i32, span: Option<(usize, usize)>, label: &str, message: &str) {
+        self.has_error = true;
+        self.report(ErrorType::Compile, line, span, label, message);
+    }
+
+    // Reports a diagnostic that does not prevent the code from running.
+    fn print_warning(
+        &mut self,
+        line: i32,
+        span: Option<(usize, usize)>,
+        label: &str,
+        message: &str,
+    ) {
+        self.report(ErrorType::Warning, line, span, label, message);
+    }
+
+    fn report(
+        &mut self,
+        ty: ErrorType,
+        line: i32,
+        span: Option<(usize, usize)>,
+        label: &str,
+        message: &str,
+    ) {
+        if !self.print_errors {
+            return;
+        }
+
+        // Only report errors if there is a WrenErrorFn to handle them.
+        if self.vm.config.error_fn.is_none() {
+            return;
+        }
+
+        // Format the label and message. Unlike C Wren's fixed-size buffer, this
+        // grows to fit, so a long label with a long message is still reported.
+        let message = format!("{}: {}", label, message);
+
+        let module_name = match self.vm.heap.as_module(self.module).name {
+            Some(name) => String::from_utf8_lossy(&self.vm.heap.as_string(name).value).into_owned(),
+            None => "<unknown>".to_string(),
+        };
+
+        self.vm.report_error(ty, &module_name, line, &message);
+
+        if let Some((start, length)) = span {
+            let snippet = self.source_snippet(start, length);
+            self.vm
+                .report_error(ErrorType::Snippet, &module_name, line, &snippet);
+        }
+    }
+
+    // Renders the source line containing the [length] bytes at [start], with a
+    // row of carets underneath them:
+    //
+    //     var a = 1 +* 2
+    //                ^
+    fn source_snippet(&self, start: usize, length: usize) -> String {
+        // Point the end of file just past the last line instead of at the empty
+        // one after its trailing newline.
+        let mut start = start.min(self.source.len());
+        if start == self.source.len() {
+            while start > 0 && matches!(self.source[start - 1], b'\n' | b'\r') {
+                start -= 1;
+            }
+        }
+
+        let line_start = self.source[..start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let line_end = self.source[start..]
+            .iter()
+            .position(|&c| c == b'\n')
+            .map_or(self.source.len(), |newline| start + newline);
+        let end = (start + length).min(line_end);
+
+        // Tabs are expanded to Config.tab_width spaces, both in the quoted line
+        // and when counting the columns before and under the carets, so that
+        // the two lines always agree.
+        let tab = " ".repeat(self.vm.config.tab_width);
+        let columns = |bytes: &[u8]| -> usize {
+            String::from_utf8_lossy(bytes)
+                .chars()
+                .map(|c| if c == '\t' { tab.len() } else { 1 })
+                .sum()
+        };
+
+        let text = String::from_utf8_lossy(&self.source[line_start..line_end]);
+        let padding = " ".repeat(columns(&self.source[line_start..start]));
+        let carets = "^".repeat(columns(&self.source[start..end]).max(1));
+
+        format!(
+            "{}\n{}{}",
+            text.trim_end_matches('\r').replace('\t', &tab),
+            padding,
+            carets
+        )
     }
 
-    // @todo
-    //   configurable with args
     // Outputs a lexical error.
-    fn lex_error(&self, format: &str) {
-        self.print_error(self.current_line, "Error", format);
+    fn lex_error(&mut self, message: &str) {
+        let span = (self.token_start, self.current_char_i - self.token_start);
+        self.print_error(self.current_line, Some(span), "Error", message);
+    }
+
+    // Outputs a compile or syntax error. This also marks the compilation as
+    // having an error, which ensures that the resulting code will be discarded
+    // and never run. This means that after calling error(), it's fine to
+    // generate whatever invalid bytecode you want since it won't be used.
+    //
+    // You'll note that most places that call error() continue to parse and
+    // compile after that. That's so that we can try to find as many compilation
+    // errors in one pass as possible instead of just bailing at the first one.
+    fn error(&mut self, message: &str) {
+        let token = self.previous;
+
+        // Only report the first error of a statement. The rest are usually just
+        // the parser being confused by it.
+        if self.panic_mode {
+            self.has_error = true;
+            return;
+        }
+        self.panic_mode = true;
+
+        // If the parse error was caused by an error token, the lexer has already
+        // reported it.
+        if token.ty == TokenType::ERROR {
+            return;
+        }
+
+        let span = Some((token.start, token.length));
+        if token.ty == TokenType::LINE {
+            self.print_error(token.line, span, "Error at newline", message);
+        } else if token.ty == TokenType::EOF {
+            self.print_error(token.line, span, "Error at end of file", message);
+        } else {
+            let label = self.token_label("Error", &token);
+            self.print_error(token.line, span, &label, message);
+        }
+    }
+
+    // Outputs a warning at the current token. Unlike error(), the compiled code
+    // is still used.
+    fn warning(&mut self, message: &str) {
+        let token = self.current;
+        let label = self.token_label("Warning", &token);
+        let span = Some((token.start, token.length));
+        self.print_warning(token.line, span, &label, message);
+    }
+
+    fn token_label(&self, kind: &str, token: &Token) -> String {
+        error_label(kind, self.token_text(token))
+    }
+
+    // Tracks reachability across the statements of a block. Called before each
+    // statement in the block is compiled, with the state returned by the
+    // previous call.
+    //
+    // An unconditional `return`, `break`, or `continue` always leaves the block,
+    // so the statement after it in the same block can never run and gets a
+    // warning. One nested in an `if` or a loop body is part of that statement
+    // instead, so code following a conditional return is not affected. Only the
+    // first unreachable statement in a block is reported.
+    fn check_reachable(&mut self, state: Reachability) -> Reachability {
+        match state {
+            Reachability::Reachable => match self.peek() {
+                TokenType::RETURN => Reachability::Exited("return"),
+                TokenType::BREAK => Reachability::Exited("break"),
+                TokenType::CONTINUE => Reachability::Exited("continue"),
+                _ => Reachability::Reachable,
+            },
+            Reachability::Exited(keyword) => {
+                self.warning(&format!("Unreachable code after '{}'.", keyword));
+                Reachability::Warned
+            }
+            Reachability::Warned => Reachability::Warned,
+        }
+    }
+
+    // Leaves panic mode after a syntax error by skipping tokens until what looks
+    // like the start of the next statement: just after a newline, or at a "}" or
+    // a keyword that begins a statement.
+    //
+    // Only those in the block being compiled count. Anything inside a block
+    // argument or map literal nested in the bad statement is skipped with it,
+    // so that its "}" isn't mistaken for the end of the block and reported too.
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.peek() != TokenType::EOF {
+            // The bad statement already consumed the "}" closing the block.
+            if self.brace_depth < self.block_depth {
+                return;
+            }
+
+            if self.brace_depth > self.block_depth {
+                self.next_token();
+                continue;
+            }
+
+            if self.previous.ty == TokenType::LINE {
+                return;
+            }
+
+            match self.peek() {
+                TokenType::RIGHT_BRACE
+                | TokenType::BREAK
+                | TokenType::CLASS
+                | TokenType::CONTINUE
+                | TokenType::FOR
+                | TokenType::FOREIGN
+                | TokenType::IF
+                | TokenType::IMPORT
+                | TokenType::RETURN
+                | TokenType::VAR
+                | TokenType::WHILE => return,
+                _ => {}
+            }
+
+            self.next_token();
+        }
+    }
+
+    // Goes one level deeper into nested code. Returns `false` if that is too
+    // deep, after reporting it and skipping the rest of the source.
+    fn enter_nesting(&mut self) -> bool {
+        if self.nesting < self.vm.config.max_nesting_depth {
+            self.nesting += 1;
+            return true;
+        }
+
+        self.error("Expression nesting too deep.");
+
+        // Every level unwinding from here would report its own missing
+        // delimiter, so stop reporting and skip to the end.
+        self.print_errors = false;
+        while self.peek() != TokenType::EOF {
+            self.next_token();
+        }
+        false
+    }
+
+    // Compiles a statement nested in the one being compiled, such as the body
+    // of an "if", using [compile].
+    fn nested(&mut self, compile: fn(&mut Self)) {
+        if self.enter_nesting() {
+            compile(self);
+            self.nesting -= 1;
+        }
+    }
+
+    fn token_text(&self, token: &Token) -> &[u8] {
+        &self.source[token.start..token.start + token.length]
     }
 
-    fn read_token_str(&self, length: usize) -> &str {
+    fn read_token_str(&self, length: usize) -> &[u8] {
         &self.source[self.token_start..self.token_start + length]
     }
 
-    fn peek_char(&self) -> char {
-        self.source.chars().nth(self.current_char_i).unwrap_or('\0')
+    // Returns the current character the parser is sitting on.
+    fn peek_char(&self) -> u8 {
+        self.source
+            .get(self.current_char_i)
+            .copied()
+            .unwrap_or(b'\0')
+    }
+
+    // Returns `true` if the whole source has been lexed. Unlike checking for a
+    // "\0" character, this doesn't stop early at a NUL byte in the source.
+    fn is_at_end(&self) -> bool {
+        self.current_char_i >= self.source.len()
     }
 
-    fn peek_next_char(&self) -> char {
+    // Returns the character after the current character.
+    fn peek_next_char(&self) -> u8 {
+        // If we're at the end of the source, don't read past it.
+        if self.peek_char() == b'\0' {
+            return b'\0';
+        }
         self.source
-            .chars()
-            .nth(self.current_char_i + 1)
-            .unwrap_or('\0')
+            .get(self.current_char_i + 1)
+            .copied()
+            .unwrap_or(b'\0')
     }
 
-    fn next_char(&mut self) -> char {
+    // Advances the parser forward one character.
+    fn next_char(&mut self) -> u8 {
         let c = self.peek_char();
         self.current_char_i += 1;
-        if c == '\n' {
+        if c == b'\n' {
             self.current_line += 1;
         }
         c
     }
 
-    fn match_char(&mut self, c: char) -> bool {
+    // Returns `true` if the line after the newline just consumed starts with a
+    // "." method call, ignoring indentation. A ".." or "..." range operator
+    // doesn't count.
+    fn next_line_continues(&self) -> bool {
+        let rest = &self.source[self.current_char_i..];
+        match rest
+            .iter()
+            .position(|&c| !matches!(c, b' ' | b'\t' | b'\r'))
+        {
+            Some(dot) => rest[dot] == b'.' && rest.get(dot + 1) != Some(&b'.'),
+            None => false,
+        }
+    }
+
+    // If the current character is [c], consumes it and returns `true`.
+    fn match_char(&mut self, c: u8) -> bool {
         if self.peek_char() != c {
             return false;
         }
@@ -298,19 +1032,77 @@ impl Parser {
 
     // Sets the parser's current token to the given [type] and current character
     // range.
-    fn make_token(&self, ty: TokenType) {
-        unimplemented!()
+    fn make_token(&mut self, ty: TokenType) {
+        self.next.ty = ty;
+        self.next.start = self.token_start;
+        self.next.length = self.current_char_i - self.token_start;
+        self.next.line = self.current_line;
+
+        // Make line tokens appear on the line containing the "\n".
+        if ty == TokenType::LINE {
+            self.next.line -= 1;
+        } else if self.collect_comments {
+            self.attach_comments(ty);
+        }
+    }
+
+    // Records the line comment that was just skipped as a possible doc comment,
+    // unless it follows code on the same line.
+    fn collect_comment(&mut self) {
+        let line_start = self.source[..self.token_start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |i| i + 1);
+        let is_own_line = self.source[line_start..self.token_start]
+            .iter()
+            .all(|&c| c == b' ' || c == b'\t' || c == b'\r');
+        if !is_own_line {
+            return;
+        }
+
+        // A blank line ends the block of comments above it.
+        if let Some(&(line, _)) = self.pending_comments.last() {
+            if line != self.current_line - 1 {
+                self.pending_comments.clear();
+            }
+        }
+
+        // Skip the leading "//".
+        let text = &self.source[self.token_start + 2..self.current_char_i];
+        let text = String::from_utf8_lossy(text).trim().to_string();
+        self.pending_comments.push((self.current_line, text));
+    }
+
+    // Keeps the pending comments if a token of type [ty] begins a declaration on
+    // the line below them, and discards them otherwise.
+    fn attach_comments(&mut self, ty: TokenType) {
+        let pending = std::mem::take(&mut self.pending_comments);
+        let is_declaration = matches!(
+            ty,
+            TokenType::CLASS
+                | TokenType::CONSTRUCT
+                | TokenType::FOREIGN
+                | TokenType::STATIC
+                | TokenType::VAR
+        );
+        let is_adjacent = pending
+            .last()
+            .is_some_and(|&(line, _)| line == self.current_line - 1);
+        if is_declaration && is_adjacent {
+            self.comments.extend(pending);
+        }
     }
 
     // If the current character is [c], then consumes it and makes a token of type
     // [two]. Otherwise makes a token of type [one].
-    fn two_char_token(&self, c: char, two: Token, one: Token) {
-        unimplemented!()
+    fn two_char_token(&mut self, c: u8, two: TokenType, one: TokenType) {
+        let ty = if self.match_char(c) { two } else { one };
+        self.make_token(ty);
     }
 
     // Skips the rest of the current line.
     fn skip_line_comment(&mut self) {
-        while self.peek_char() != '\n' && self.peek_char() != '\0' {
+        while self.peek_char() != b'\n' && self.peek_char() != b'\0' {
             self.next_char();
         }
     }
@@ -319,19 +1111,21 @@ impl Parser {
     fn skip_block_comment(&mut self) {
         let mut nesting: usize = 1;
         while nesting > 0 {
-            if self.peek_char() == '\0' {
+            // A NUL byte is just another comment character. Only the end of the
+            // source leaves the comment unterminated.
+            if self.is_at_end() {
                 self.lex_error("Unterminated block comment.");
                 return;
             }
 
-            if self.peek_char() == '/' && self.peek_next_char() == '*' {
+            if self.peek_char() == b'/' && self.peek_next_char() == b'*' {
                 self.next_char();
                 self.next_char();
                 nesting += 1;
                 continue;
             }
 
-            if self.peek_char() == '*' && self.peek_next_char() == '/' {
+            if self.peek_char() == b'*' && self.peek_next_char() == b'/' {
                 self.next_char();
                 self.next_char();
                 nesting -= 1;
@@ -346,14 +1140,14 @@ impl Parser {
     // returns its numeric value. If the character isn't a hex digit, returns -1.
     fn read_hex_digit(&mut self) -> i32 {
         let c = self.next_char();
-        if c >= '0' && c <= '9' {
-            return (c as i32) - ('0' as i32);
+        if c.is_ascii_digit() {
+            return (c - b'0') as i32;
         }
-        if c >= 'a' && c <= 'f' {
-            return (c as i32) - ('a' as i32) + 10;
+        if (b'a'..=b'f').contains(&c) {
+            return (c - b'a') as i32 + 10;
         }
-        if c >= 'A' && c <= 'F' {
-            return (c as i32) - ('A' as i32) + 10;
+        if (b'A'..=b'F').contains(&c) {
+            return (c - b'A') as i32 + 10;
         }
 
         // Don't consume it if it isn't expected. Keeps us from reading past the end
@@ -364,8 +1158,22 @@ impl Parser {
     }
 
     // Parses the numeric value of the current token.
-    fn make_number(&self, is_hex: bool) {
-        unimplemented!()
+    fn make_number(&mut self, is_hex: bool) {
+        let length = self.current_char_i - self.token_start;
+        let text = String::from_utf8_lossy(self.read_token_str(length)).into_owned();
+
+        match number_value(&text, is_hex) {
+            Some(value) => self.next.value = Value::Num(value),
+            None => {
+                self.lex_error(&format!(
+                    "Number literal was too large ({}).",
+                    std::mem::size_of::<i64>()
+                ));
+                self.next.value = Value::Num(0.0);
+            }
+        }
+
+        self.make_token(TokenType::NUMBER);
     }
 
     // Finishes lexing a hexadecimal number literal.
@@ -387,7 +1195,7 @@ impl Parser {
 
         // See if it has a floating point. Make sure there is a digit after the "."
         // so we don't get confused by method calls on number literals.
-        if self.peek_char() == '.' && is_digit(self.peek_next_char()) {
+        if self.peek_char() == b'.' && is_digit(self.peek_next_char()) {
             self.next_char();
             while is_digit(self.peek_char()) {
                 self.next_char();
@@ -395,10 +1203,10 @@ impl Parser {
         }
 
         // See if the number is in scientific notation.
-        if self.match_char('e') || self.match_char('E') {
+        if self.match_char(b'e') || self.match_char(b'E') {
             // Allow a single positive/negative exponent symbol.
-            if !self.match_char('+') {
-                self.match_char('-');
+            if !self.match_char(b'+') {
+                self.match_char(b'-');
             }
             if !is_digit(self.peek_char()) {
                 self.lex_error("Unterminated scientific notation.");
@@ -411,44 +1219,63 @@ impl Parser {
     }
 
     // Finishes lexing an identifier. Handles reserved words.
-    fn read_name(&mut self, ty: &TokenType, first_char: char) {
-        let mut buffer = vec![];
-        buffer.push(first_char);
+    fn read_name(&mut self, ty: TokenType, first_char: u8) {
+        let mut buffer = vec![first_char];
 
         while is_name(self.peek_char()) || is_digit(self.peek_char()) {
-            buffer.push(self.next_char());
-        }
-        // Update the type if it's a keyword.
-        let mut token_ty = ty.clone();
-        let length = self.current_char_i - self.token_start;
-        for kw in KEYWORDS {
-            if length == kw.len() && self.read_token_str(length) == kw.identifier {
-                token_ty = &kw.token_type;
-            }
+            let c = self.next_char();
+            buffer.push(c);
         }
+        // Update the type if it's a keyword. Only a whole name matches one, so
+        // a name that is a prefix of a keyword stays a name.
+        let token_ty = KEYWORDS
+            .iter()
+            .find(|keyword| keyword.identifier.as_bytes() == buffer.as_slice())
+            .map_or(ty, |keyword| keyword.token_type);
 
-        unimplemented!();
-        //   parser->next.value = wrenNewStringLength(parser->vm,
-        //                                             (char*)string.data, string.count);
-        //
-        //   wrenByteBufferClear(parser->vm, &string);
-        //   makeToken(parser, type);
-        // }
+        self.next.value = wren_new_string(self.vm, &buffer);
+        self.make_token(token_ty);
     }
 
     // Reads [digits] hex digits in a string literal and returns their number value.
-    fn read_hex_escape(&self, digits: i32, description: &str) {
-        unimplemented!();
+    fn read_hex_escape(&mut self, digits: i32, description: &str) -> i32 {
+        let mut value = 0;
+        for _ in 0..digits {
+            if self.peek_char() == b'"' || self.peek_char() == b'\0' {
+                self.lex_error(&format!("Incomplete {} escape sequence.", description));
+
+                // Don't consume it if it isn't expected. Keeps us from reading past the
+                // end of an unterminated string.
+                self.current_char_i -= 1;
+                break;
+            }
+
+            let digit = self.read_hex_digit();
+            if digit == -1 {
+                self.lex_error(&format!("Invalid {} escape sequence.", description));
+                break;
+            }
+
+            value = (value * 16) | digit;
+        }
+
+        value
     }
 
     // Reads a hex digit Unicode escape sequence in a string literal.
-    fn read_unicode_escape(&self, byte_buffer: &[i32], length: usize) {
-        unimplemented!();
+    fn read_unicode_escape(&mut self, string: &mut Vec<u8>, length: i32) {
+        let value = self.read_hex_escape(length, "Unicode");
+
+        // Grow the buffer enough for the encoded result.
+        let num_bytes = utf8_encode_num_bytes(value);
+        if num_bytes != 0 {
+            utf8_encode(value, string);
+        }
     }
 
     fn read_raw_string(&mut self) {
-        let mut string: Vec<char> = vec![];
-        let mut ty = TokenType::STRING;
+        let mut string: Vec<u8> = vec![];
+        let ty = TokenType::STRING;
 
         //consume the second and third "
         self.next_char();
@@ -465,27 +1292,28 @@ impl Parser {
             let c1 = self.peek_char();
             let c2 = self.peek_next_char();
 
-            if c == '"' && c1 == '"' && c2 == '"' {
-                break;
+            if c == b'\r' {
+                continue;
             }
 
-            match c {
-                '\r' => {
-                    continue;
-                }
-                '\n' => {
-                    last_new_line = string.len() as i32;
-                    skip_end = last_new_line;
-                    if first_new_line == -1 {
-                        first_new_line = string.len() as i32
-                    }
+            if c == b'\n' {
+                last_new_line = string.len() as i32;
+                skip_end = last_new_line;
+                if first_new_line == -1 {
+                    first_new_line = string.len() as i32
                 }
-                _ => {}
             }
 
-            let is_whitespace = c == ' ' || c == '\t';
-            if c == '\n' || is_whitespace {
-                skip_end = 1;
+            if c == b'"' && c1 == b'"' && c2 == b'"' {
+                // Consume the second and third ".
+                self.next_char();
+                self.next_char();
+                break;
+            }
+
+            let is_whitespace = c == b' ' || c == b'\t';
+            if c != b'\n' && !is_whitespace {
+                skip_end = -1;
             }
 
             // If we haven't seen a newline or other character yet,
@@ -498,12 +1326,11 @@ impl Parser {
 
             // We've counted leading whitespace till we hit something else,
             // but it's not a newline, so we reset skipStart since we need these characters
-            // if (firstNewline == -1 && !isWhitespace && c != '\n') skipStart = -1;
-            if first_new_line == -1 && !is_whitespace && c != '\n' {
+            if first_new_line == -1 && !is_whitespace && c != b'\n' {
                 skip_start = -1;
             }
 
-            if c == '\0' || c1 == '\0' || c2 == '\0' {
+            if c == b'\0' || c1 == b'\0' || c2 == b'\0' {
                 self.lex_error("Unterminated raw string.");
                 // Don't consume it if it isn't expected. Keeps us from reading past the
                 // end of an unterminated string.
@@ -514,10 +1341,6 @@ impl Parser {
             string.push(c);
         }
 
-        // consume the second and third "
-        self.next_char();
-        self.next_char();
-
         let mut offset: i32 = 0;
         let mut count: i32 = string.len() as i32;
 
@@ -533,9 +1356,3078 @@ impl Parser {
             count -= offset;
         }
 
-        // @todo!()
-        // self.next.value = wren_new_string_length(string, offset, count);
+        // An unterminated string may stop before the newline the offset skips.
+        // The text is empty then.
+        let start = (offset as usize).min(string.len());
+        let mut text = &string[start..start + count as usize];
+
+        // When the text starts on the line after the opening quotes, it is
+        // usually indented to line up with the surrounding code. Remove the
+        // indentation all of its lines share, keeping any deeper indentation
+        // and every tab or space after it.
+        let dedented;
+        if offset > 0 {
+            dedented = strip_common_indentation(text);
+            text = &dedented;
+        }
+        self.next.value = wren_new_string(self.vm, text);
+
+        self.make_token(ty);
+    }
+
+    // Finishes lexing a string literal.
+    fn read_string(&mut self) {
+        let mut string: Vec<u8> = vec![];
+        let mut ty = TokenType::STRING;
+
+        loop {
+            let c = self.next_char();
+            if c == b'"' {
+                break;
+            }
+            if c == b'\r' {
+                continue;
+            }
+
+            if c == b'\0' {
+                self.lex_error("Unterminated string.");
+
+                // Don't consume it if it isn't expected. Keeps us from reading past the
+                // end of an unterminated string.
+                self.current_char_i -= 1;
+                break;
+            }
+
+            if c == b'%' {
+                if self.num_parens < MAX_INTERPOLATION_NESTING {
+                    // TODO: Allow format string.
+                    if self.next_char() != b'(' {
+                        self.lex_error("Expect '(' after '%'.");
+                    }
+
+                    self.parens[self.num_parens] = 1;
+                    self.num_parens += 1;
+                    ty = TokenType::INTERPOLATION;
+                    break;
+                }
+
+                self.lex_error(&format!(
+                    "Interpolation may only nest {} levels deep.",
+                    MAX_INTERPOLATION_NESTING
+                ));
+            }
+
+            if c == b'\\' {
+                match self.next_char() {
+                    b'"' => string.push(b'"'),
+                    b'\\' => string.push(b'\\'),
+                    b'%' => string.push(b'%'),
+                    b'0' => string.push(b'\0'),
+                    b'a' => string.push(0x07),
+                    b'b' => string.push(0x08),
+                    b'e' => string.push(0x1b),
+                    b'f' => string.push(0x0c),
+                    b'n' => string.push(b'\n'),
+                    b'r' => string.push(b'\r'),
+                    b't' => string.push(b'\t'),
+                    b'u' => self.read_unicode_escape(&mut string, 4),
+                    b'U' => self.read_unicode_escape(&mut string, 8),
+                    b'v' => string.push(0x0b),
+                    b'x' => {
+                        let byte = self.read_hex_escape(2, "byte");
+                        string.push(byte as u8);
+                    }
+
+                    // A backslash at the very end of the source leaves the string
+                    // unterminated. Step back so the loop reports that instead.
+                    b'\0' if self.current_char_i > self.source.len() => {
+                        self.current_char_i -= 1;
+                    }
+
+                    c => {
+                        self.lex_error(&format!("Invalid escape character '{}'.", c as char));
+                    }
+                }
+            } else {
+                string.push(c);
+            }
+        }
 
+        self.next.value = wren_new_string(self.vm, &string);
         self.make_token(ty);
     }
+
+    // Lex the next token and store it in [parser.next].
+    fn next_token(&mut self) {
+        self.previous = self.current;
+        self.current = self.next;
+
+        match self.previous.ty {
+            TokenType::LEFT_BRACE => self.brace_depth += 1,
+            TokenType::RIGHT_BRACE => self.brace_depth = self.brace_depth.saturating_sub(1),
+            _ => {}
+        }
+
+        // If we are out of tokens, don't try to tokenize any more. We *do* still
+        // copy the TOKEN_EOF to previous so that code that expects it to be consumed
+        // will still work.
+        if self.next.ty == TokenType::EOF {
+            return;
+        }
+        if self.current.ty == TokenType::EOF {
+            return;
+        }
+
+        while !self.is_at_end() {
+            self.token_start = self.current_char_i;
+
+            let c = self.next_char();
+            match c {
+                b'(' => {
+                    // If we are inside an interpolated expression, count the unmatched "(".
+                    if self.num_parens > 0 {
+                        self.parens[self.num_parens - 1] += 1;
+                    }
+                    self.make_token(TokenType::LEFT_PAREN);
+                    return;
+                }
+
+                b')' => {
+                    // If we are inside an interpolated expression, count the ")".
+                    if self.num_parens > 0 {
+                        self.parens[self.num_parens - 1] -= 1;
+                        if self.parens[self.num_parens - 1] == 0 {
+                            // This is the final ")", so the interpolation expression has ended.
+                            // This ")" now begins the next section of the template string.
+                            self.num_parens -= 1;
+                            self.read_string();
+                            return;
+                        }
+                    }
+
+                    self.make_token(TokenType::RIGHT_PAREN);
+                    return;
+                }
+
+                b'[' => return self.make_token(TokenType::LEFT_BRACKET),
+                b']' => return self.make_token(TokenType::RIGHT_BRACKET),
+                b'{' => return self.make_token(TokenType::LEFT_BRACE),
+                b'}' => return self.make_token(TokenType::RIGHT_BRACE),
+                b':' => return self.make_token(TokenType::COLON),
+                b',' => return self.make_token(TokenType::COMMA),
+                b'*' => return self.two_char_token(b'=', TokenType::STAREQ, TokenType::STAR),
+                b'%' => return self.make_token(TokenType::PERCENT),
+                b'#' => {
+                    // Ignore shebang on the first line.
+                    if self.current_line == 1
+                        && self.peek_char() == b'!'
+                        && self.peek_next_char() == b'/'
+                    {
+                        self.skip_line_comment();
+                        continue;
+                    }
+                    // Otherwise we treat it as a token
+                    return self.make_token(TokenType::HASH);
+                }
+                b'^' => return self.make_token(TokenType::CARET),
+                b'+' => return self.two_char_token(b'=', TokenType::PLUSEQ, TokenType::PLUS),
+                b'-' => return self.two_char_token(b'=', TokenType::MINUSEQ, TokenType::MINUS),
+                b'~' => return self.make_token(TokenType::TILDE),
+                b'?' => {
+                    return self.two_char_token(b'.', TokenType::QUESTIONDOT, TokenType::QUESTION)
+                }
+
+                b'|' => return self.two_char_token(b'|', TokenType::PIPEPIPE, TokenType::PIPE),
+                b'&' => return self.two_char_token(b'&', TokenType::AMPAMP, TokenType::AMP),
+                b'=' => return self.two_char_token(b'=', TokenType::EQEQ, TokenType::EQ),
+                b'!' => return self.two_char_token(b'=', TokenType::BANGEQ, TokenType::BANG),
+
+                b'.' => {
+                    if self.match_char(b'.') {
+                        return self.two_char_token(b'.', TokenType::DOTDOTDOT, TokenType::DOTDOT);
+                    }
+
+                    return self.make_token(TokenType::DOT);
+                }
+
+                b'/' => {
+                    if self.match_char(b'/') {
+                        self.skip_line_comment();
+                        if self.collect_comments {
+                            self.collect_comment();
+                        }
+                        continue;
+                    }
+
+                    if self.match_char(b'*') {
+                        self.skip_block_comment();
+                        continue;
+                    }
+
+                    return self.two_char_token(b'=', TokenType::SLASHEQ, TokenType::SLASH);
+                }
+
+                b'<' => {
+                    if self.match_char(b'<') {
+                        return self.make_token(TokenType::LTLT);
+                    }
+                    return self.two_char_token(b'=', TokenType::LTEQ, TokenType::LT);
+                }
+
+                b'>' => {
+                    if self.match_char(b'>') {
+                        return self.make_token(TokenType::GTGT);
+                    }
+                    return self.two_char_token(b'=', TokenType::GTEQ, TokenType::GT);
+                }
+
+                b'\n' => {
+                    // A line that starts with "." continues the expression on the
+                    // previous one, so that long method chains can be split:
+                    //
+                    //     list
+                    //       .map {|n| n * 2 }
+                    //       .where {|n| n > 4 }
+                    if self.next_line_continues() {
+                        continue;
+                    }
+                    return self.make_token(TokenType::LINE);
+                }
+
+                b' ' | b'\r' | b'\t' => {
+                    // Skip forward until we run out of whitespace.
+                    while self.peek_char() == b' '
+                        || self.peek_char() == b'\r'
+                        || self.peek_char() == b'\t'
+                    {
+                        self.next_char();
+                    }
+                }
+
+                b'"' => {
+                    if self.peek_char() == b'"' && self.peek_next_char() == b'"' {
+                        return self.read_raw_string();
+                    }
+                    return self.read_string();
+                }
+
+                b'_' => {
+                    let ty = if self.peek_char() == b'_' {
+                        TokenType::STATIC_FIELD
+                    } else {
+                        TokenType::FIELD
+                    };
+                    return self.read_name(ty, c);
+                }
+
+                b'0' => {
+                    if self.peek_char() == b'x' {
+                        return self.read_hex_number();
+                    }
+
+                    return self.read_number();
+                }
+
+                _ => {
+                    if is_name(c) {
+                        return self.read_name(TokenType::NAME, c);
+                    }
+                    if is_digit(c) {
+                        return self.read_number();
+                    }
+
+                    if (32..=126).contains(&c) {
+                        self.lex_error(&format!("Invalid character '{}'.", c as char));
+                    } else {
+                        // Don't show non-ASCII values since we didn't UTF-8 decode the
+                        // bytes. Since there are no non-ASCII byte values that are
+                        // meaningful code units in Wren, the lexer works on raw bytes,
+                        // even though the source code and console output are UTF-8.
+                        self.lex_error(&format!("Invalid byte 0x{:x}.", c));
+                    }
+                    self.next.ty = TokenType::ERROR;
+                    self.next.length = 0;
+                    return;
+                }
+            }
+        }
+
+        // If we get here, we're out of source, so just make EOF tokens.
+        self.token_start = self.current_char_i;
+
+        // Running out inside an interpolated expression means the string it is
+        // part of never got closed. Whatever the parser then fails to find at the
+        // end of the file follows from that, so don't report it too.
+        if self.num_parens > 0 {
+            self.lex_error("Unterminated string.");
+            self.num_parens = 0;
+            self.panic_mode = true;
+        }
+
+        self.make_token(TokenType::EOF);
+    }
+
+    // Parsing ---------------------------------------------------------------------
+
+    fn compiler(&mut self) -> &mut Compiler {
+        self.compilers.last_mut().expect("Should have a compiler.")
+    }
+
+    // Returns the type of the current token.
+    fn peek(&self) -> TokenType {
+        self.current.ty
+    }
+
+    // Consumes the current token if its type is [expected]. Returns true if a
+    // token was consumed.
+    fn match_token(&mut self, expected: TokenType) -> bool {
+        if self.peek() != expected {
+            return false;
+        }
+
+        self.next_token();
+        true
+    }
+
+    // Consumes the current token. Emits an error if its type is not [expected].
+    fn consume(&mut self, expected: TokenType, error_message: &str) {
+        // After an error, the tokens are probably not what the parser expects
+        // anyway. Leave them for [synchronize] to skip, so that a missing
+        // delimiter doesn't consume the start of the next statement.
+        if self.panic_mode && self.peek() != expected {
+            return;
+        }
+
+        self.next_token();
+        if self.previous.ty != expected {
+            self.error(error_message);
+
+            // If the next token is the one we want, assume the current one is just a
+            // spurious error and discard it to minimize the number of cascaded errors.
+            if self.current.ty == expected {
+                self.next_token();
+            }
+        }
+    }
+
+    // Matches one or more newlines. Returns true if at least one was found.
+    fn match_line(&mut self) -> bool {
+        if !self.match_token(TokenType::LINE) {
+            return false;
+        }
+
+        while self.match_token(TokenType::LINE) {}
+        true
+    }
+
+    // Discards any newlines starting at the current token.
+    fn ignore_newlines(&mut self) {
+        self.match_line();
+    }
+
+    // Consumes the current token. Emits an error if it is not a newline. Then
+    // discards any duplicate newlines following it.
+    fn consume_line(&mut self, error_message: &str) {
+        self.consume(TokenType::LINE, error_message);
+        self.ignore_newlines();
+    }
+
+    // Variables and scopes --------------------------------------------------------
+
+    // Pushes a new compiler for a function, method, or chunk of top level code.
+    fn init_compiler(&mut self, is_method: bool) {
+        // Declare a local slot for either the closure or method receiver so that we
+        // don't try to reuse that slot for a user-defined local variable. For
+        // methods, we name it "this", so that we can resolve references to that like
+        // a normal variable. For functions, they have no explicit "this", so we use
+        // an empty name. That way references to "this" inside a function walks up
+        // the parent chain to find a method enclosing the function whose "this" we
+        // can close over.
+        let name = if is_method { "this" } else { "" };
+        let locals = vec![Local {
+            name: name.to_string(),
+            depth: -1,
+            is_upvalue: false,
+            is_initialized: true,
+        }];
+
+        let scope_depth = if self.compilers.is_empty() {
+            // Compiling top-level code, so the initial scope is module-level.
+            -1
+        } else {
+            // The initial scope for functions and methods is local scope.
+            0
+        };
+
+        let fn_obj = ObjFn::new(self.module, locals.len());
+        self.compilers.push(Compiler {
+            num_slots: locals.len() as i32,
+            locals,
+            upvalues: Vec::new(),
+            loops: Vec::new(),
+            scope_depth,
+            fn_obj,
+            enclosing_class: None,
+            is_initializer: false,
+        });
+    }
+
+    // Walks the compiler chain to find the compiler for the nearest class
+    // enclosing this one. Returns `None` if not currently inside a class
+    // definition.
+    fn get_enclosing_class(&self) -> Option<usize> {
+        self.compilers
+            .iter()
+            .rposition(|compiler| compiler.enclosing_class.is_some())
+    }
+
+    // Returns the [ClassInfo] of the compiler at [index], which must be
+    // compiling a class body.
+    fn class_info(&mut self, index: usize) -> &mut ClassInfo {
+        self.compilers[index]
+            .enclosing_class
+            .as_mut()
+            .expect("Compiler should be compiling a class.")
+    }
+
+    // Emits one single-byte argument. Returns its index.
+    fn emit_byte(&mut self, byte: u8) -> usize {
+        let line = self.previous.line;
+        let fn_obj = &mut self.compiler().fn_obj;
+        fn_obj.code.push(byte);
+
+        // Assume the instruction is associated with the most recently consumed
+        // token.
+        fn_obj.debug.source_lines.push(line);
+
+        fn_obj.code.len() - 1
+    }
+
+    // Emits one bytecode instruction.
+    fn emit_op(&mut self, instruction: Code) {
+        self.emit_byte(instruction as u8);
+
+        // Keep track of the stack's high water mark.
+        let compiler = self.compiler();
+        compiler.num_slots += instruction.stack_effect();
+        if compiler.num_slots > compiler.fn_obj.max_slots as i32 {
+            compiler.fn_obj.max_slots = compiler.num_slots as usize;
+        }
+    }
+
+    // Emits one 16-bit argument, which will be written big endian.
+    fn emit_short(&mut self, arg: usize) {
+        self.emit_byte(((arg >> 8) & 0xff) as u8);
+        self.emit_byte((arg & 0xff) as u8);
+    }
+
+    // Emits one bytecode instruction followed by a 8-bit argument. Returns the
+    // index of the argument in the bytecode.
+    fn emit_byte_arg(&mut self, instruction: Code, arg: usize) -> usize {
+        self.emit_op(instruction);
+        self.emit_byte(arg as u8)
+    }
+
+    // Emits one bytecode instruction followed by a 16-bit argument, which will be
+    // written big endian.
+    fn emit_short_arg(&mut self, instruction: Code, arg: usize) {
+        self.emit_op(instruction);
+        self.emit_short(arg);
+    }
+
+    // Emits [instruction] followed by a placeholder for a jump offset. The
+    // placeholder can be patched by calling [patch_jump]. Returns the index of
+    // the placeholder.
+    fn emit_jump(&mut self, instruction: Code) -> usize {
+        self.emit_op(instruction);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff) - 1
+    }
+
+    // Replaces the placeholder argument for a previous CODE_JUMP or CODE_JUMP_IF
+    // instruction with an offset that jumps to the current end of bytecode.
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.compiler().fn_obj.code.len() - offset - 2;
+        if jump as i32 > MAX_JUMP {
+            self.error("Too much code to jump over.");
+        }
+
+        let code = &mut self.compiler().fn_obj.code;
+        code[offset] = ((jump >> 8) & 0xff) as u8;
+        code[offset + 1] = (jump & 0xff) as u8;
+    }
+
+    // Adds [constant] to the constant pool and returns its index.
+    fn add_constant(&mut self, constant: Value) -> usize {
+        if self.has_error {
+            return 0;
+        }
+
+        // See if we already have a constant for the value. If so, reuse it.
+        let existing = self
+            .compilers
+            .last()
+            .expect("Should have a compiler.")
+            .fn_obj
+            .constants
+            .iter()
+            .position(|&existing| wren_values_equal(self.vm, existing, constant));
+        if let Some(existing) = existing {
+            return existing;
+        }
+
+        // It's a new constant.
+        if self.compiler().fn_obj.constants.len() < MAX_CONSTANTS {
+            self.compiler().fn_obj.constants.push(constant);
+        } else {
+            self.error(&format!(
+                "A function may only contain {} unique constants.",
+                MAX_CONSTANTS
+            ));
+        }
+
+        self.compiler().fn_obj.constants.len() - 1
+    }
+
+    // Create a new local variable with [name]. Assumes the current scope is local
+    // and the name is unique.
+    fn add_local(&mut self, name: String) -> i32 {
+        let compiler = self.compiler();
+        let depth = compiler.scope_depth;
+        compiler.locals.push(Local {
+            name,
+            depth,
+            is_upvalue: false,
+            is_initialized: true,
+        });
+        compiler.locals.len() as i32 - 1
+    }
+
+    // Declares a variable in the current scope whose name is the given token.
+    //
+    // If [token] is `None`, uses the previously consumed token. Returns its symbol.
+    fn declare_variable(&mut self, token: Option<Token>) -> i32 {
+        let token = token.unwrap_or(self.previous);
+        let name = String::from_utf8_lossy(self.token_text(&token)).into_owned();
+
+        if token.length > MAX_VARIABLE_NAME as usize {
+            self.error(&format!(
+                "Variable name cannot be longer than {} characters.",
+                MAX_VARIABLE_NAME
+            ));
+        }
+
+        // Top-level module scope.
+        if self.compiler().scope_depth == -1 {
+            let mut line = -1;
+            let symbol = self
+                .vm
+                .define_variable(self.module, &name, Value::Null, Some(&mut line));
+
+            if symbol == -1 {
+                self.error("Module variable is already defined.");
+            } else if symbol == -2 {
+                self.error("Too many module variables defined.");
+            } else if symbol == -3 {
+                self.error(&format!(
+                    "Variable '{}' referenced before this definition (first use at line {}).",
+                    name, line
+                ));
+            }
+
+            return symbol;
+        }
+
+        // See if there is already a variable with this name declared in the current
+        // scope. (Outer scopes are OK: those get shadowed.)
+        let compiler = self.compiler();
+        let scope_depth = compiler.scope_depth;
+        for i in (0..compiler.locals.len()).rev() {
+            let local = &compiler.locals[i];
+
+            // Once we escape this scope and hit an outer one, we can stop.
+            if local.depth < scope_depth {
+                break;
+            }
+
+            if local.name == name {
+                self.error("Variable is already declared in this scope.");
+                return i as i32;
+            }
+        }
+
+        if self.compiler().locals.len() == MAX_LOCALS {
+            self.error(&format!(
+                "Cannot declare more than {} variables in one scope.",
+                MAX_LOCALS
+            ));
+            return -1;
+        }
+
+        self.add_local(name)
+    }
+
+    // Parses a name token and declares a variable in the current scope with that
+    // name. Returns its slot.
+    fn declare_named_variable(&mut self) -> i32 {
+        self.consume(TokenType::NAME, "Expect variable name.");
+        self.declare_variable(None)
+    }
+
+    // Stores a variable with the previously defined symbol in the current scope.
+    fn define_variable(&mut self, symbol: i32) {
+        // Store the variable. If it's a local, the result of the initializer is
+        // in the correct slot on the stack already so we're done.
+        if self.compiler().scope_depth >= 0 {
+            return;
+        }
+
+        // It's a module-level variable, so store the value in the module slot and
+        // then discard the temporary for the initializer.
+        self.emit_short_arg(Code::STORE_MODULE_VAR, symbol as usize);
+        self.emit_op(Code::POP);
+    }
+
+    // Starts a new local block scope.
+    fn push_scope(&mut self) {
+        self.compiler().scope_depth += 1;
+    }
+
+    // Generates code to discard local variables at [depth] or greater. Does *not*
+    // actually undeclare variables or pop any scopes, though. This is called
+    // directly when compiling "break" statements to ditch the local variables
+    // before jumping out of the loop even though they are still in scope *past*
+    // the break instruction.
+    //
+    // Returns the number of local variables that were eliminated.
+    //
+    // A "break" or "continue" in a loop at the top level of a module may call
+    // this outside of any block scope. There are no locals to discard then.
+    fn discard_locals(&mut self, depth: i32) -> usize {
+        let mut local = self.compiler().locals.len() as i32 - 1;
+        while local >= 0 && self.compiler().locals[local as usize].depth >= depth {
+            // If the local was closed over, make sure the upvalue gets closed when it
+            // goes out of scope on the stack. We use emitByte() and not emitOp() here
+            // because we don't want to track that stack effect of these pops since the
+            // variables are still in scope after the break.
+            if self.compiler().locals[local as usize].is_upvalue {
+                self.emit_byte(Code::CLOSE_UPVALUE as u8);
+            } else {
+                self.emit_byte(Code::POP as u8);
+            }
+            local -= 1;
+        }
+
+        self.compiler().locals.len() - (local + 1) as usize
+    }
+
+    // Closes the last pushed block scope and discards any local variables declared
+    // in that scope. This should only be called in a statement context where no
+    // temporaries are still on the stack.
+    fn pop_scope(&mut self) {
+        let depth = self.compiler().scope_depth;
+        debug_assert!(depth > -1, "Cannot exit top-level scope.");
+
+        let popped = self.discard_locals(depth);
+        let compiler = self.compiler();
+        let remaining = compiler.locals.len() - popped;
+        compiler.locals.truncate(remaining);
+        compiler.num_slots -= popped as i32;
+        compiler.scope_depth -= 1;
+    }
+
+    // Attempts to look up the name in the local variables of the compiler at
+    // index [compiler]. If found, returns its index, otherwise returns -1.
+    fn resolve_local(&mut self, compiler: usize, name: &str) -> i32 {
+        // Look it up in the local scopes. Look in reverse order so that the most
+        // nested variable is found first and shadows outer ones.
+        let locals = &self.compilers[compiler].locals;
+        for i in (0..locals.len()).rev() {
+            if locals[i].name == name {
+                if !locals[i].is_initialized {
+                    self.error("Cannot read local variable in its own initializer.");
+                }
+                return i as i32;
+            }
+        }
+
+        -1
+    }
+
+    // Adds an upvalue to the compiler at index [compiler] with the given
+    // properties. If an identical one already exists, returns its index.
+    // Otherwise, adds a new one.
+    fn add_upvalue(&mut self, compiler: usize, is_local: bool, index: i32) -> i32 {
+        let upvalues = &self.compilers[compiler].upvalues;
+
+        // Look for an existing one.
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing as i32;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            self.error(&format!(
+                "A function cannot close over more than {} variables.",
+                MAX_UPVALUES
+            ));
+
+            // The code will be discarded, so any existing upvalue will do.
+            return 0;
+        }
+
+        // If we got here, it's a new upvalue.
+        let upvalues = &mut self.compilers[compiler].upvalues;
+        upvalues.push(CompilerUpvalue { is_local, index });
+        upvalues.len() as i32 - 1
+    }
+
+    // Attempts to look up [name] in the function enclosing the one being
+    // compiled by the compiler at index [compiler]. If found, it adds an upvalue
+    // for it to this compiler's list of upvalues and returns its index. If not
+    // found, returns -1.
+    fn find_upvalue(&mut self, compiler: usize, name: &str) -> i32 {
+        // If we are at the top level, we didn't find it.
+        if compiler == 0 {
+            return -1;
+        }
+        let parent = compiler - 1;
+
+        // If we hit the method boundary (and the name isn't a static field), then
+        // stop looking for it. We'll instead treat it as a self send.
+        if !name.starts_with('_') && self.compilers[parent].enclosing_class.is_some() {
+            return -1;
+        }
+
+        // See if it's a local variable in the immediately enclosing function.
+        let local = self.resolve_local(parent, name);
+        if local != -1 {
+            // Mark the local as an upvalue so we know to close it when it goes out of
+            // scope.
+            self.compilers[parent].locals[local as usize].is_upvalue = true;
+
+            return self.add_upvalue(compiler, true, local);
+        }
+
+        // See if it's an upvalue in the immediately enclosing function. In other
+        // words, if it's a local variable in a non-immediately enclosing function.
+        // This "flattens" closures automatically: it adds upvalues to all of the
+        // intermediate functions to get from the function where a local is
+        // declared all the way into the possibly deeply nested function that is
+        // closing over it.
+        let upvalue = self.find_upvalue(parent, name);
+        if upvalue != -1 {
+            return self.add_upvalue(compiler, false, upvalue);
+        }
+
+        // If we got here, we walked all the way up the parent chain and couldn't
+        // find it.
+        -1
+    }
+
+    // Look up [name] in the current scope to see what variable it refers to.
+    // Returns the variable either in local scope, or the enclosing function's
+    // upvalue list. Does not search the module scope. Returns a variable with
+    // index -1 if not found.
+    fn resolve_non_module(&mut self, name: &str) -> Variable {
+        let current = self.compilers.len() - 1;
+        let index = self.resolve_local(current, name);
+        if index != -1 {
+            return Variable {
+                index,
+                scope: Scope::Local,
+            };
+        }
+
+        Variable {
+            index: self.find_upvalue(current, name),
+            scope: Scope::Upvalue,
+        }
+    }
+
+    // Look up [name] in the current scope to see what variable it refers to.
+    // Returns the variable either in local scope, an upvalue captured from an
+    // enclosing function, or the enclosing module's top-level scope. Returns a
+    // variable with index -1 if not found.
+    fn resolve_name(&mut self, name: &str) -> Variable {
+        let variable = self.resolve_non_module(name);
+        if variable.index != -1 {
+            return variable;
+        }
+
+        let index = self
+            .vm
+            .heap
+            .as_module(self.module)
+            .variable_names
+            .find(name);
+        Variable {
+            index,
+            scope: Scope::Module,
+        }
+    }
+
+    fn load_local(&mut self, slot: i32) {
+        if slot <= 8 {
+            self.emit_op(Code::from_byte(Code::LOAD_LOCAL_0 as u8 + slot as u8));
+            return;
+        }
+
+        self.emit_byte_arg(Code::LOAD_LOCAL, slot as usize);
+    }
+
+    // Finishes [compiler], which is compiling a function, method, or chunk of top
+    // level code. If there is a parent compiler, then this emits code in the
+    // parent compiler to load the resulting function.
+    fn end_compiler(&mut self, debug_name: &str) -> Option<ObjRef> {
+        // If we hit an error, don't finish the function since it's borked anyway.
+        if self.has_error {
+            self.compilers.pop();
+            return None;
+        }
+
+        // Mark the end of the bytecode. Since it may contain multiple early returns,
+        // we can't rely on CODE_RETURN to tell us we're at the end.
+        self.emit_op(Code::END);
+
+        // Pop this compiler off the stack.
+        let mut compiler = self.compilers.pop().expect("Should have a compiler.");
+        compiler.fn_obj.debug.name = debug_name.to_string();
+        compiler.fn_obj.num_upvalues = compiler.upvalues.len();
+        let fn_obj = wren_new_function(self.vm, compiler.fn_obj);
+
+        // In the function that contains this one, load the resulting function object.
+        if !self.compilers.is_empty() {
+            let constant = self.add_constant(Value::Obj(fn_obj));
+
+            // Wrap the function in a closure. We do this even if it has no upvalues so
+            // that the VM can uniformly assume all called objects are closures. This
+            // makes creating a function a little slower, but makes invoking them
+            // faster. Given that functions are invoked more often than they are
+            // created, this is a win.
+            self.emit_short_arg(Code::CLOSURE, constant);
+
+            // Emit arguments for each upvalue to know whether to capture a local or
+            // an upvalue.
+            for upvalue in &compiler.upvalues {
+                self.emit_byte(upvalue.is_local as u8);
+                self.emit_byte(upvalue.index as u8);
+            }
+        }
+
+        Some(fn_obj)
+    }
+
+    // Grammar ---------------------------------------------------------------------
+
+    // Parses an expression. Unlike statements, expressions leave a resulting value
+    // on the stack.
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Lowest);
+    }
+
+    // The main entrypoint for the top-down operator precedence parser.
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        if !self.enter_nesting() {
+            return;
+        }
+
+        self.next_token();
+        let prefix = match get_rule(self.previous.ty).prefix {
+            Some(prefix) => prefix,
+            None => {
+                self.error("Expected expression.");
+                self.nesting -= 1;
+                return;
+            }
+        };
+
+        // Track if the precendence of the surrounding expression is low enough to
+        // allow an assignment inside this one. We can't compile an assignment like
+        // a normal expression because it requires us to handle the LHS specially --
+        // it needs to be an lvalue, not an rvalue. So, for each of the kinds of
+        // expressions that are valid lvalues -- names, subscripts, fields, etc. --
+        // we pass in whether or not it appears in a context loose enough to allow
+        // "=". If so, it will parse the "=" itself and handle it appropriately.
+        let can_assign = precedence <= Precedence::Conditional;
+        let operand_start = self.compiler().fn_obj.code.len();
+        prefix(self, can_assign);
+
+        while precedence <= get_rule(self.current.ty).precedence {
+            self.next_token();
+            self.operand_start = operand_start;
+            let infix = get_rule(self.previous.ty)
+                .infix
+                .expect("Token with a precedence should have an infix rule.");
+            infix(self, can_assign);
+        }
+
+        // Every valid lvalue consumes the "=" itself, so one left over means the
+        // expression before it can't be assigned to.
+        if can_assign && self.match_token(TokenType::EQ) {
+            self.error("Invalid assignment target.");
+
+            // Parse the right-hand side anyway so that it isn't reported too.
+            self.expression();
+        }
+
+        self.nesting -= 1;
+    }
+
+    // Emits the code to load [variable].
+    fn load_variable(&mut self, variable: Variable) {
+        match variable.scope {
+            Scope::Local => self.load_local(variable.index),
+            Scope::Upvalue => {
+                self.emit_byte_arg(Code::LOAD_UPVALUE, variable.index as usize);
+            }
+            Scope::Module => self.emit_short_arg(Code::LOAD_MODULE_VAR, variable.index as usize),
+        }
+    }
+
+    // Emits the code to store the value on top of the stack in [variable].
+    fn store_variable(&mut self, variable: Variable) {
+        match variable.scope {
+            Scope::Local => {
+                self.emit_byte_arg(Code::STORE_LOCAL, variable.index as usize);
+            }
+            Scope::Upvalue => {
+                self.emit_byte_arg(Code::STORE_UPVALUE, variable.index as usize);
+            }
+            Scope::Module => self.emit_short_arg(Code::STORE_MODULE_VAR, variable.index as usize),
+        }
+    }
+
+    // If [can_assign] and the current token is a compound assignment operator
+    // like "+=", consumes it and returns the signature of the method the
+    // operator calls.
+    fn match_compound_assignment(&mut self, can_assign: bool) -> Option<&'static str> {
+        if !can_assign {
+            return None;
+        }
+
+        let operator = match self.current.ty {
+            TokenType::PLUSEQ => "+(_)",
+            TokenType::MINUSEQ => "-(_)",
+            TokenType::STAREQ => "*(_)",
+            TokenType::SLASHEQ => "/(_)",
+            _ => return None,
+        };
+        self.next_token();
+        Some(operator)
+    }
+
+    // Emits code to load a copy of the [count] values on top of the stack.
+    //
+    // A compound assignment to a property or subscript uses this to call the
+    // getter with the same receiver and arguments it later passes to the
+    // setter, so that they are only evaluated once.
+    fn duplicate_top(&mut self, count: i32) {
+        // After an error the slot count may be off, and the code gets discarded
+        // anyway.
+        if self.has_error {
+            return;
+        }
+
+        let first = self.compiler().num_slots - count;
+        for slot in first..first + count {
+            self.load_local(slot);
+        }
+    }
+
+    // Compiles a read or assignment to [variable].
+    fn bare_name(&mut self, can_assign: bool, variable: Variable) {
+        // If there's an "=" after a bare name, it's a variable assignment.
+        if can_assign && self.match_token(TokenType::EQ) {
+            // Compile the right-hand side.
+            self.expression();
+            self.store_variable(variable);
+            return;
+        }
+
+        // A compound assignment like "a += b" is compiled as "a = a + b".
+        if let Some(operator) = self.match_compound_assignment(can_assign) {
+            self.load_variable(variable);
+            self.expression();
+            self.call_method(1, operator);
+            self.store_variable(variable);
+            return;
+        }
+
+        // Emit the load instruction.
+        self.load_variable(variable);
+    }
+
+    // Gets the symbol for a method [name].
+    fn method_symbol(&mut self, name: &str) -> usize {
+        self.vm.method_names.ensure(name)
+    }
+
+    // Gets the method symbol for [signature].
+    fn signature_symbol(&mut self, signature: &Signature) -> usize {
+        self.method_symbol(&signature.to_full_name())
+    }
+
+    // Returns a signature with [ty] whose name is from the last consumed token.
+    fn signature_from_token(&mut self, ty: SignatureType) -> Signature {
+        // Get the token for the method name.
+        let token = self.previous;
+        let mut name = String::from_utf8_lossy(self.token_text(&token)).into_owned();
+
+        if token.length > MAX_METHOD_NAME as usize {
+            self.error(&format!(
+                "Method names cannot be longer than {} characters.",
+                MAX_METHOD_NAME
+            ));
+            name = String::from_utf8_lossy(&self.token_text(&token)[..MAX_METHOD_NAME as usize])
+                .into_owned();
+        }
+
+        Signature { name, ty, arity: 0 }
+    }
+
+    // Reports an error if [num_args] is more than a method can take.
+    fn validate_num_parameters(&mut self, num_args: i32) {
+        if num_args == MAX_PARAMETERS + 1 {
+            // Only show an error at exactly max + 1 so that we can keep parsing the
+            // parameters and minimize cascaded errors.
+            self.error(&format!(
+                "Methods cannot have more than {} parameters.",
+                MAX_PARAMETERS
+            ));
+        }
+    }
+
+    // Parses a comma-separated list of parameters and declares them as locals,
+    // adding each one to [signature]'s arity.
+    fn finish_parameter_list(&mut self, signature: &mut Signature) {
+        loop {
+            self.ignore_newlines();
+            signature.arity += 1;
+            self.validate_num_parameters(signature.arity);
+
+            // Define a local variable in the method for the parameter.
+            self.declare_named_variable();
+
+            if !self.match_token(TokenType::COMMA) {
+                break;
+            }
+        }
+    }
+
+    // Parses a comma-separated list of arguments. Modifies [signature] to include
+    // the arity of the argument list.
+    fn finish_argument_list(&mut self, signature: &mut Signature) {
+        loop {
+            self.ignore_newlines();
+            signature.arity += 1;
+            self.validate_num_parameters(signature.arity);
+            self.expression();
+
+            if !self.match_token(TokenType::COMMA) {
+                break;
+            }
+        }
+
+        // Allow a newline before the closing delimiter.
+        self.ignore_newlines();
+    }
+
+    // Compiles a call to the method with signature [name] passing [num_args]
+    // arguments that are already on the stack.
+    fn call_method(&mut self, num_args: usize, name: &str) {
+        let symbol = self.method_symbol(name);
+        let instruction = Code::from_byte(Code::CALL_0 as u8 + num_args as u8);
+        self.emit_short_arg(instruction, symbol);
+    }
+
+    // Compiles a method call with [signature] using [instruction].
+    fn call_signature(&mut self, instruction: Code, signature: &Signature) {
+        let symbol = self.signature_symbol(signature);
+        let instruction = Code::from_byte(instruction as u8 + signature.arity as u8);
+        self.emit_short_arg(instruction, symbol);
+    }
+
+    // Compiles the body of a function or method, after its "{" has been
+    // consumed, and emits its return.
+    fn finish_body(&mut self) {
+        let is_expression_body = self.finish_block();
+
+        if self.compiler().is_initializer {
+            // If the initializer body evaluates to a value, discard it.
+            if is_expression_body {
+                self.emit_op(Code::POP);
+            }
+
+            // The receiver is always stored in the first local slot.
+            self.emit_op(Code::LOAD_LOCAL_0);
+        } else if !is_expression_body {
+            // Implicitly return null in statement bodies.
+            null(self, false);
+        }
+
+        self.emit_op(Code::RETURN);
+    }
+
+    // Compiles an (optional) argument list for a method call with [signature]
+    // and then calls it.
+    fn method_call(&mut self, instruction: Code, signature: &Signature) {
+        // Make a new signature that contains the updated arity and type based on
+        // the arguments we find.
+        let mut called = Signature {
+            name: signature.name.clone(),
+            ty: SignatureType::Getter,
+            arity: 0,
+        };
+
+        // Parse the argument list, if any.
+        if self.match_token(TokenType::LEFT_PAREN) {
+            called.ty = SignatureType::Method;
+
+            // Allow new line before an empty argument list
+            self.ignore_newlines();
+
+            // Allow empty an argument list.
+            if self.peek() != TokenType::RIGHT_PAREN {
+                self.finish_argument_list(&mut called);
+            }
+            self.consume(TokenType::RIGHT_PAREN, "Expect ')' after arguments.");
+        }
+
+        // Parse the block argument, if any.
+        if self.match_token(TokenType::LEFT_BRACE) {
+            // Include the block argument in the arity.
+            called.ty = SignatureType::Method;
+            called.arity += 1;
+            self.validate_num_parameters(called.arity);
+
+            self.init_compiler(false);
+
+            // Make a dummy signature to track the arity.
+            let mut fn_signature = Signature {
+                name: String::new(),
+                ty: SignatureType::Method,
+                arity: 0,
+            };
+
+            // Parse the parameter list, if any.
+            if self.match_token(TokenType::PIPE) {
+                self.finish_parameter_list(&mut fn_signature);
+                self.consume(TokenType::PIPE, "Expect '|' after function parameters.");
+            }
+
+            self.compiler().fn_obj.arity = fn_signature.arity;
+
+            self.finish_body();
+
+            // Name the function based on the method its passed to.
+            let block_name = format!("{} block argument", called.to_full_name());
+            self.end_compiler(&block_name);
+        }
+
+        self.call_signature(instruction, &called);
+    }
+
+    // Compiles a call whose name is the previously consumed token. This includes
+    // getters, method calls with arguments, and setter calls.
+    fn named_call(&mut self, can_assign: bool, instruction: Code) {
+        // Get the token for the method name.
+        let mut signature = self.signature_from_token(SignatureType::Getter);
+
+        if can_assign && self.match_token(TokenType::EQ) {
+            self.ignore_newlines();
+
+            // Build the setter signature.
+            signature.ty = SignatureType::Setter;
+            signature.arity = 1;
+
+            // Compile the assigned value.
+            self.expression();
+            self.call_signature(instruction, &signature);
+        } else if let Some(operator) = self.match_compound_assignment(can_assign) {
+            // Read the property from a copy of the receiver, then pass the
+            // result of the operator to the setter.
+            self.duplicate_top(1);
+            self.call_signature(instruction, &signature);
+            self.expression();
+            self.call_method(1, operator);
+
+            signature.ty = SignatureType::Setter;
+            signature.arity = 1;
+            self.call_signature(instruction, &signature);
+        } else {
+            self.method_call(instruction, &signature);
+        }
+    }
+
+    // Parses a block body, after the initial "{" has been consumed.
+    //
+    // Returns true if it was a expression body, false if it was a statement body.
+    // (More precisely, returns true if a value was left on the stack. An empty
+    // block returns false.)
+    fn finish_block(&mut self) -> bool {
+        // Empty blocks do nothing.
+        if self.match_token(TokenType::RIGHT_BRACE) {
+            return false;
+        }
+
+        // If there's no line after the "{", it's a single-expression body.
+        if !self.match_line() {
+            self.expression();
+            self.consume(TokenType::RIGHT_BRACE, "Expect '}' at end of block.");
+            return true;
+        }
+
+        // Empty blocks (with just a newline inside) do nothing.
+        if self.match_token(TokenType::RIGHT_BRACE) {
+            return false;
+        }
+
+        // Compile the definition list.
+        let enclosing_depth = self.block_depth;
+        self.block_depth = self.brace_depth;
+        let mut reachability = Reachability::Reachable;
+        loop {
+            reachability = self.check_reachable(reachability);
+            self.nested(Parser::definition);
+            if self.panic_mode {
+                self.synchronize();
+            } else {
+                self.consume_line("Expect newline after statement.");
+            }
+
+            if self.brace_depth < self.block_depth
+                || self.peek() == TokenType::RIGHT_BRACE
+                || self.peek() == TokenType::EOF
+            {
+                break;
+            }
+        }
+
+        // A bad statement may have consumed the "}" as the token in error.
+        let is_closed = self.brace_depth < self.block_depth;
+        self.block_depth = enclosing_depth;
+        if !is_closed {
+            self.consume(TokenType::RIGHT_BRACE, "Expect '}' at end of block.");
+        }
+        false
+    }
+
+    // Marks the beginning of a loop. Keeps track of the current instruction so we
+    // know what to loop back to at the end of the body.
+    fn start_loop(&mut self) {
+        let compiler = self.compiler();
+        let start = compiler.fn_obj.code.len();
+        let scope_depth = compiler.scope_depth;
+        compiler.loops.push(Loop {
+            start,
+            exit_jump: 0,
+            body: 0,
+            scope_depth,
+        });
+    }
+
+    // Emits the [CODE_JUMP_IF] instruction used to test the loop condition and
+    // potentially exit the loop. Keeps track of the instruction so we can patch it
+    // later once we know where the end of the body is.
+    fn test_exit_loop(&mut self) {
+        let exit_jump = self.emit_jump(Code::JUMP_IF);
+        self.current_loop().exit_jump = exit_jump;
+    }
+
+    // Compiles the body of the loop and tracks its extent so that contained "break"
+    // statements can be handled correctly.
+    fn loop_body(&mut self) {
+        let body = self.compiler().fn_obj.code.len();
+        self.current_loop().body = body;
+        self.nested(Parser::statement);
+    }
+
+    // Emits a CODE_LOOP instruction that jumps back to the instruction at
+    // [start].
+    fn emit_loop(&mut self, start: usize) {
+        // +3 to also jump back over the CODE_LOOP instruction and its argument.
+        let loop_offset = self.compiler().fn_obj.code.len() - start + 3;
+        self.emit_short_arg(Code::LOOP, loop_offset);
+    }
+
+    // Ends the current innermost loop. Patches up all jumps and breaks now that
+    // we know where the end of the loop is.
+    fn end_loop(&mut self) {
+        // We don't check for overflow here since the forward jump over the loop body
+        // will report an error for the same problem.
+        let start = self.current_loop().start;
+        self.emit_loop(start);
+
+        let exit_jump = self.current_loop().exit_jump;
+        self.patch_jump(exit_jump);
+
+        // Find any break placeholder instructions (which will be CODE_END in the
+        // bytecode) and replace them with real jumps.
+        let mut i = self.current_loop().body;
+        while i < self.compiler().fn_obj.code.len() {
+            if self.compiler().fn_obj.code[i] == Code::END as u8 {
+                self.compiler().fn_obj.code[i] = Code::JUMP as u8;
+                self.patch_jump(i + 1);
+                i += 3;
+            } else {
+                // Skip this instruction and its arguments.
+                let fn_obj = &self
+                    .compilers
+                    .last()
+                    .expect("Should have a compiler.")
+                    .fn_obj;
+                i += 1 + get_byte_count_for_arguments(&self.vm.heap, fn_obj, i);
+            }
+        }
+
+        self.compiler().loops.pop();
+    }
+
+    // Returns the innermost loop being compiled in the current function.
+    fn current_loop(&mut self) -> &mut Loop {
+        self.compiler()
+            .loops
+            .last_mut()
+            .expect("Should be compiling a loop.")
+    }
+
+    fn for_statement(&mut self) {
+        // A for statement like:
+        //
+        //     for (i in sequence.expression) {
+        //       System.print(i)
+        //     }
+        //
+        // Is compiled to bytecode almost as if the source looked like this:
+        //
+        //     {
+        //       var seq_ = sequence.expression
+        //       var iter_
+        //       while (iter_ = seq_.iterate(iter_)) {
+        //         var i = seq_.iteratorValue(iter_)
+        //         System.print(i)
+        //       }
+        //     }
+        //
+        // It's not exactly this, because the synthetic variables `seq_` and `iter_`
+        // actually get names that aren't valid Wren identfiers, but that's the basic
+        // idea.
+        //
+        // The important parts are:
+        // - The sequence expression is only evaluated once.
+        // - The .iterate() method is used to advance the iterator and determine if
+        //   it should exit the loop.
+        // - The .iteratorValue() method is used to get the value at the current
+        //   iterator position.
+
+        // Create a scope for the hidden local variables used for the iterator.
+        self.push_scope();
+
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'for'.");
+        self.consume(TokenType::NAME, "Expect for loop variable name.");
+
+        // Remember the name of the loop variable.
+        let name = String::from_utf8_lossy(self.token_text(&self.previous)).into_owned();
+
+        self.consume(TokenType::IN, "Expect 'in' after loop variable.");
+        self.ignore_newlines();
+
+        // Evaluate the sequence expression and store it in a hidden local variable.
+        // The space in the variable name ensures it won't collide with a user-defined
+        // variable.
+        self.expression();
+
+        // Verify that there is space to hidden local variables.
+        // Note that we expect only two add_local calls next to each other in the
+        // following code.
+        if self.compiler().locals.len() + 2 > MAX_LOCALS {
+            self.error(&format!(
+                "Cannot declare more than {} variables in one scope. (Not enough space for for-loops internal variables)",
+                MAX_LOCALS
+            ));
+            return;
+        }
+        let seq_slot = self.add_local("seq ".to_string());
+
+        // Create another hidden local for the iterator object.
+        null(self, false);
+        let iter_slot = self.add_local("iter ".to_string());
+
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after loop expression.");
+
+        self.start_loop();
+
+        // Advance the iterator by calling the ".iterate" method on the sequence.
+        self.load_local(seq_slot);
+        self.load_local(iter_slot);
+
+        // Update and test the iterator.
+        self.call_method(1, "iterate(_)");
+        self.emit_byte_arg(Code::STORE_LOCAL, iter_slot as usize);
+        self.test_exit_loop();
+
+        // Get the current value in the sequence by calling ".iteratorValue".
+        self.load_local(seq_slot);
+        self.load_local(iter_slot);
+        self.call_method(1, "iteratorValue(_)");
+
+        // Bind the loop variable in its own scope. This ensures we get a fresh
+        // variable each iteration so that closures for it don't all see the same one.
+        self.push_scope();
+        self.add_local(name);
+
+        self.loop_body();
+
+        // Loop variable.
+        self.pop_scope();
+
+        self.end_loop();
+
+        // Hidden variables.
+        self.pop_scope();
+    }
+
+    fn if_statement(&mut self) {
+        // Compile the condition.
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after if condition.");
+
+        // Jump to the else branch if the condition is false.
+        let if_jump = self.emit_jump(Code::JUMP_IF);
+
+        // Compile the then branch.
+        self.nested(Parser::statement);
+
+        // Compile the else branch if there is one.
+        if self.match_token(TokenType::ELSE) {
+            // Jump over the else branch when the if branch is taken.
+            let else_jump = self.emit_jump(Code::JUMP);
+            self.patch_jump(if_jump);
+
+            self.nested(Parser::statement);
+
+            // Patch the jump over the else.
+            self.patch_jump(else_jump);
+        } else {
+            self.patch_jump(if_jump);
+        }
+    }
+
+    fn while_statement(&mut self) {
+        self.start_loop();
+
+        // Compile the condition.
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after while condition.");
+
+        self.test_exit_loop();
+        self.loop_body();
+        self.end_loop();
+    }
+
+    // Compiles a simple statement. These can only appear at the top-level or
+    // within curly blocks. Simple statements exclude variable binding statements
+    // like "var" and "class" which are not allowed directly in places like the
+    // branches of an "if" statement.
+    //
+    // Unlike expressions, statements do not leave a value on the stack.
+    fn statement(&mut self) {
+        if self.match_token(TokenType::BREAK) {
+            let scope_depth = match self.compiler().loops.last() {
+                Some(current) => current.scope_depth,
+                None => {
+                    self.error("Cannot use 'break' outside of a loop.");
+                    return;
+                }
+            };
+
+            // Since we will be jumping out of the scope, make sure any locals in it
+            // are discarded first.
+            self.discard_locals(scope_depth + 1);
+
+            // Emit a placeholder instruction for the jump to the end of the body. When
+            // we're done compiling the loop body and know where the end is, we'll
+            // replace these with `CODE_JUMP` instructions with appropriate offsets.
+            // We use `CODE_END` here because that can't occur in the middle of
+            // bytecode.
+            self.emit_jump(Code::END);
+            return;
+        }
+
+        if self.match_token(TokenType::CONTINUE) {
+            let (start, scope_depth) = match self.compiler().loops.last() {
+                Some(current) => (current.start, current.scope_depth),
+                None => {
+                    self.error("Cannot use 'continue' outside of a loop.");
+                    return;
+                }
+            };
+
+            // Since we will be jumping out of the scope, make sure any locals in it
+            // are discarded first. Any of them captured by a closure get their
+            // upvalues closed, so the next iteration gets fresh variables.
+            self.discard_locals(scope_depth + 1);
+
+            // Emit a jump back to the top of the loop.
+            self.emit_loop(start);
+            return;
+        }
+
+        if self.match_token(TokenType::FOR) {
+            self.for_statement();
+            return;
+        }
+
+        if self.match_token(TokenType::IF) {
+            self.if_statement();
+            return;
+        }
+
+        if self.match_token(TokenType::WHILE) {
+            self.while_statement();
+            return;
+        }
+
+        if self.match_token(TokenType::LEFT_BRACE) {
+            // Block statement.
+            self.push_scope();
+            if self.finish_block() {
+                // Block was an expression, so discard it.
+                self.emit_op(Code::POP);
+            }
+            self.pop_scope();
+            return;
+        }
+
+        if self.match_token(TokenType::RETURN) {
+            // Compile the return value.
+            if self.peek() == TokenType::LINE {
+                // If there's no expression after return, initializers should
+                // return 'this' and regular methods should return null
+                if self.compiler().is_initializer {
+                    self.emit_op(Code::LOAD_LOCAL_0);
+                } else {
+                    null(self, false);
+                }
+            } else {
+                if self.compiler().is_initializer {
+                    self.error("A constructor cannot return a value.");
+                }
+
+                self.expression();
+            }
+
+            self.emit_op(Code::RETURN);
+            return;
+        }
+
+        // Expression statement.
+        self.expression();
+        self.emit_op(Code::POP);
+    }
+
+    // Compiles a "var" variable definition statement.
+    // Compiles an "import" statement.
+    //
+    // An import compiles to a series of instructions. Given:
+    //
+    //     import "foo" for Bar, Baz
+    //
+    // We compile a single IMPORT_MODULE "foo" instruction to load the module
+    // itself. When that finishes executing the imported module, it leaves the
+    // ObjModule in vm.last_module. Then, for Bar and Baz, we:
+    //
+    // * Declare a variable in the current scope with that name.
+    // * Emit an IMPORT_VARIABLE instruction to load the variable's value from the
+    //   other module.
+    // * Compile the code to store that value in the variable in this scope.
+    fn import(&mut self) {
+        self.ignore_newlines();
+        self.consume(TokenType::STRING, "Expect a string after 'import'.");
+        let module_constant = self.add_constant(self.previous.value);
+
+        // Load the module.
+        self.emit_short_arg(Code::IMPORT_MODULE, module_constant);
+
+        // Discard the unused result value from calling the module body's closure.
+        self.emit_op(Code::POP);
+
+        // The for clause is optional.
+        if !self.match_token(TokenType::FOR) {
+            return;
+        }
+
+        // Compile the comma-separated list of variables to import.
+        loop {
+            self.ignore_newlines();
+            self.consume(TokenType::NAME, "Expect variable name.");
+
+            // We need to hold onto the source variable, in order to reference it in
+            // the import later.
+            let source_variable_token = self.previous;
+
+            // Define a string constant for the original variable name.
+            let text = self.token_text(&source_variable_token).to_vec();
+            let name = wren_new_string(self.vm, &text);
+            let source_variable_constant = self.add_constant(name);
+
+            let slot = if self.match_token(TokenType::AS) {
+                // import "module" for Source as Dest
+                // Use 'Dest' as the name by declaring a new variable for it.
+                self.declare_named_variable()
+            } else {
+                // import "module" for Source
+                // Uses 'Source' as the name directly.
+                self.declare_variable(Some(source_variable_token))
+            };
+
+            // Load the variable from the other module.
+            self.emit_short_arg(Code::IMPORT_VARIABLE, source_variable_constant);
+
+            // Store the result in the variable here.
+            self.define_variable(slot);
+
+            if !self.match_token(TokenType::COMMA) {
+                break;
+            }
+        }
+    }
+
+    fn variable_definition(&mut self) {
+        self.consume(TokenType::NAME, "Expect variable name.");
+        let name_token = self.previous;
+
+        // A local is declared before its initializer so that it shadows any outer
+        // variable with the same name, but it stays uninitialized until the
+        // initializer is done so that it can't be read there. Module variables
+        // are declared afterwards since a use before the definition is already
+        // an error for them.
+        let is_local = self.compiler().scope_depth >= 0;
+        let mut symbol = -1;
+        if is_local {
+            symbol = self.declare_variable(Some(name_token));
+            if let Some(local) = self.compiler().locals.get_mut(symbol as usize) {
+                local.is_initialized = false;
+            }
+        }
+
+        // Compile the initializer.
+        if self.match_token(TokenType::EQ) {
+            self.ignore_newlines();
+            self.expression();
+        } else {
+            // Default initialize it to null.
+            null(self, false);
+        }
+
+        // Now it can be used.
+        if is_local {
+            if let Some(local) = self.compiler().locals.get_mut(symbol as usize) {
+                local.is_initialized = true;
+            }
+        } else {
+            symbol = self.declare_variable(Some(name_token));
+        }
+        self.define_variable(symbol);
+    }
+
+    // Loads the receiver of the currently enclosing method. Correctly handles
+    // functions defined inside methods.
+    fn load_this(&mut self) {
+        let variable = self.resolve_non_module("this");
+        self.load_variable(variable);
+    }
+
+    // Emits code to load the core module variable [name].
+    fn load_core_variable(&mut self, name: &str) {
+        let symbol = self
+            .vm
+            .heap
+            .as_module(self.module)
+            .variable_names
+            .find(name);
+        debug_assert!(symbol != -1, "Should have already defined core name.");
+        self.emit_short_arg(Code::LOAD_MODULE_VAR, symbol as usize);
+    }
+
+    // Compiles an optional setter parameter in a method [signature].
+    //
+    // Returns `true` if it was a setter.
+    fn maybe_setter(&mut self, signature: &mut Signature) -> bool {
+        // See if it's a setter.
+        if !self.match_token(TokenType::EQ) {
+            return false;
+        }
+
+        // It's a setter.
+        if signature.ty == SignatureType::Subscript {
+            signature.ty = SignatureType::SubscriptSetter;
+        } else {
+            signature.ty = SignatureType::Setter;
+        }
+
+        // Parse the value parameter.
+        self.consume(TokenType::LEFT_PAREN, "Expect '(' after '='.");
+        self.declare_named_variable();
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameter name.");
+
+        signature.arity += 1;
+        self.validate_num_parameters(signature.arity);
+
+        true
+    }
+
+    // Parses the rest of a parameter list after the "(" has been consumed, if
+    // there is one.
+    fn parameter_list(&mut self, signature: &mut Signature) {
+        // The parameter list is optional.
+        if !self.match_token(TokenType::LEFT_PAREN) {
+            return;
+        }
+
+        signature.ty = SignatureType::Method;
+
+        // Allow new line before an empty argument list
+        self.ignore_newlines();
+
+        // Allow an empty parameter list.
+        if self.match_token(TokenType::RIGHT_PAREN) {
+            return;
+        }
+
+        self.finish_parameter_list(signature);
+        self.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameters.");
+    }
+
+    // Creates a matching constructor method for an initializer with [signature]
+    // and [initializer_symbol].
+    //
+    // Construction is a two-stage process in Wren that involves two separate
+    // methods. There is a static method that allocates a new instance of the
+    // class. It then invokes an initializer method on the new instance, forwarding
+    // all of the constructor arguments to it.
+    //
+    // The allocator method always has a fixed implementation:
+    //
+    //     CODE_CONSTRUCT - Replace the class in slot 0 with a new instance of it.
+    //     CODE_CALL      - Invoke the initializer on the new instance.
+    //
+    // This creates that method and calls the initializer with [initializerSymbol].
+    fn create_constructor(&mut self, signature: &Signature, initializer_symbol: usize) {
+        self.init_compiler(true);
+        self.compiler().fn_obj.arity = signature.arity;
+
+        // Allocate the instance.
+        self.emit_op(Code::CONSTRUCT);
+
+        // Run its initializer.
+        let instruction = Code::from_byte(Code::CALL_0 as u8 + signature.arity as u8);
+        self.emit_short_arg(instruction, initializer_symbol);
+
+        // Return the instance.
+        self.emit_op(Code::RETURN);
+
+        self.end_compiler("");
+    }
+
+    // Loads the enclosing class onto the stack and then binds the function already
+    // on the stack as a method on that class.
+    fn define_method(&mut self, class_variable: Variable, is_static: bool, method_symbol: usize) {
+        // Load the class. We have to do this for each method because we can't
+        // keep the class on top of the stack. If there are static fields, they
+        // will be locals above the initial variable slot for the class on the
+        // stack. To skip past those, we just load the class each time right before
+        // defining a method.
+        self.load_variable(class_variable);
+
+        // Define the method.
+        let instruction = if is_static {
+            Code::METHOD_STATIC
+        } else {
+            Code::METHOD_INSTANCE
+        };
+        self.emit_short_arg(instruction, method_symbol);
+    }
+
+    // Declares a method in the enclosing class with [signature].
+    //
+    // Reports an error if a method with that signature is already declared.
+    // Returns the symbol for the method.
+    fn declare_method(&mut self, signature: &Signature, name: &str) -> usize {
+        let symbol = self.signature_symbol(signature);
+
+        // See if the class has already declared method with this signature.
+        let class_index = self
+            .get_enclosing_class()
+            .expect("Should be compiling a class.");
+        let class_info = self.class_info(class_index);
+        let in_static = class_info.in_static;
+        let methods = if in_static {
+            &mut class_info.static_methods
+        } else {
+            &mut class_info.methods
+        };
+
+        let is_duplicate = methods.contains(&symbol);
+        methods.push(symbol);
+
+        if is_duplicate {
+            let class_name = class_info.name.clone();
+            let static_prefix = if in_static { "static " } else { "" };
+            self.error(&format!(
+                "Class {} already defines a {}method '{}'.",
+                class_name, static_prefix, name
+            ));
+        }
+
+        symbol
+    }
+
+    // Compiles a method definition inside a class body.
+    //
+    // Returns `true` if it compiled successfully, or `false` if the method couldn't
+    // be parsed.
+    fn method(&mut self, class_variable: Variable) -> bool {
+        // Parse any attributes before the method.
+        let is_foreign = self.match_token(TokenType::FOREIGN);
+        let is_static = self.match_token(TokenType::STATIC);
+        let class_index = self
+            .get_enclosing_class()
+            .expect("Should be compiling a class.");
+        self.class_info(class_index).in_static = is_static;
+
+        let signature_fn = get_rule(self.current.ty).method;
+        self.next_token();
+
+        let signature_fn = match signature_fn {
+            Some(signature_fn) => signature_fn,
+            None => {
+                self.error("Expect method definition.");
+                return false;
+            }
+        };
+
+        // Build the method signature.
+        let mut signature = self.signature_from_token(SignatureType::Getter);
+
+        self.init_compiler(true);
+
+        // Compile the method signature.
+        signature_fn(self, &mut signature);
+        self.compiler().fn_obj.arity = signature.arity;
+
+        self.compiler().is_initializer = signature.ty == SignatureType::Initializer;
+
+        if is_static && signature.ty == SignatureType::Initializer {
+            self.error("A constructor cannot be static.");
+        }
+
+        // Include the full signature in debug messages in stack traces.
+        let full_signature = signature.to_full_name();
+
+        // Check for duplicate methods. Doesn't matter that it's already been
+        // defined, error will discard bytecode anyway.
+        // Check if the method table already contains this symbol
+        let method_symbol = self.declare_method(&signature, &full_signature);
+
+        if is_foreign {
+            // Define a constant for the signature.
+            let signature = wren_new_string(self.vm, full_signature.as_bytes());
+
+            // We don't need the function we started compiling in the parameter list
+            // any more.
+            self.compilers.pop();
+
+            let constant = self.add_constant(signature);
+            self.emit_short_arg(Code::CONSTANT, constant);
+        } else {
+            self.consume(TokenType::LEFT_BRACE, "Expect '{' to begin method body.");
+            self.finish_body();
+            self.end_compiler(&full_signature);
+        }
+
+        // Define the method. For a constructor, this defines the instance
+        // initializer method.
+        self.define_method(class_variable, is_static, method_symbol);
+
+        if signature.ty == SignatureType::Initializer {
+            // Also define a matching constructor method on the metaclass.
+            signature.ty = SignatureType::Method;
+            let constructor_symbol = self.signature_symbol(&signature);
+
+            self.create_constructor(&signature, method_symbol);
+            self.define_method(class_variable, true, constructor_symbol);
+        }
+
+        true
+    }
+
+    // Compiles a class definition. Assumes the "class" token has already been
+    // consumed.
+    fn class_definition(&mut self) {
+        // Create a variable to store the class in.
+        let scope = if self.compiler().scope_depth == -1 {
+            Scope::Module
+        } else {
+            Scope::Local
+        };
+        let index = self.declare_named_variable();
+        let class_variable = Variable { index, scope };
+
+        // Create shared class name value
+        let name_token = self.previous;
+        let class_name = self.token_text(&name_token).to_vec();
+        let class_name_string = wren_new_string(self.vm, &class_name);
+
+        // Make a string constant for the name.
+        let constant = self.add_constant(class_name_string);
+        self.emit_short_arg(Code::CONSTANT, constant);
+
+        // Load the superclass (if there is one).
+        if self.match_token(TokenType::IS) {
+            self.parse_precedence(Precedence::Call);
+        } else {
+            // Implicitly inherit from Object.
+            self.load_core_variable("Object");
+        }
+
+        // Store a placeholder for the number of fields argument. We don't know the
+        // count until we've compiled all the methods to see which fields are used.
+        let num_fields_instruction = self.emit_byte_arg(Code::CLASS, 255);
+
+        // Store it in its name.
+        self.define_variable(class_variable.index);
+
+        // Push a local variable scope. Static fields in a class body are hoisted out
+        // into local variables declared in this scope. Methods that use them will
+        // have upvalues referencing them.
+        self.push_scope();
+
+        // Set up a symbol table for the class's fields. We'll initially compile
+        // them to slots starting at zero. When the method is bound to the class, the
+        // bytecode will be adjusted by [wren_bind_method_code] to take inherited
+        // fields into account.
+        self.compiler().enclosing_class = Some(ClassInfo {
+            name: String::from_utf8_lossy(&class_name).into_owned(),
+            fields: SymbolTable::default(),
+            methods: Vec::new(),
+            static_methods: Vec::new(),
+            in_static: false,
+        });
+
+        // Compile the method definitions.
+        self.consume(TokenType::LEFT_BRACE, "Expect '{' after class declaration.");
+        self.match_line();
+
+        while !self.match_token(TokenType::RIGHT_BRACE) {
+            if !self.method(class_variable) {
+                break;
+            }
+
+            // Don't require a newline after the last definition.
+            if self.match_token(TokenType::RIGHT_BRACE) {
+                break;
+            }
+
+            self.consume_line("Expect newline after definition in class.");
+        }
+
+        // Update the class with the number of fields.
+        let class_info = self
+            .compiler()
+            .enclosing_class
+            .take()
+            .expect("Should be compiling a class.");
+        self.compiler().fn_obj.code[num_fields_instruction] = class_info.fields.count() as u8;
+
+        self.pop_scope();
+    }
+
+    // Compiles a "definition". These are the statements that bind new variables.
+    // They can only appear at the top level of a block and are prohibited in places
+    // like the non-curly body of an if or while.
+    fn definition(&mut self) {
+        if self.match_token(TokenType::CLASS) {
+            self.class_definition();
+            return;
+        }
+
+        if self.match_token(TokenType::IMPORT) {
+            self.import();
+            return;
+        }
+
+        if self.match_token(TokenType::VAR) {
+            self.variable_definition();
+            return;
+        }
+
+        self.statement();
+    }
+}
+
+fn grouping(parser: &mut Parser, _can_assign: bool) {
+    parser.expression();
+    parser.consume(TokenType::RIGHT_PAREN, "Expect ')' after expression.");
+}
+
+// A list literal.
+fn list(parser: &mut Parser, _can_assign: bool) {
+    // Instantiate a new list.
+    parser.load_core_variable("List");
+    parser.call_method(0, "new()");
+
+    // Compile the list elements. Each one compiles to a ".addCore_()" call.
+    loop {
+        parser.ignore_newlines();
+
+        // Stop if we hit the end of the list. Checking before each element
+        // allows a trailing comma.
+        if parser.peek() == TokenType::RIGHT_BRACKET {
+            break;
+        }
+
+        // The element.
+        parser.expression();
+        parser.call_method(1, "addCore_(_)");
+
+        if !parser.match_token(TokenType::COMMA) {
+            break;
+        }
+    }
+
+    // Allow newlines before the closing ']'.
+    parser.ignore_newlines();
+    parser.consume(TokenType::RIGHT_BRACKET, "Expect ']' after list elements.");
+}
+
+// A map literal.
+fn map(parser: &mut Parser, _can_assign: bool) {
+    // Instantiate a new map.
+    parser.load_core_variable("Map");
+    parser.call_method(0, "new()");
+
+    // Compile the map entries. Each one compiles to an ".addCore_()" call.
+    loop {
+        parser.ignore_newlines();
+
+        // Stop if we hit the end of the map. Checking before each entry allows a
+        // trailing comma.
+        if parser.peek() == TokenType::RIGHT_BRACE {
+            break;
+        }
+
+        // The key.
+        parser.parse_precedence(Precedence::Unary);
+        parser.consume(TokenType::COLON, "Expect ':' after map key.");
+        parser.ignore_newlines();
+
+        // The value.
+        parser.expression();
+        parser.call_method(2, "addCore_(_,_)");
+
+        if !parser.match_token(TokenType::COMMA) {
+            break;
+        }
+    }
+
+    // Allow newlines before the closing '}'.
+    parser.ignore_newlines();
+    parser.consume(TokenType::RIGHT_BRACE, "Expect '}' after map entries.");
+}
+
+// Unary operators like `-foo`.
+fn unary_op(parser: &mut Parser, _can_assign: bool) {
+    let signature = parser.signature_from_token(SignatureType::Getter);
+
+    parser.ignore_newlines();
+
+    // Compile the argument.
+    parser.parse_precedence(Precedence::Unary.next());
+
+    // Call the operator method on the left-hand side.
+    parser.call_signature(Code::CALL_0, &signature);
+}
+
+fn boolean(parser: &mut Parser, _can_assign: bool) {
+    if parser.previous.ty == TokenType::FALSE {
+        parser.emit_op(Code::FALSE);
+    } else {
+        parser.emit_op(Code::TRUE);
+    }
+}
+
+fn null(parser: &mut Parser, _can_assign: bool) {
+    parser.emit_op(Code::NULL);
+}
+
+// A number or string literal.
+fn literal(parser: &mut Parser, _can_assign: bool) {
+    let constant = parser.add_constant(parser.previous.value);
+    parser.emit_short_arg(Code::CONSTANT, constant);
+}
+
+// A string literal with interpolated expressions, like `"a %(b) c"`.
+fn string_interpolation(parser: &mut Parser, _can_assign: bool) {
+    // Instantiate a new list.
+    parser.load_core_variable("List");
+    parser.call_method(0, "new()");
+
+    loop {
+        // The opening string part.
+        literal(parser, false);
+        parser.call_method(1, "addCore_(_)");
+
+        // The interpolated expression.
+        parser.ignore_newlines();
+        parser.expression();
+        parser.call_method(1, "addCore_(_)");
+
+        parser.ignore_newlines();
+        if !parser.match_token(TokenType::INTERPOLATION) {
+            break;
+        }
+    }
+
+    // The trailing string part.
+    parser.consume(TokenType::STRING, "Expect end of string interpolation.");
+    literal(parser, false);
+    parser.call_method(1, "addCore_(_)");
+
+    // The list of interpolated parts.
+    parser.call_method(0, "join()");
+}
+
+fn infix_op(parser: &mut Parser, _can_assign: bool) {
+    let rule = get_rule(parser.previous.ty);
+    let mut signature = parser.signature_from_token(SignatureType::Method);
+    signature.arity = 1;
+
+    // An infix operator cannot end an expression.
+    parser.ignore_newlines();
+
+    // Compile the right-hand side.
+    parser.parse_precedence(rule.precedence.next());
+
+    // Call the operator method on the left-hand side.
+    parser.call_signature(Code::CALL_0, &signature);
+}
+
+// A membership test like `element in sequence`. This is sugar for calling
+// `sequence.contains(element)`.
+fn in_op(parser: &mut Parser, _can_assign: bool) {
+    let element_start = parser.operand_start;
+
+    // An infix operator cannot end an expression.
+    parser.ignore_newlines();
+
+    // Compile the sequence.
+    let sequence_start = parser.compiler().fn_obj.code.len();
+    parser.parse_precedence(Precedence::Is.next());
+
+    // The element has already been compiled, but the sequence is the receiver so
+    // it must be below the element on the stack. Neither operand's code refers
+    // to absolute bytecode offsets, so swap the two by moving the sequence's
+    // code in front of the element's. This also means the sequence is evaluated
+    // first, just like it would be in the equivalent method call.
+    let fn_obj = &mut parser.compiler().fn_obj;
+    let element_length = sequence_start - element_start;
+    fn_obj.code[element_start..].rotate_left(element_length);
+    fn_obj.debug.source_lines[element_start..].rotate_left(element_length);
+
+    parser.call_method(1, "contains(_)");
+}
+
+// Subscript or "array indexing" operator like `foo[bar]`.
+fn subscript(parser: &mut Parser, can_assign: bool) {
+    let mut signature = Signature {
+        name: String::new(),
+        ty: SignatureType::Subscript,
+        arity: 0,
+    };
+
+    // Parse the argument list.
+    parser.finish_argument_list(&mut signature);
+    parser.consume(TokenType::RIGHT_BRACKET, "Expect ']' after arguments.");
+
+    if can_assign && parser.match_token(TokenType::EQ) {
+        signature.ty = SignatureType::SubscriptSetter;
+        signature.arity += 1;
+        parser.validate_num_parameters(signature.arity);
+
+        // Compile the assigned value.
+        parser.expression();
+    } else if let Some(operator) = parser.match_compound_assignment(can_assign) {
+        // Read the element using copies of the receiver and arguments, so the
+        // originals are still on the stack for the setter.
+        parser.duplicate_top(signature.arity + 1);
+        parser.call_signature(Code::CALL_0, &signature);
+        parser.expression();
+        parser.call_method(1, operator);
+
+        signature.ty = SignatureType::SubscriptSetter;
+        signature.arity += 1;
+        parser.validate_num_parameters(signature.arity);
+    }
+
+    parser.call_signature(Code::CALL_0, &signature);
+}
+
+fn call(parser: &mut Parser, can_assign: bool) {
+    parser.ignore_newlines();
+    parser.consume(TokenType::NAME, "Expect method name after '.'.");
+    parser.named_call(can_assign, Code::CALL_0);
+}
+
+// Calling any other expression as if it were a function, as in `f(1)`, is
+// shorthand for invoking its "call" method with the arguments. Values that
+// aren't functions then fail the method lookup like any other missing method.
+fn call_value(parser: &mut Parser, _can_assign: bool) {
+    let mut signature = Signature {
+        name: "call".to_string(),
+        ty: SignatureType::Method,
+        arity: 0,
+    };
+
+    // Allow new line before an empty argument list
+    parser.ignore_newlines();
+
+    // Allow empty an argument list.
+    if parser.peek() != TokenType::RIGHT_PAREN {
+        parser.finish_argument_list(&mut signature);
+    }
+    parser.consume(TokenType::RIGHT_PAREN, "Expect ')' after arguments.");
+    parser.call_signature(Code::CALL_0, &signature);
+}
+
+// A method call that is skipped when the receiver is null, as in `a?.b`. The
+// null receiver is then the result. The calls and subscripts chained after it
+// are skipped too, so `a?.b.c[0]` is null when `a` is, instead of sending `c`
+// to null.
+fn safe_call(parser: &mut Parser, can_assign: bool) {
+    let jump = parser.emit_jump(Code::JUMP_IF_NULL);
+    call(parser, can_assign);
+
+    // Compile the rest of the chain here, rather than returning to the caller's
+    // loop, so that it falls inside the jump.
+    while get_rule(parser.current.ty).precedence == Precedence::Call {
+        parser.next_token();
+        let infix = get_rule(parser.previous.ty)
+            .infix
+            .expect("Token with a precedence should have an infix rule.");
+        infix(parser, can_assign);
+    }
+
+    parser.patch_jump(jump);
+}
+
+fn field(parser: &mut Parser, can_assign: bool) {
+    // Initialize it with a fake value so we can keep parsing and minimize the
+    // number of cascaded errors.
+    let mut field = MAX_FIELDS as usize;
+
+    let enclosing_class = parser.get_enclosing_class();
+    match enclosing_class {
+        None => {
+            parser.error("Cannot reference a field outside of a class definition.");
+        }
+        Some(class_index) if parser.class_info(class_index).in_static => {
+            parser.error("Cannot reference a field in a static method.");
+        }
+        Some(class_index) => {
+            // Look up the field, or implicitly define it.
+            let token = parser.previous;
+            let name = String::from_utf8_lossy(parser.token_text(&token)).into_owned();
+            field = parser.class_info(class_index).fields.ensure(&name);
+
+            if field >= MAX_FIELDS as usize {
+                parser.error(&format!("A class can only have {} fields.", MAX_FIELDS));
+            }
+        }
+    }
+
+    // Fields are accessed with a more optimal instruction from directly inside
+    // a method.
+    let is_method = match enclosing_class {
+        Some(class_index) => class_index + 2 == parser.compilers.len(),
+        None => false,
+    };
+
+    // If there's an "=" after a field name, it's an assignment.
+    if can_assign && parser.match_token(TokenType::EQ) {
+        // Compile the right-hand side.
+        parser.expression();
+        access_field(parser, field, is_method, false);
+    } else if let Some(operator) = parser.match_compound_assignment(can_assign) {
+        // A compound assignment reads the field, applies the operator to it and
+        // the right-hand side, and stores the result back.
+        access_field(parser, field, is_method, true);
+        parser.expression();
+        parser.call_method(1, operator);
+        access_field(parser, field, is_method, false);
+    } else {
+        access_field(parser, field, is_method, true);
+    }
+}
+
+// Emits the code to load [field] of `this`, or to store the value on top of
+// the stack in it if [is_load] is `false`.
+fn access_field(parser: &mut Parser, field: usize, is_method: bool, is_load: bool) {
+    // If we're directly inside a method, use a more optimal instruction.
+    if is_method {
+        let instruction = if is_load {
+            Code::LOAD_FIELD_THIS
+        } else {
+            Code::STORE_FIELD_THIS
+        };
+        parser.emit_byte_arg(instruction, field);
+    } else {
+        parser.load_this();
+        let instruction = if is_load {
+            Code::LOAD_FIELD
+        } else {
+            Code::STORE_FIELD
+        };
+        parser.emit_byte_arg(instruction, field);
+    }
+}
+
+// `as` is only meaningful in an import clause, which consumes it directly. If
+// it shows up anywhere else, report that instead of a confusing parse error.
+fn unexpected_as(parser: &mut Parser, _can_assign: bool) {
+    parser.error("Unexpected 'as' keyword.");
+
+    // Skip the name that would follow it in an import so that it doesn't cause
+    // more errors.
+    parser.match_token(TokenType::NAME);
+}
+
+fn this(parser: &mut Parser, _can_assign: bool) {
+    if parser.get_enclosing_class().is_none() {
+        parser.error("Cannot use 'this' outside of a method.");
+        return;
+    }
+
+    parser.load_this();
+}
+
+fn name(parser: &mut Parser, can_assign: bool) {
+    // Look for the name in the scope chain up to the nearest enclosing method.
+    let token = parser.previous;
+    let name = String::from_utf8_lossy(parser.token_text(&token)).into_owned();
+
+    let mut variable = parser.resolve_name(&name);
+
+    // If we're inside a method and the name is lowercase and isn't a variable,
+    // treat it as a method on this.
+    if variable.index == -1
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && parser.get_enclosing_class().is_some()
+    {
+        parser.load_this();
+        parser.named_call(can_assign, Code::CALL_0);
+        return;
+    }
+
+    if variable.index == -1 {
+        // Implicitly define a module-level variable in the hopes that we get a
+        // real definition later.
+        variable.index = parser.vm.declare_variable(parser.module, &name, token.line);
+
+        if variable.index == -2 {
+            parser.error("Too many module variables defined.");
+        }
+    }
+
+    parser.bare_name(can_assign, variable);
+}
+
+// Compiles a method signature for an infix operator.
+fn infix_signature(parser: &mut Parser, signature: &mut Signature) {
+    // Add the RHS parameter.
+    signature.ty = SignatureType::Method;
+    signature.arity = 1;
+
+    // Parse the parameter name.
+    parser.consume(TokenType::LEFT_PAREN, "Expect '(' after operator name.");
+    parser.declare_named_variable();
+    parser.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameter name.");
+}
+
+// Compiles a method signature for an unary operator (i.e. "!").
+fn unary_signature(_parser: &mut Parser, signature: &mut Signature) {
+    // Do nothing. The name is already complete.
+    signature.ty = SignatureType::Getter;
+}
+
+// Compiles a method signature for an operator that can either be unary or
+// infix (i.e. "-").
+fn mixed_signature(parser: &mut Parser, signature: &mut Signature) {
+    signature.ty = SignatureType::Getter;
+
+    // If there is a parameter, it's an infix operator, otherwise it's unary.
+    if parser.match_token(TokenType::LEFT_PAREN) {
+        // Add the RHS parameter.
+        signature.ty = SignatureType::Method;
+        signature.arity = 1;
+
+        // Parse the parameter name.
+        parser.declare_named_variable();
+        parser.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameter name.");
+    }
+}
+
+// Compiles a method signature for a subscript operator.
+fn subscript_signature(parser: &mut Parser, signature: &mut Signature) {
+    signature.ty = SignatureType::Subscript;
+
+    // The signature currently has "[" as its name since that was the token that
+    // matched it. Clear that out.
+    signature.name.clear();
+
+    // Parse the parameters inside the subscript.
+    parser.finish_parameter_list(signature);
+    parser.consume(TokenType::RIGHT_BRACKET, "Expect ']' after parameters.");
+
+    parser.maybe_setter(signature);
+}
+
+// Compiles a method signature for a named method or setter.
+fn named_signature(parser: &mut Parser, signature: &mut Signature) {
+    signature.ty = SignatureType::Getter;
+
+    // If it's a setter, it can't also have a parameter list.
+    if parser.maybe_setter(signature) {
+        return;
+    }
+
+    // Regular named method with an optional parameter list.
+    parser.parameter_list(signature);
+}
+
+// Compiles a method signature for a constructor.
+fn constructor_signature(parser: &mut Parser, signature: &mut Signature) {
+    parser.consume(
+        TokenType::NAME,
+        "Expect constructor name after 'construct'.",
+    );
+
+    // Capture the name.
+    *signature = parser.signature_from_token(SignatureType::Initializer);
+
+    if parser.match_token(TokenType::EQ) {
+        parser.error("A constructor cannot be a setter.");
+    }
+
+    if !parser.match_token(TokenType::LEFT_PAREN) {
+        parser.error("A constructor cannot be a getter.");
+        return;
+    }
+
+    // Allow an empty parameter list.
+    if parser.match_token(TokenType::RIGHT_PAREN) {
+        return;
+    }
+
+    parser.finish_parameter_list(signature);
+    parser.consume(TokenType::RIGHT_PAREN, "Expect ')' after parameters.");
+}
+
+const UNUSED: GrammarRule = GrammarRule {
+    prefix: None,
+    infix: None,
+    method: None,
+    precedence: Precedence::None,
+};
+
+const fn infix(precedence: Precedence, infix: GrammarFn) -> GrammarRule {
+    GrammarRule {
+        prefix: None,
+        infix: Some(infix),
+        method: None,
+        precedence,
+    }
+}
+
+const fn prefix(prefix: GrammarFn) -> GrammarRule {
+    GrammarRule {
+        prefix: Some(prefix),
+        infix: None,
+        method: None,
+        precedence: Precedence::None,
+    }
+}
+
+const fn prefix_operator() -> GrammarRule {
+    GrammarRule {
+        prefix: Some(unary_op),
+        infix: None,
+        method: Some(unary_signature),
+        precedence: Precedence::None,
+    }
+}
+
+const fn infix_operator(precedence: Precedence) -> GrammarRule {
+    GrammarRule {
+        prefix: None,
+        infix: Some(infix_op),
+        method: Some(infix_signature),
+        precedence,
+    }
+}
+
+// An operator that can be used both as a prefix and an infix operator.
+const fn operator() -> GrammarRule {
+    GrammarRule {
+        prefix: Some(unary_op),
+        infix: Some(infix_op),
+        method: Some(mixed_signature),
+        precedence: Precedence::Term,
+    }
+}
+
+// Gets the [GrammarRule] associated with tokens of [ty].
+fn get_rule(ty: TokenType) -> &'static GrammarRule {
+    &RULES[ty as usize]
+}
+
+// This table defines all of the parsing rules for the prefix and infix
+// expressions in the grammar. Expressions are parsed using a Pratt parser.
+//
+// See: http://journal.stuffwithstuff.com/2011/03/19/pratt-parsers-expression-parsing-made-easy/
+static RULES: &[GrammarRule] = &[
+    /* LEFT_PAREN    */
+    GrammarRule {
+        prefix: Some(grouping),
+        infix: Some(call_value),
+        method: None,
+        precedence: Precedence::Call,
+    },
+    /* RIGHT_PAREN   */ UNUSED,
+    /* LEFT_BRACKET  */
+    GrammarRule {
+        prefix: Some(list),
+        infix: Some(subscript),
+        method: Some(subscript_signature),
+        precedence: Precedence::Call,
+    },
+    /* RIGHT_BRACKET */ UNUSED,
+    /* LEFT_BRACE    */ prefix(map),
+    /* RIGHT_BRACE   */ UNUSED,
+    /* COLON         */ UNUSED,
+    /* DOT           */ infix(Precedence::Call, call),
+    /* DOTDOT        */ infix_operator(Precedence::Range),
+    /* DOTDOTDOT     */ infix_operator(Precedence::Range),
+    /* COMMA         */ UNUSED,
+    /* STAR          */ infix_operator(Precedence::Factor),
+    /* SLASH         */ infix_operator(Precedence::Factor),
+    /* PERCENT       */ infix_operator(Precedence::Factor),
+    /* HASH          */ UNUSED,
+    /* PLUS          */ infix_operator(Precedence::Term),
+    /* MINUS         */ operator(),
+    /* LTLT          */ infix_operator(Precedence::BitwiseShift),
+    /* GTGT          */ infix_operator(Precedence::BitwiseShift),
+    /* PIPE          */ infix_operator(Precedence::BitwiseOr),
+    /* PIPEPIPE      */ UNUSED,
+    /* CARET         */ infix_operator(Precedence::BitwiseXor),
+    /* AMP           */ infix_operator(Precedence::BitwiseAnd),
+    /* AMPAMP        */ UNUSED,
+    /* BANG          */ prefix_operator(),
+    /* TILDE         */ prefix_operator(),
+    /* QUESTION      */ UNUSED,
+    /* QUESTIONDOT   */ infix(Precedence::Call, safe_call),
+    /* EQ            */ UNUSED,
+    /* LT            */ infix_operator(Precedence::Comparison),
+    /* GT            */ infix_operator(Precedence::Comparison),
+    /* LTEQ          */ infix_operator(Precedence::Comparison),
+    /* GTEQ          */ infix_operator(Precedence::Comparison),
+    /* EQEQ          */ infix_operator(Precedence::Equality),
+    /* BANGEQ        */ infix_operator(Precedence::Equality),
+    /* PLUSEQ        */ UNUSED,
+    /* MINUSEQ       */ UNUSED,
+    /* STAREQ        */ UNUSED,
+    /* SLASHEQ       */ UNUSED,
+    /* BREAK         */ UNUSED,
+    /* CONTINUE      */ UNUSED,
+    /* CLASS         */ UNUSED,
+    /* CONSTRUCT     */
+    GrammarRule {
+        prefix: None,
+        infix: None,
+        method: Some(constructor_signature),
+        precedence: Precedence::None,
+    },
+    /* ELSE          */ UNUSED,
+    /* FALSE         */ prefix(boolean),
+    /* FOR           */ UNUSED,
+    /* FOREIGN       */ UNUSED,
+    /* IF            */ UNUSED,
+    /* IMPORT        */ UNUSED,
+    /* AS            */
+    GrammarRule {
+        prefix: Some(unexpected_as),
+        infix: Some(unexpected_as),
+        method: None,
+        precedence: Precedence::Call,
+    },
+    /* IN            */ infix(Precedence::Is, in_op),
+    /* IS            */ infix_operator(Precedence::Is),
+    /* NULL          */ prefix(null),
+    /* RETURN        */ UNUSED,
+    /* STATIC        */ UNUSED,
+    /* SUPER         */ UNUSED,
+    /* THIS          */ prefix(this),
+    /* TRUE          */ prefix(boolean),
+    /* VAR           */ UNUSED,
+    /* WHILE         */ UNUSED,
+    /* FIELD         */ prefix(field),
+    /* STATIC_FIELD  */ UNUSED,
+    /* NAME          */
+    GrammarRule {
+        prefix: Some(name),
+        infix: None,
+        method: Some(named_signature),
+        precedence: Precedence::None,
+    },
+    /* NUMBER        */ prefix(literal),
+    /* STRING        */ prefix(literal),
+    /* INTERPOLATION */ prefix(string_interpolation),
+    /* LINE          */ UNUSED,
+    /* ERROR         */ UNUSED,
+    /* EOF           */ UNUSED,
+];
+
+// Removes the longest run of leading spaces and tabs that every line in [text]
+// starts with. Lines that are empty or only whitespace don't count towards it,
+// and lose as much of it as they have. Spaces and tabs are compared as they
+// are, so a line indented with a tab and one indented with spaces share no
+// indentation.
+fn strip_common_indentation(text: &[u8]) -> Vec<u8> {
+    let is_indent = |c: &u8| *c == b' ' || *c == b'\t';
+    let lines: Vec<&[u8]> = text.split(|&c| c == b'\n').collect();
+
+    let mut common: Option<&[u8]> = None;
+    for line in lines.iter().filter(|line| !line.iter().all(is_indent)) {
+        let indent = &line[..line.iter().take_while(|c| is_indent(c)).count()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                let shared = common
+                    .iter()
+                    .zip(indent)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or(&[]);
+
+    let mut result = Vec::with_capacity(text.len());
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            result.push(b'\n');
+        }
+
+        let shared = common
+            .iter()
+            .zip(line.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        result.extend_from_slice(&line[shared..]);
+    }
+    result
+}
+
+// Returns the number of bytes for the arguments to the instruction
+// at [ip] in [fn_obj]'s bytecode.
+pub(crate) fn get_byte_count_for_arguments(heap: &Heap, fn_obj: &ObjFn, ip: usize) -> usize {
+    let instruction = Code::from_byte(fn_obj.code[ip]);
+    match instruction {
+        Code::NULL
+        | Code::FALSE
+        | Code::TRUE
+        | Code::POP
+        | Code::CLOSE_UPVALUE
+        | Code::RETURN
+        | Code::END
+        | Code::LOAD_LOCAL_0
+        | Code::LOAD_LOCAL_1
+        | Code::LOAD_LOCAL_2
+        | Code::LOAD_LOCAL_3
+        | Code::LOAD_LOCAL_4
+        | Code::LOAD_LOCAL_5
+        | Code::LOAD_LOCAL_6
+        | Code::LOAD_LOCAL_7
+        | Code::LOAD_LOCAL_8
+        | Code::CONSTRUCT
+        | Code::FOREIGN_CONSTRUCT
+        | Code::FOREIGN_CLASS
+        | Code::END_MODULE
+        | Code::END_CLASS => 0,
+
+        Code::LOAD_LOCAL
+        | Code::STORE_LOCAL
+        | Code::LOAD_UPVALUE
+        | Code::STORE_UPVALUE
+        | Code::LOAD_FIELD_THIS
+        | Code::STORE_FIELD_THIS
+        | Code::LOAD_FIELD
+        | Code::STORE_FIELD
+        | Code::CLASS => 1,
+
+        Code::CONSTANT
+        | Code::LOAD_MODULE_VAR
+        | Code::STORE_MODULE_VAR
+        | Code::CALL_0
+        | Code::CALL_1
+        | Code::CALL_2
+        | Code::CALL_3
+        | Code::CALL_4
+        | Code::CALL_5
+        | Code::CALL_6
+        | Code::CALL_7
+        | Code::CALL_8
+        | Code::CALL_9
+        | Code::CALL_10
+        | Code::CALL_11
+        | Code::CALL_12
+        | Code::CALL_13
+        | Code::CALL_14
+        | Code::CALL_15
+        | Code::CALL_16
+        | Code::JUMP
+        | Code::LOOP
+        | Code::JUMP_IF
+        | Code::JUMP_IF_NULL
+        | Code::AND
+        | Code::OR
+        | Code::METHOD_INSTANCE
+        | Code::METHOD_STATIC
+        | Code::IMPORT_MODULE
+        | Code::IMPORT_VARIABLE => 2,
+
+        Code::SUPER_0
+        | Code::SUPER_1
+        | Code::SUPER_2
+        | Code::SUPER_3
+        | Code::SUPER_4
+        | Code::SUPER_5
+        | Code::SUPER_6
+        | Code::SUPER_7
+        | Code::SUPER_8
+        | Code::SUPER_9
+        | Code::SUPER_10
+        | Code::SUPER_11
+        | Code::SUPER_12
+        | Code::SUPER_13
+        | Code::SUPER_14
+        | Code::SUPER_15
+        | Code::SUPER_16 => 4,
+
+        Code::CLOSURE => {
+            let constant = ((fn_obj.code[ip + 1] as usize) << 8) | fn_obj.code[ip + 2] as usize;
+            let loaded_fn = heap.as_fn(fn_obj.constants[constant].as_obj());
+
+            // There are two bytes for the constant, then two for each upvalue.
+            2 + (loaded_fn.num_upvalues * 2)
+        }
+    }
+}
+
+// Once a method has been bound to [class_obj], this walks its bytecode and
+// updates the field indices to take the number of inherited fields into
+// account.
+pub(crate) fn wren_bind_method_code(vm: &mut WrenVM, class_obj: ObjRef, fn_obj: ObjRef) {
+    let superclass = vm
+        .heap
+        .as_class(class_obj)
+        .superclass
+        .expect("Class should have a superclass.");
+    let super_fields = vm.heap.as_class(superclass).num_fields as u8;
+
+    let mut nested = Vec::new();
+    let mut ip = 0;
+    loop {
+        let function = vm.heap.as_fn(fn_obj);
+        let instruction = Code::from_byte(function.code[ip]);
+        match instruction {
+            Code::LOAD_FIELD
+            | Code::STORE_FIELD
+            | Code::LOAD_FIELD_THIS
+            | Code::STORE_FIELD_THIS => {
+                // Shift this class's fields down past the inherited ones. We don't
+                // check for overflow here because we'll see if the number of fields
+                // overflows when the subclass is created.
+                let code = &mut vm.heap.as_fn_mut(fn_obj).code;
+                code[ip + 1] = code[ip + 1].wrapping_add(super_fields);
+            }
+
+            Code::CLOSURE => {
+                // Bind the nested closure too.
+                let constant =
+                    ((function.code[ip + 1] as usize) << 8) | function.code[ip + 2] as usize;
+                nested.push(function.constants[constant].as_obj());
+            }
+
+            Code::END => break,
+
+            // Other instructions are unaffected, so just skip over them.
+            _ => {}
+        }
+        ip += 1 + get_byte_count_for_arguments(&vm.heap, vm.heap.as_fn(fn_obj), ip);
+    }
+
+    for fn_obj in nested {
+        wren_bind_method_code(vm, class_obj, fn_obj);
+    }
+}
+
+// Compiles [source], a string of Wren source code located in [module], to an
+// [ObjFn] that will execute that code when invoked. Returns `None` if the
+// source contains any syntax errors.
+//
+// If [is_expression] is `true`, [source] should be a single expression, and
+// this compiles it to a function that evaluates and returns that expression.
+// Otherwise, [source] should be a series of top level statements.
+//
+// If [print_errors] is `true`, any compile errors are output to stderr.
+// Otherwise, they are silently discarded.
+pub(crate) fn wren_compile(
+    vm: &mut WrenVM,
+    module: ObjRef,
+    source: &[u8],
+    is_expression: bool,
+    print_errors: bool,
+) -> Option<ObjRef> {
+    // Remember how many variables the module already has, so that only the ones
+    // implicitly declared by this source are checked for a definition at the end.
+    let num_existing_variables = vm.heap.as_module(module).variables.len();
+
+    let mut parser = Parser::new(vm, module, source, print_errors);
+
+    // Refuse oversized source before lexing any of it.
+    let max_length = parser.vm.config.max_source_length;
+    if max_length.is_some_and(|max_length| source.len() > max_length) {
+        parser.print_error(1, None, "Error", "Source too large.");
+        return None;
+    }
+
+    // Read the first token into next
+    parser.next_token();
+    // Copy next -> current
+    parser.next_token();
+
+    parser.init_compiler(false);
+
+    parser.ignore_newlines();
+
+    if is_expression {
+        parser.expression();
+        parser.consume(TokenType::EOF, "Expect end of expression.");
+    } else {
+        let mut reachability = Reachability::Reachable;
+        while !parser.match_token(TokenType::EOF) {
+            reachability = parser.check_reachable(reachability);
+            parser.definition();
+
+            // If there is no newline, it must be the end of file on the same line.
+            if !parser.panic_mode && !parser.match_line() {
+                parser.consume(TokenType::EOF, "Expect end of file.");
+                if !parser.panic_mode {
+                    break;
+                }
+            }
+
+            // After a syntax error, skip ahead to the next statement and carry on
+            // so that the errors in it get reported too.
+            if parser.panic_mode {
+                parser.synchronize();
+            }
+        }
+
+        parser.emit_op(Code::END_MODULE);
+    }
+
+    parser.emit_op(Code::RETURN);
+
+    // See if there are any implicitly declared module-level variables that never
+    // got an explicit definition. They will have values that are numbers
+    // indicating the line where the variable was first used.
+    let num_variables = parser.vm.heap.as_module(module).variables.len();
+    for i in num_existing_variables..num_variables {
+        let module = parser.vm.heap.as_module(module);
+        if let Value::Num(line) = module.variables[i] {
+            let label = error_label("Error", module.variable_names.name(i).as_bytes());
+            parser.print_error(
+                line as i32,
+                None,
+                &label,
+                "Variable is used but not defined.",
+            );
+        }
+    }
+
+    parser.end_compiler("(script)")
+}
+
+// Lexes [source] without compiling it, returning every token up to the end of
+// the file. If [collect_comments] is `true`, the `//` comments documenting each
+// declaration are returned as well.
+pub(crate) fn wren_tokenize(vm: &mut WrenVM, source: &[u8], collect_comments: bool) -> Tokens {
+    // Strings are still lexed to values, so they need a module to live in even
+    // though nothing is compiled into it.
+    let module = wren_new_module(vm, None);
+
+    let mut parser = Parser::new(vm, module, source, true);
+    parser.collect_comments = collect_comments;
+    parser.next_token();
+
+    let mut tokens = Vec::new();
+    while parser.next.ty != TokenType::EOF {
+        let token = parser.next;
+        let text = &parser.source[token.start..token.start + token.length];
+        tokens.push((token.line, String::from_utf8_lossy(text).into_owned()));
+        parser.next_token();
+    }
+
+    Tokens {
+        tokens,
+        comments: parser.comments,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Config;
+
+    #[test]
+    fn fresh_parser_reads_its_first_token() {
+        let mut vm = WrenVM::new(Config::default());
+        let module = wren_new_module(&mut vm, None);
+
+        let mut parser = Parser::new(&mut vm, module, b"var a", false);
+        parser.next_token();
+        assert_eq!(parser.next.ty, TokenType::VAR);
+        parser.next_token();
+        assert_eq!(parser.current.ty, TokenType::VAR);
+        assert_eq!(parser.next.ty, TokenType::NAME);
+
+        let mut parser = Parser::new(&mut vm, module, b"", false);
+        parser.next_token();
+        assert_eq!(parser.next.ty, TokenType::EOF);
+    }
+
+    // The instructions in [fn_obj]'s bytecode, without their arguments.
+    fn instructions(heap: &Heap, fn_obj: &ObjFn) -> Vec<Code> {
+        let mut instructions = Vec::new();
+        let mut ip = 0;
+        while ip < fn_obj.code.len() {
+            instructions.push(Code::from_byte(fn_obj.code[ip]));
+            ip += 1 + get_byte_count_for_arguments(heap, fn_obj, ip);
+        }
+        instructions
+    }
+
+    #[test]
+    fn literal_true_and_null_use_dedicated_instructions() {
+        let mut vm = WrenVM::new(Config::default());
+        let module = wren_new_module(&mut vm, None);
+
+        let fn_obj = wren_compile(
+            &mut vm,
+            module,
+            b"var a = true\nvar b = null\n",
+            false,
+            false,
+        )
+        .expect("Source should compile.");
+        let fn_obj = vm.heap.as_fn(fn_obj);
+        let code = instructions(&vm.heap, fn_obj);
+        assert!(code.contains(&Code::TRUE));
+        assert!(code.contains(&Code::NULL));
+        assert!(!code.contains(&Code::CONSTANT));
+        assert!(fn_obj.constants.is_empty());
+    }
+
+    // Compiles [source] and returns every error it reports. It must not panic.
+    fn compile_errors(source: &[u8]) -> Vec<String> {
+        let errors = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let reported = errors.clone();
+        let mut vm = WrenVM::new(Config {
+            error_fn: Some(Box::new(
+                move |_vm: &mut WrenVM,
+                      _ty: ErrorType,
+                      _module: &str,
+                      _line: i32,
+                      message: &str| {
+                    reported.borrow_mut().push(message.to_string())
+                },
+            )),
+            ..Config::default()
+        });
+        let _ = vm.compile_source(Some("main"), source, false, true);
+        let errors = errors.borrow().clone();
+        errors
+    }
+
+    #[test]
+    fn pathological_input_reports_errors_without_panicking() {
+        let long_name = "a".repeat(2000);
+        let inputs: Vec<Vec<u8>> = vec![
+            // Malformed UTF-8, alone, in a name and in a string.
+            b"\xff\xfe".to_vec(),
+            b"var a\xc3 = 1".to_vec(),
+            b"var s = \"\xe2\x82\" + \"\xff\"\nvar".to_vec(),
+            // Unterminated constructs.
+            b"\"abc".to_vec(),
+            b"\"\"\"raw".to_vec(),
+            b"/* open /* nested */".to_vec(),
+            b"\"%(1 + ".to_vec(),
+            b"\"\\u{12".to_vec(),
+            b"\"\\x".to_vec(),
+            b"class A {\n  foo(".to_vec(),
+            b"var f = Fn.new {|a, ".to_vec(),
+            b"0x".to_vec(),
+            // Huge nesting.
+            "(".repeat(10_000).into_bytes(),
+            "[".repeat(10_000).into_bytes(),
+            "{".repeat(10_000).into_bytes(),
+            "-".repeat(10_000).into_bytes(),
+            "Fn.new { ".repeat(2_000).into_bytes(),
+            // Very long names in error messages.
+            format!("var x = {} +", long_name).into_bytes(),
+            format!("var f = Fn.new {{ {} }}", long_name).into_bytes(),
+            format!("var f = Fn.new {{ B{} }}", "b".repeat(63)).into_bytes(),
+            format!(
+                "{}System.print(b{})\nvar b{} = \"{}\"",
+                "\n".repeat(100_000),
+                "b".repeat(62),
+                "b".repeat(62),
+                long_name
+            )
+            .into_bytes(),
+            format!(
+                "class A {{\n  {}() {{}}\n  {}() {{}}\n}}",
+                long_name, long_name
+            )
+            .into_bytes(),
+        ];
+
+        for input in inputs {
+            let errors = compile_errors(&input);
+            assert!(
+                !errors.is_empty(),
+                "{:?} should not compile.",
+                String::from_utf8_lossy(&input[..input.len().min(40)])
+            );
+        }
+    }
 }
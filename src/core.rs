@@ -1 +1,2097 @@
+use crate::common::{MAX_PARAMETERS, MAX_REPEATED_STRING};
+use crate::compile::{wren_parse_number, MAX_NESTING};
+use crate::utils::utf8_decode_num_bytes;
+use crate::value::*;
+use crate::vm::{InterpretResult, WrenVM};
 
+// Binds a primitive method named [name] (in Wren) implemented using Rust
+// function [function] to `ObjClass` [class_obj].
+fn primitive(vm: &mut WrenVM, class_obj: ObjRef, name: &str, function: Primitive) {
+    let symbol = vm.method_names.ensure(name);
+    wren_bind_method(
+        vm.heap.as_class_mut(class_obj),
+        symbol,
+        Method::Primitive(function),
+    );
+}
+
+// Returns [value] from the primitive currently executing.
+macro_rules! return_val {
+    ($args:ident, $value:expr) => {{
+        $args[0] = $value;
+        return true;
+    }};
+}
+
+// Aborts the current fiber with [message] and returns from the primitive.
+macro_rules! return_error {
+    ($vm:ident, $message:expr) => {{
+        let error = wren_new_string($vm, $message.as_bytes());
+        $vm.set_error(error);
+        return false;
+    }};
+}
+
+// Validates that [arg] is a function. Returns `false` and sets the fiber's
+// error if not.
+fn validate_fn(vm: &mut WrenVM, arg: Value, arg_name: &str) -> bool {
+    if vm.heap.is_closure(arg) {
+        return true;
+    }
+    return_error!(vm, format!("{} must be a function.", arg_name));
+}
+
+// Validates that [arg] is a valid object for use as a map key. Returns `false`
+// and sets the fiber's error if not.
+fn validate_key(vm: &mut WrenVM, arg: Value) -> bool {
+    if wren_map_is_valid_key(vm, arg) {
+        return true;
+    }
+    return_error!(vm, "Key must be a value type.");
+}
+
+// Validates that [arg] is a num. Returns `false` and sets the fiber's error if
+// not.
+fn validate_num(vm: &mut WrenVM, arg: Value, arg_name: &str) -> bool {
+    if let Value::Num(_) = arg {
+        return true;
+    }
+    return_error!(vm, format!("{} must be a number.", arg_name));
+}
+
+// Validates that [arg] is a string. Returns `false` and sets the fiber's error
+// if not.
+fn validate_string(vm: &mut WrenVM, arg: Value, arg_name: &str) -> bool {
+    if vm.heap.is_string(arg) {
+        return true;
+    }
+    return_error!(vm, format!("{} must be a string.", arg_name));
+}
+
+// Validates that [value] is an integer. Returns `false` and sets the fiber's
+// error if not.
+fn validate_int_value(vm: &mut WrenVM, value: f64, arg_name: &str) -> bool {
+    if value.trunc() == value {
+        return true;
+    }
+    return_error!(vm, format!("{} must be an integer.", arg_name));
+}
+
+// Validates that [arg] is an integer. Returns `false` and sets the fiber's
+// error if not.
+fn validate_int(vm: &mut WrenVM, arg: Value, arg_name: &str) -> bool {
+    if !validate_num(vm, arg, arg_name) {
+        return false;
+    }
+    validate_int_value(vm, as_num(arg), arg_name)
+}
+
+// Validates that [arg] is an integer that fits in a byte. Returns the byte if
+// so. If invalid, returns `None` and sets the fiber's error.
+fn validate_byte(vm: &mut WrenVM, arg: Value) -> Option<u8> {
+    if !validate_int(vm, arg, "Byte") {
+        return None;
+    }
+
+    let value = as_num(arg);
+    if (0.0..=255.0).contains(&value) {
+        return Some(value as u8);
+    }
+
+    let error = wren_new_string(vm, b"Byte must be between 0 and 255.");
+    vm.set_error(error);
+    None
+}
+
+// Validates that [value] is an integer within `[0, count)`. Also allows
+// negative indices which map backwards from the end. Returns the valid positive
+// index value. If invalid, returns `None` and sets the fiber's error.
+fn validate_index_value(
+    vm: &mut WrenVM,
+    count: usize,
+    value: f64,
+    arg_name: &str,
+) -> Option<usize> {
+    if !validate_int_value(vm, value, arg_name) {
+        return None;
+    }
+
+    // Negative indices count from the end.
+    let value = if value < 0.0 {
+        count as f64 + value
+    } else {
+        value
+    };
+
+    // Check bounds.
+    if value >= 0.0 && value < count as f64 {
+        return Some(value as usize);
+    }
+
+    let error = wren_new_string(vm, format!("{} out of bounds.", arg_name).as_bytes());
+    vm.set_error(error);
+    None
+}
+
+// Validates that the argument at [arg] is an integer within `[0, count)`.
+// Also allows negative indices which map backwards from the end. Returns the
+// valid positive index value. If invalid, returns `None` and sets the fiber's
+// error.
+fn validate_index(vm: &mut WrenVM, arg: Value, count: usize, arg_name: &str) -> Option<usize> {
+    if !validate_num(vm, arg, arg_name) {
+        return None;
+    }
+    validate_index_value(vm, count, as_num(arg), arg_name)
+}
+
+// Given a [range] and the [length] of the object being operated on, determines
+// the series of elements that should be chosen from the underlying object.
+// Handles ranges that count backwards from the end as well as negative ranges.
+//
+// Returns the index from which the range should start, the number of elements
+// in the resulting sequence, and the direction that the range is going: `1` if
+// the range is increasing from the start index or `-1` if the range is
+// decreasing. If the range is out of bounds, returns `None` and sets the
+// fiber's error.
+fn calculate_range(vm: &mut WrenVM, range: ObjRef, length: usize) -> Option<(usize, usize, isize)> {
+    let (range_from, range_to, is_inclusive) = {
+        let range = vm.heap.as_range(range);
+        (range.from, range.to, range.is_inclusive)
+    };
+
+    // Edge case: an empty range is allowed at the end of a sequence. This way,
+    // list[0..-1] and list[0...list.count] can be used to copy a list even when
+    // empty.
+    let empty_end = if is_inclusive { -1.0 } else { length as f64 };
+    if range_from == length as f64 && range_to == empty_end {
+        return Some((0, 0, 0));
+    }
+
+    let from = validate_index_value(vm, length, range_from, "Range start")?;
+
+    // Bounds check the end manually to handle exclusive ranges.
+    let mut value = range_to;
+    if !validate_int_value(vm, value, "Range end") {
+        return None;
+    }
+
+    // Negative indices count from the end.
+    if value < 0.0 {
+        value += length as f64;
+    }
+
+    // Convert the exclusive range to an inclusive one.
+    if !is_inclusive {
+        // An exclusive range with the same start and end points is empty.
+        if value == from as f64 {
+            return Some((from, 0, 0));
+        }
+
+        // Shift the endpoint to make it inclusive, handling both increasing and
+        // decreasing ranges.
+        value += if value >= from as f64 { -1.0 } else { 1.0 };
+    }
+
+    // Check bounds.
+    if value < 0.0 || value >= length as f64 {
+        let error = wren_new_string(vm, b"Range end out of bounds.");
+        vm.set_error(error);
+        return None;
+    }
+
+    let to = value as usize;
+    let count = from.abs_diff(to) + 1;
+    let step = if from < to { 1 } else { -1 };
+    Some((from, count, step))
+}
+
+// Returns the number stored in [value], which must be a num.
+fn as_num(value: Value) -> f64 {
+    match value {
+        Value::Num(num) => num,
+        _ => unreachable!("Value is not a number."),
+    }
+}
+
+fn bool_not(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(matches!(args[0], Value::Bool(false))));
+}
+
+fn bool_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if matches!(args[0], Value::Bool(true)) {
+        return_val!(args, wren_new_string(vm, b"true"));
+    } else {
+        return_val!(args, wren_new_string(vm, b"false"));
+    }
+}
+
+fn bytes_new(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(wren_new_bytes(vm, &[])));
+}
+
+fn bytes_from_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_string(vm, args[1], "Argument") {
+        return false;
+    }
+
+    let string = vm.heap.as_string(args[1].as_obj()).value.clone();
+    return_val!(args, Value::Obj(wren_new_bytes(vm, &string)));
+}
+
+fn bytes_add(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let byte = match validate_byte(vm, args[1]) {
+        Some(byte) => byte,
+        None => return false,
+    };
+
+    vm.heap.as_bytes_mut(args[0].as_obj()).value.push(byte);
+    return_val!(args, args[1]);
+}
+
+fn bytes_count(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let count = vm.heap.as_bytes(args[0].as_obj()).value.len();
+    return_val!(args, Value::Num(count as f64));
+}
+
+fn bytes_subscript(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let bytes = args[0].as_obj();
+    let count = vm.heap.as_bytes(bytes).value.len();
+    let index = match validate_index(vm, args[1], count, "Subscript") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    return_val!(
+        args,
+        Value::Num(vm.heap.as_bytes(bytes).value[index] as f64)
+    );
+}
+
+fn bytes_subscript_setter(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let bytes = args[0].as_obj();
+    let count = vm.heap.as_bytes(bytes).value.len();
+    let index = match validate_index(vm, args[1], count, "Subscript") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    let byte = match validate_byte(vm, args[2]) {
+        Some(byte) => byte,
+        None => return false,
+    };
+
+    vm.heap.as_bytes_mut(bytes).value[index] = byte;
+    return_val!(args, args[2]);
+}
+
+fn bytes_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let bytes = vm.heap.as_bytes(args[0].as_obj()).value.clone();
+    return_val!(args, wren_new_string(vm, &bytes));
+}
+
+fn class_name(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(vm.heap.as_class(args[0].as_obj()).name));
+}
+
+fn class_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(vm.heap.as_class(args[0].as_obj()).name));
+}
+
+fn class_supertype(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    // Object has no superclass.
+    match vm.heap.as_class(args[0].as_obj()).superclass {
+        Some(superclass) => return_val!(args, Value::Obj(superclass)),
+        None => return_val!(args, Value::Null),
+    }
+}
+
+fn fiber_abort(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.set_error(args[1]);
+
+    // If the error is explicitly null, it's not really an abort.
+    matches!(args[1], Value::Null)
+}
+
+fn fiber_new(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_fn(vm, args[1], "Argument") {
+        return false;
+    }
+
+    let closure = args[1].as_obj();
+    let fn_obj = vm.heap.as_closure(closure).fn_obj;
+    if vm.heap.as_fn(fn_obj).arity > 1 {
+        return_error!(vm, "Function cannot take more than one parameter.");
+    }
+
+    let fiber = wren_new_fiber(vm, closure);
+    return_val!(args, Value::Obj(fiber));
+}
+
+fn fiber_call(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    run_fiber(vm, args[0].as_obj(), args, true, false, "call")
+}
+
+fn fiber_call1(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    run_fiber(vm, args[0].as_obj(), args, true, true, "call")
+}
+
+fn fiber_try(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    run_fiber(vm, args[0].as_obj(), args, true, false, "try");
+
+    // If we're switching to a valid fiber to try, remember that we're trying it.
+    if !vm.has_error() {
+        let fiber = vm.fiber.expect("Should have a fiber.");
+        vm.heap.as_fiber_mut(fiber).state = FiberState::Try;
+    }
+    false
+}
+
+fn fiber_try1(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    run_fiber(vm, args[0].as_obj(), args, true, true, "try");
+
+    // If we're switching to a valid fiber to try, remember that we're trying it.
+    if !vm.has_error() {
+        let fiber = vm.fiber.expect("Should have a fiber.");
+        vm.heap.as_fiber_mut(fiber).state = FiberState::Try;
+    }
+    false
+}
+
+fn fiber_is_done(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let fiber = vm.heap.as_fiber(args[0].as_obj());
+    let is_done = fiber.frames.is_empty() || !matches!(fiber.error, Value::Null);
+    return_val!(args, Value::Bool(is_done));
+}
+
+fn fiber_suspend(vm: &mut WrenVM, _args: &mut [Value]) -> bool {
+    if vm.suspended.is_some() {
+        return_error!(vm, "Another fiber is already suspended.");
+    }
+
+    // The suspend call returns null when the host resumes the fiber.
+    let current = vm.fiber.expect("Should have a fiber.");
+    *vm.heap.as_fiber_mut(current).stack.last_mut().unwrap() = Value::Null;
+
+    // Switching to no fiber tells the interpreter to stop and exit.
+    vm.suspended = Some(current);
+    vm.fiber = None;
+    false
+}
+
+fn fiber_yield(vm: &mut WrenVM, _args: &mut [Value]) -> bool {
+    yield_fiber(vm, Value::Null, false)
+}
+
+fn fiber_yield1(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    yield_fiber(vm, args[1], true)
+}
+
+fn fiber_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, wren_new_string(vm, b"<fiber>"));
+}
+
+fn fn_new(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_fn(vm, args[1], "Argument") {
+        return false;
+    }
+
+    // The block argument is already a function, so just return it.
+    return_val!(args, args[1]);
+}
+
+fn fn_arity(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let fn_obj = vm.heap.as_closure(args[0].as_obj()).fn_obj;
+    return_val!(args, Value::Num(vm.heap.as_fn(fn_obj).arity as f64));
+}
+
+fn fn_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, wren_new_string(vm, b"<fn>"));
+}
+
+// Calls the function in `args[0]` with the rest of [args] as its arguments by
+// pushing a call frame for it. Always returns `false` so that the interpreter
+// picks up the new frame.
+fn fn_call(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let closure = args[0].as_obj();
+    let arity = vm.heap.as_fn(vm.heap.as_closure(closure).fn_obj).arity as usize;
+
+    // We only care about missing arguments, not extras. The "- 1" is because
+    // [args] includes the receiver, the function itself, which we don't want to
+    // count.
+    let num_args = args.len() - 1;
+    if num_args < arity {
+        return_error!(vm, "Function expects more arguments.");
+    }
+
+    // Discard any extra arguments so that the function's locals end up in the
+    // slots the compiler gave them.
+    let fiber = vm.fiber.expect("Should have a fiber.");
+    let stack = &mut vm.heap.as_fiber_mut(fiber).stack;
+    let stack_start = stack.len() - args.len();
+    stack.truncate(stack_start + arity + 1);
+
+    vm.push_call_frame(fiber, closure, stack_start);
+    false
+}
+
+// Transfers execution to [fiber] from the current one. If [has_value] is
+// `true`, `args[1]` is passed to it. [verb] is what the running is called in
+// error messages.
+fn run_fiber(
+    vm: &mut WrenVM,
+    fiber: ObjRef,
+    args: &mut [Value],
+    is_call: bool,
+    has_value: bool,
+    verb: &str,
+) -> bool {
+    let fiber_obj = vm.heap.as_fiber(fiber);
+    if !matches!(fiber_obj.error, Value::Null) {
+        return_error!(vm, format!("Cannot {} an aborted fiber.", verb));
+    }
+
+    if fiber_obj.frames.is_empty() {
+        return_error!(vm, format!("Cannot {} a finished fiber.", verb));
+    }
+
+    if is_call {
+        // A fiber that is already running, or waiting on a fiber it called,
+        // can't be called again until it yields.
+        if fiber_obj.caller.is_some() || vm.fiber == Some(fiber) {
+            return_error!(vm, "Fiber has already been called.");
+        }
+
+        // Remember who ran it.
+        vm.heap.as_fiber_mut(fiber).caller = vm.fiber;
+    }
+
+    // When the calling fiber resumes, we'll store the result of the call in its
+    // stack. If the call has two arguments (the fiber and the value), we only
+    // need one slot for the result, so discard the other slot now.
+    let current = vm.fiber.expect("Should have a fiber.");
+    if has_value {
+        vm.heap.as_fiber_mut(current).stack.pop();
+    }
+
+    let value = if has_value { args[1] } else { Value::Null };
+    vm.send_to_fiber(fiber, value);
+    vm.fiber = Some(fiber);
+    false
+}
+
+// Suspends the current fiber and returns control to the fiber that called it,
+// making that fiber's call return [value]. If nothing called it, control goes
+// back to the host.
+//
+// [has_value] is `true` for `Fiber.yield(_)`, which has an extra stack slot to
+// discard.
+fn yield_fiber(vm: &mut WrenVM, value: Value, has_value: bool) -> bool {
+    let current = vm.fiber.expect("Should have a fiber.");
+
+    // When the yielding fiber resumes, we'll store the result of the yield call
+    // in its stack. Until then, that slot holds the yielded value so the host
+    // can read it.
+    let current_obj = vm.heap.as_fiber_mut(current);
+    if has_value {
+        current_obj.stack.pop();
+    }
+    *current_obj.stack.last_mut().unwrap() = value;
+
+    let caller = current_obj.caller.take();
+    vm.fiber = caller;
+
+    if let Some(caller) = caller {
+        // Make the caller's run method return the yielded value.
+        *vm.heap.as_fiber_mut(caller).stack.last_mut().unwrap() = value;
+    }
+
+    false
+}
+
+fn list_new(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(wren_new_list(vm, 0)));
+}
+
+fn list_add(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.heap.as_list_mut(args[0].as_obj()).elements.push(args[1]);
+    return_val!(args, args[1]);
+}
+
+// Adds an element to the list and then returns the list itself. This is called
+// by the compiler when compiling list literals instead of using add() to
+// minimize stack churn.
+fn list_add_core(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.heap.as_list_mut(args[0].as_obj()).elements.push(args[1]);
+
+    // Return the list.
+    return_val!(args, args[0]);
+}
+
+fn list_clear(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.heap.as_list_mut(args[0].as_obj()).elements.clear();
+    return_val!(args, Value::Null);
+}
+
+fn list_subscript(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let count = vm.heap.as_list(list).elements.len();
+
+    if let Value::Num(_) = args[1] {
+        let index = match validate_index(vm, args[1], count, "Subscript") {
+            Some(index) => index,
+            None => return false,
+        };
+
+        return_val!(args, vm.heap.as_list(list).elements[index]);
+    }
+
+    if !vm.heap.is_range(args[1]) {
+        return_error!(vm, "Subscript must be a number or a range.");
+    }
+
+    let (start, count, step) = match calculate_range(vm, args[1].as_obj(), count) {
+        Some(range) => range,
+        None => return false,
+    };
+
+    let elements = &vm.heap.as_list(list).elements;
+    let elements: Vec<Value> = (0..count)
+        .map(|i| elements[(start as isize + i as isize * step) as usize])
+        .collect();
+    let result = wren_new_list(vm, 0);
+    vm.heap.as_list_mut(result).elements = elements;
+    return_val!(args, Value::Obj(result));
+}
+
+fn list_insert(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+
+    // count + 1 here so you can "insert" at the very end.
+    let count = vm.heap.as_list(list).elements.len();
+    let index = match validate_index(vm, args[1], count + 1, "Index") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    vm.heap.as_list_mut(list).elements.insert(index, args[2]);
+    return_val!(args, args[2]);
+}
+
+fn list_remove_at(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let count = vm.heap.as_list(list).elements.len();
+    let index = match validate_index(vm, args[1], count, "Index") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    return_val!(args, vm.heap.as_list_mut(list).elements.remove(index));
+}
+
+fn list_remove_value(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let index = vm
+        .heap
+        .as_list(list)
+        .elements
+        .iter()
+        .position(|&element| wren_values_equal(vm, element, args[1]));
+
+    match index {
+        Some(index) => return_val!(args, vm.heap.as_list_mut(list).elements.remove(index)),
+        None => return_val!(args, Value::Null),
+    }
+}
+
+fn list_swap(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let count = vm.heap.as_list(list).elements.len();
+    let index_a = match validate_index(vm, args[1], count, "Index 0") {
+        Some(index) => index,
+        None => return false,
+    };
+    let index_b = match validate_index(vm, args[2], count, "Index 1") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    vm.heap.as_list_mut(list).elements.swap(index_a, index_b);
+    return_val!(args, Value::Null);
+}
+
+fn list_reverse(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.heap.as_list_mut(args[0].as_obj()).elements.reverse();
+    return_val!(args, args[0]);
+}
+
+fn list_reversed(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let elements: Vec<Value> = vm
+        .heap
+        .as_list(args[0].as_obj())
+        .elements
+        .iter()
+        .rev()
+        .copied()
+        .collect();
+    let reversed = wren_new_list(vm, 0);
+    vm.heap.as_list_mut(reversed).elements = elements;
+    return_val!(args, Value::Obj(reversed));
+}
+
+fn list_subscript_setter(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let count = vm.heap.as_list(list).elements.len();
+    let index = match validate_index(vm, args[1], count, "Subscript") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    vm.heap.as_list_mut(list).elements[index] = args[2];
+    return_val!(args, args[2]);
+}
+
+fn list_count(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let count = vm.heap.as_list(args[0].as_obj()).elements.len();
+    return_val!(args, Value::Num(count as f64));
+}
+
+fn list_iterate(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let count = vm.heap.as_list(args[0].as_obj()).elements.len();
+
+    // If we're starting the iteration, return the first index.
+    if let Value::Null = args[1] {
+        if count == 0 {
+            return_val!(args, Value::Bool(false));
+        }
+        return_val!(args, Value::Num(0.0));
+    }
+
+    if !validate_int(vm, args[1], "Iterator") {
+        return false;
+    }
+
+    // Stop if we're out of bounds.
+    let index = as_num(args[1]);
+    if index < 0.0 || index >= count as f64 - 1.0 {
+        return_val!(args, Value::Bool(false));
+    }
+
+    // Otherwise, move to the next index.
+    return_val!(args, Value::Num(index + 1.0));
+}
+
+fn list_iterator_value(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let list = args[0].as_obj();
+    let count = vm.heap.as_list(list).elements.len();
+    let index = match validate_index(vm, args[1], count, "Iterator") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    return_val!(args, vm.heap.as_list(list).elements[index]);
+}
+
+fn map_new(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(wren_new_map(vm)));
+}
+
+fn map_add_core(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_key(vm, args[1]) {
+        return false;
+    }
+
+    wren_map_set(vm, args[0].as_obj(), args[1], args[2]);
+
+    // Return the map itself.
+    return_val!(args, args[0]);
+}
+
+fn map_add_all(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !vm.heap.is_map(args[1]) {
+        return_error!(vm, "Argument must be a map.");
+    }
+
+    // Copy the entries out first in case the map is being added to itself.
+    let entries: Vec<MapEntry> = vm
+        .heap
+        .as_map(args[1].as_obj())
+        .entries
+        .iter()
+        .filter(|entry| !entry.key.is_undefined())
+        .copied()
+        .collect();
+    for entry in entries {
+        wren_map_set(vm, args[0].as_obj(), entry.key, entry.value);
+    }
+
+    // Return the map itself.
+    return_val!(args, args[0]);
+}
+
+fn map_clear(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    wren_map_clear(vm, args[0].as_obj());
+    return_val!(args, Value::Null);
+}
+
+fn map_contains_key(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_key(vm, args[1]) {
+        return false;
+    }
+
+    let value = wren_map_get(vm, args[0].as_obj(), args[1]);
+    return_val!(args, Value::Bool(!value.is_undefined()));
+}
+
+fn map_count(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let count = vm.heap.as_map(args[0].as_obj()).count;
+    return_val!(args, Value::Num(count as f64));
+}
+
+fn map_iterate(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let map = vm.heap.as_map(args[0].as_obj());
+    if map.count == 0 {
+        return_val!(args, Value::Bool(false));
+    }
+
+    // If we're starting the iteration, start at the first used entry.
+    let mut index = 0;
+
+    // Otherwise, start one past the last entry we stopped at.
+    if !matches!(args[1], Value::Null) {
+        if !validate_int(vm, args[1], "Iterator") {
+            return false;
+        }
+
+        if as_num(args[1]) < 0.0 {
+            return_val!(args, Value::Bool(false));
+        }
+        index = as_num(args[1]) as usize;
+
+        // Advance the iterator.
+        index += 1;
+    }
+
+    // Find a used entry, if any.
+    let entries = &vm.heap.as_map(args[0].as_obj()).entries;
+    match (index..entries.len()).find(|&i| !entries[i].key.is_undefined()) {
+        Some(index) => return_val!(args, Value::Num(index as f64)),
+
+        // If we get here, walked all of the entries.
+        None => return_val!(args, Value::Bool(false)),
+    }
+}
+
+// Returns the entry of the map at [args[0]] that the iterator [args[1]] points
+// to, or `None` after setting the fiber's error if it is invalid.
+fn map_iterator_entry(vm: &mut WrenVM, args: &[Value]) -> Option<MapEntry> {
+    let capacity = vm.heap.as_map(args[0].as_obj()).entries.len();
+    let index = validate_index(vm, args[1], capacity, "Iterator")?;
+
+    let entry = vm.heap.as_map(args[0].as_obj()).entries[index];
+    if entry.key.is_undefined() {
+        let error = wren_new_string(vm, b"Invalid map iterator.");
+        vm.set_error(error);
+        return None;
+    }
+    Some(entry)
+}
+
+fn map_key_iterator_value(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    match map_iterator_entry(vm, args) {
+        Some(entry) => return_val!(args, entry.key),
+        None => false,
+    }
+}
+
+fn map_value_iterator_value(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    match map_iterator_entry(vm, args) {
+        Some(entry) => return_val!(args, entry.value),
+        None => false,
+    }
+}
+
+fn map_remove(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_key(vm, args[1]) {
+        return false;
+    }
+
+    return_val!(args, wren_map_remove_key(vm, args[0].as_obj(), args[1]));
+}
+
+fn map_subscript(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_key(vm, args[1]) {
+        return false;
+    }
+
+    match wren_map_get(vm, args[0].as_obj(), args[1]) {
+        Value::Undefined => return_val!(args, Value::Null),
+        value => return_val!(args, value),
+    }
+}
+
+fn map_subscript_setter(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_key(vm, args[1]) {
+        return false;
+    }
+
+    // Unlike removing the key, storing null keeps it in the map.
+    wren_map_set(vm, args[0].as_obj(), args[1], args[2]);
+    return_val!(args, args[2]);
+}
+
+fn null_not(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(true));
+}
+
+fn null_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, wren_new_string(vm, b"null"));
+}
+
+// Defines a primitive for an infix arithmetic or comparison operator on nums.
+macro_rules! num_infix {
+    ($name:ident, $op:tt, Num) => {
+        fn $name(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+            if !validate_num(vm, args[1], "Right operand") {
+                return false;
+            }
+            return_val!(args, Value::Num(as_num(args[0]) $op as_num(args[1])));
+        }
+    };
+    ($name:ident, $op:tt, Bool) => {
+        fn $name(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+            if !validate_num(vm, args[1], "Right operand") {
+                return false;
+            }
+            return_val!(args, Value::Bool(as_num(args[0]) $op as_num(args[1])));
+        }
+    };
+}
+
+num_infix!(num_minus, -, Num);
+num_infix!(num_plus, +, Num);
+num_infix!(num_multiply, *, Num);
+num_infix!(num_divide, /, Num);
+num_infix!(num_mod, %, Num);
+num_infix!(num_lt, <, Bool);
+num_infix!(num_gt, >, Bool);
+num_infix!(num_lte, <=, Bool);
+num_infix!(num_gte, >=, Bool);
+
+// Defines a primitive for a bitwise operator on nums. The operands are
+// truncated to unsigned 32-bit integers first.
+macro_rules! num_bitwise {
+    ($name:ident, $op:expr) => {
+        fn $name(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+            if !validate_num(vm, args[1], "Right operand") {
+                return false;
+            }
+            let left = as_num(args[0]) as i64 as u32;
+            let right = as_num(args[1]) as i64 as u32;
+            let op: fn(u32, u32) -> u32 = $op;
+            return_val!(args, Value::Num(op(left, right) as f64));
+        }
+    };
+}
+
+num_bitwise!(num_bitwise_and, |left, right| left & right);
+num_bitwise!(num_bitwise_or, |left, right| left | right);
+num_bitwise!(num_bitwise_xor, |left, right| left ^ right);
+num_bitwise!(num_bitwise_left_shift, |left, right| left
+    .wrapping_shl(right));
+num_bitwise!(num_bitwise_right_shift, |left, right| left
+    .wrapping_shr(right));
+
+fn num_eqeq(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    match args[1] {
+        Value::Num(right) => return_val!(args, Value::Bool(as_num(args[0]) == right)),
+        _ => return_val!(args, Value::Bool(false)),
+    }
+}
+
+fn num_bangeq(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    match args[1] {
+        Value::Num(right) => return_val!(args, Value::Bool(as_num(args[0]) != right)),
+        _ => return_val!(args, Value::Bool(true)),
+    }
+}
+
+fn num_bitwise_not(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Num(!(as_num(args[0]) as i64 as u32) as f64));
+}
+
+fn num_clamp(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_num(vm, args[1], "Min value") {
+        return false;
+    }
+    if !validate_num(vm, args[2], "Max value") {
+        return false;
+    }
+
+    let value = as_num(args[0]);
+    let min = as_num(args[1]);
+    let max = as_num(args[2]);
+    if min > max {
+        return_error!(vm, "Min value must not be greater than max value.");
+    }
+
+    let result = if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    };
+    return_val!(args, Value::Num(result));
+}
+
+// Interpolates from [args[1]] to [args[2]] by the receiver, so 0 gives the
+// first and 1 the second.
+fn num_lerp(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_num(vm, args[1], "Start") {
+        return false;
+    }
+    if !validate_num(vm, args[2], "End") {
+        return false;
+    }
+
+    let amount = as_num(args[0]);
+    let start = as_num(args[1]);
+    let end = as_num(args[2]);
+    return_val!(args, Value::Num(start + (end - start) * amount));
+}
+
+fn num_dot_dot(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_num(vm, args[1], "Right hand side of range") {
+        return false;
+    }
+
+    let from = as_num(args[0]);
+    let to = as_num(args[1]);
+    return_val!(args, wren_new_range(vm, from, to, true));
+}
+
+fn num_dot_dot_dot(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_num(vm, args[1], "Right hand side of range") {
+        return false;
+    }
+
+    let from = as_num(args[0]);
+    let to = as_num(args[1]);
+    return_val!(args, wren_new_range(vm, from, to, false));
+}
+
+fn num_from_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_string(vm, args[1], "Argument") {
+        return false;
+    }
+
+    // Unlike a literal in source code, the string may be surrounded by
+    // whitespace.
+    let text = vm.heap.as_string(args[1].as_obj()).value.trim_ascii();
+    match wren_parse_number(text) {
+        Some(value) => return_val!(args, Value::Num(value)),
+        None => return_val!(args, Value::Null),
+    }
+}
+
+fn num_negate(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Num(-as_num(args[0])));
+}
+
+fn num_fraction(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    // Like C's modf(), an infinity has no fractional part.
+    let value = as_num(args[0]);
+    let fraction = if value.is_infinite() {
+        0.0_f64.copysign(value)
+    } else {
+        value.fract()
+    };
+    return_val!(args, Value::Num(fraction));
+}
+
+fn num_is_integer(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let value = as_num(args[0]);
+    if value.is_nan() || value.is_infinite() {
+        return_val!(args, Value::Bool(false));
+    }
+    return_val!(args, Value::Bool(value.trunc() == value));
+}
+
+fn num_truncate(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Num(as_num(args[0]).trunc()));
+}
+
+fn num_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, wren_num_to_string(vm, as_num(args[0])));
+}
+
+fn num_to_string_radix(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if as_num(args[0]).is_infinite() {
+        return_error!(vm, "Receiver must be an integer.");
+    }
+    if !validate_int_value(vm, as_num(args[0]), "Receiver") {
+        return false;
+    }
+    if !validate_int(vm, args[1], "Base") {
+        return false;
+    }
+
+    let base = as_num(args[1]);
+    if !(2.0..=36.0).contains(&base) {
+        return_error!(vm, "Base must be between 2 and 36.");
+    }
+
+    let value = as_num(args[0]);
+    let mut magnitude = value.abs();
+    let mut digits = Vec::new();
+
+    // Peel off digits from least to most significant. Working in floating point
+    // keeps integers above the u64 range exact.
+    loop {
+        let digit = (magnitude % base) as u32;
+        digits.push(char::from_digit(digit, base as u32).unwrap() as u8);
+        magnitude = ((magnitude - digit as f64) / base).trunc();
+        if magnitude == 0.0 {
+            break;
+        }
+    }
+
+    if value < 0.0 {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    return_val!(args, wren_new_string(vm, &digits));
+}
+
+fn object_not(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(false));
+}
+
+fn object_eqeq(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(wren_values_equal(vm, args[0], args[1])));
+}
+
+fn object_bangeq(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(!wren_values_equal(vm, args[0], args[1])));
+}
+
+fn object_is(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !vm.heap.is_class(args[1]) {
+        return_error!(vm, "Right operand must be a class.");
+    }
+
+    let mut class_obj = Some(vm.get_class(args[0]));
+    let base_class_obj = args[1].as_obj();
+
+    // Walk the superclass chain looking for the class.
+    while let Some(current) = class_obj {
+        if base_class_obj == current {
+            return_val!(args, Value::Bool(true));
+        }
+
+        class_obj = vm.heap.as_class(current).superclass;
+    }
+
+    return_val!(args, Value::Bool(false));
+}
+
+fn object_same(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Bool(wren_values_same(args[1], args[2])));
+}
+
+fn object_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let class_obj = vm.get_class(args[0]);
+    let name = vm.heap.as_class(class_obj).name;
+    let name = vm.heap.as_string(name).value.clone();
+    return_val!(args, wren_string_format(vm, &[b"instance of ", &name]));
+}
+
+fn object_type(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Obj(vm.get_class(args[0])));
+}
+
+fn range_from(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Num(vm.heap.as_range(args[0].as_obj()).from));
+}
+
+fn range_to(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, Value::Num(vm.heap.as_range(args[0].as_obj()).to));
+}
+
+fn range_min(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let range = vm.heap.as_range(args[0].as_obj());
+    return_val!(args, Value::Num(range.from.min(range.to)));
+}
+
+fn range_max(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let range = vm.heap.as_range(args[0].as_obj());
+    return_val!(args, Value::Num(range.from.max(range.to)));
+}
+
+fn range_is_inclusive(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(
+        args,
+        Value::Bool(vm.heap.as_range(args[0].as_obj()).is_inclusive)
+    );
+}
+
+fn range_contains(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    // Only numbers can be in a range, so anything else simply isn't.
+    if !matches!(args[1], Value::Num(_)) {
+        return_val!(args, Value::Bool(false));
+    }
+
+    let range = vm.heap.as_range(args[0].as_obj());
+    let value = as_num(args[1]);
+
+    // Ranges may run in either direction, so order the bounds first.
+    let (low, high) = if range.from <= range.to {
+        (range.from, range.to)
+    } else {
+        (range.to, range.from)
+    };
+
+    // An exclusive range leaves out its [to] end, whichever side that is.
+    let contains = value >= low && value <= high && (range.is_inclusive || value != range.to);
+    return_val!(args, Value::Bool(contains));
+}
+
+fn range_iterate(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let range = vm.heap.as_range(args[0].as_obj());
+    let (from, to, is_inclusive) = (range.from, range.to, range.is_inclusive);
+
+    // Special case: empty range.
+    if from == to && !is_inclusive {
+        return_val!(args, Value::Bool(false));
+    }
+
+    // Start the iteration.
+    if let Value::Null = args[1] {
+        return_val!(args, Value::Num(from));
+    }
+
+    if !validate_num(vm, args[1], "Iterator") {
+        return false;
+    }
+
+    let mut iterator = as_num(args[1]);
+
+    // Iterate towards [to] from [from].
+    if from < to {
+        iterator += 1.0;
+        if iterator > to {
+            return_val!(args, Value::Bool(false));
+        }
+    } else {
+        iterator -= 1.0;
+        if iterator < to {
+            return_val!(args, Value::Bool(false));
+        }
+    }
+
+    if !is_inclusive && iterator == to {
+        return_val!(args, Value::Bool(false));
+    }
+
+    return_val!(args, Value::Num(iterator));
+}
+
+fn range_iterator_value(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    // Assume the iterator is a number so that is the value of the range.
+    return_val!(args, args[1]);
+}
+
+fn range_to_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let range = vm.heap.as_range(args[0].as_obj());
+    let (from, to, is_inclusive) = (range.from, range.to, range.is_inclusive);
+
+    let from = wren_num_to_string(vm, from);
+    let to = wren_num_to_string(vm, to);
+    let from = vm.heap.as_string(from.as_obj()).value.clone();
+    let to = vm.heap.as_string(to.as_obj()).value.clone();
+    let dots: &[u8] = if is_inclusive { b".." } else { b"..." };
+    return_val!(args, wren_string_format(vm, &[&from, dots, &to]));
+}
+
+fn string_to_string(_vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    return_val!(args, args[0]);
+}
+
+fn string_plus(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_string(vm, args[1], "Right operand") {
+        return false;
+    }
+
+    let left = vm.heap.as_string(args[0].as_obj()).value.clone();
+    let right = vm.heap.as_string(args[1].as_obj()).value.clone();
+    return_val!(args, wren_string_format(vm, &[&left, &right]));
+}
+
+fn string_multiply(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let count = match args[1] {
+        Value::Num(count) if count >= 0.0 && count.is_finite() && count.trunc() == count => {
+            count as usize
+        }
+        _ => return_error!(vm, "Count must be a non-negative integer."),
+    };
+
+    let length = vm.heap.as_string(args[0].as_obj()).value.len();
+    match length.checked_mul(count) {
+        Some(total) if total <= MAX_REPEATED_STRING => {}
+        _ => return_error!(vm, "Count too large."),
+    }
+
+    let repeated = vm.heap.as_string(args[0].as_obj()).value.repeat(count);
+    return_val!(args, wren_new_string(vm, &repeated));
+}
+
+fn string_subscript(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let string = args[0].as_obj();
+    let length = vm.heap.as_string(string).value.len();
+
+    if let Value::Num(_) = args[1] {
+        let index = match validate_index(vm, args[1], length, "Subscript") {
+            Some(index) => index,
+            None => return false,
+        };
+
+        return_val!(args, wren_string_code_point_at(vm, string, index));
+    }
+
+    if !vm.heap.is_range(args[1]) {
+        return_error!(vm, "Subscript must be a number or a range.");
+    }
+
+    let (start, count, step) = match calculate_range(vm, args[1].as_obj(), length) {
+        Some(range) => range,
+        None => return false,
+    };
+
+    return_val!(
+        args,
+        wren_new_string_from_range(vm, string, start, count, step)
+    );
+}
+
+fn string_iterate(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let string = args[0].as_obj();
+
+    // If we're starting the iteration, return the first index.
+    if let Value::Null = args[1] {
+        if vm.heap.as_string(string).value.is_empty() {
+            return_val!(args, Value::Bool(false));
+        }
+        return_val!(args, Value::Num(0.0));
+    }
+
+    if !validate_int(vm, args[1], "Iterator") {
+        return false;
+    }
+
+    if as_num(args[1]) < 0.0 {
+        return_val!(args, Value::Bool(false));
+    }
+    let mut index = as_num(args[1]) as usize;
+
+    // Advance to the beginning of the next UTF-8 sequence.
+    let string = &vm.heap.as_string(string).value;
+    loop {
+        index += 1;
+        if index >= string.len() {
+            return_val!(args, Value::Bool(false));
+        }
+
+        if (string[index] & 0xc0) != 0x80 {
+            break;
+        }
+    }
+
+    return_val!(args, Value::Num(index as f64));
+}
+
+fn string_iterator_value(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let string = args[0].as_obj();
+    let length = vm.heap.as_string(string).value.len();
+    let index = match validate_index(vm, args[1], length, "Iterator") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    return_val!(args, wren_string_code_point_at(vm, string, index));
+}
+
+fn string_byte_at(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let string = args[0].as_obj();
+    let length = vm.heap.as_string(string).value.len();
+    let index = match validate_index(vm, args[1], length, "Index") {
+        Some(index) => index,
+        None => return false,
+    };
+
+    let byte = vm.heap.as_string(string).value[index];
+    return_val!(args, Value::Num(byte as f64));
+}
+
+fn string_byte_count(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let length = vm.heap.as_string(args[0].as_obj()).value.len();
+    return_val!(args, Value::Num(length as f64));
+}
+
+fn string_iterate_byte(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let length = vm.heap.as_string(args[0].as_obj()).value.len();
+
+    // If we're starting the iteration, return the first index.
+    if let Value::Null = args[1] {
+        if length == 0 {
+            return_val!(args, Value::Bool(false));
+        }
+        return_val!(args, Value::Num(0.0));
+    }
+
+    if !validate_int(vm, args[1], "Iterator") {
+        return false;
+    }
+
+    if as_num(args[1]) < 0.0 {
+        return_val!(args, Value::Bool(false));
+    }
+
+    // Advance to the next byte.
+    let index = as_num(args[1]) as usize + 1;
+    if index >= length {
+        return_val!(args, Value::Bool(false));
+    }
+
+    return_val!(args, Value::Num(index as f64));
+}
+
+fn string_contains(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_string(vm, args[1], "Argument") {
+        return false;
+    }
+
+    let string = vm.heap.as_string(args[0].as_obj());
+    let search = vm.heap.as_string(args[1].as_obj());
+    let found = wren_string_find(string, search, 0).is_some();
+    return_val!(args, Value::Bool(found));
+}
+
+fn string_index_of(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !validate_string(vm, args[1], "Argument") {
+        return false;
+    }
+
+    let string = vm.heap.as_string(args[0].as_obj());
+    let search = vm.heap.as_string(args[1].as_obj());
+    let index = match wren_string_find(string, search, 0) {
+        Some(index) => index as f64,
+        None => -1.0,
+    };
+    return_val!(args, Value::Num(index));
+}
+
+fn string_split(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    if !vm.heap.is_string(args[1]) || vm.heap.as_string(args[1].as_obj()).value.is_empty() {
+        return_error!(vm, "Delimiter must be a non-empty string.");
+    }
+
+    // Find every piece between the delimiters first. Consecutive delimiters
+    // leave an empty string between them, and so does one at either end.
+    let mut pieces = Vec::new();
+    {
+        let string = vm.heap.as_string(args[0].as_obj());
+        let delimiter = vm.heap.as_string(args[1].as_obj());
+        let mut start = 0;
+        while let Some(index) = wren_string_find(string, delimiter, start) {
+            pieces.push(string.value[start..index].to_vec());
+            start = index + delimiter.value.len();
+        }
+        pieces.push(string.value[start..].to_vec());
+    }
+
+    let list = wren_new_list(vm, 0);
+    for piece in pieces {
+        let piece = wren_new_string(vm, &piece);
+        vm.heap.as_list_mut(list).elements.push(piece);
+    }
+    return_val!(args, Value::Obj(list));
+}
+
+// The characters the argumentless trim methods remove.
+const WHITESPACE: &[u8] = b"\t\r\n ";
+
+// Splits [bytes] into its UTF-8 sequences. A byte that doesn't start a valid
+// sequence is a sequence of its own.
+fn utf8_sequences(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut sequences = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let length = utf8_decode_num_bytes(bytes[index])
+            .max(1)
+            .min(bytes.len() - index);
+        sequences.push(&bytes[index..index + length]);
+        index += length;
+    }
+    sequences
+}
+
+// Removes every code point in the string [chars] from the start and/or end of
+// the receiver.
+fn string_trim(vm: &mut WrenVM, args: &mut [Value], chars: Value, start: bool, end: bool) -> bool {
+    if !vm.heap.is_string(chars) {
+        return_error!(vm, "Characters must be a string.");
+    }
+
+    let string = &vm.heap.as_string(args[0].as_obj()).value;
+    let chars = &vm.heap.as_string(chars.as_obj()).value;
+
+    // Compare whole code points so that a multibyte character in [chars] only
+    // matches itself, not other characters sharing some of its bytes.
+    let trimmed = utf8_sequences(chars);
+    let sequences = utf8_sequences(string);
+    let mut from = 0;
+    let mut to = sequences.len();
+    if start {
+        while from < to && trimmed.contains(&sequences[from]) {
+            from += 1;
+        }
+    }
+    if end {
+        while to > from && trimmed.contains(&sequences[to - 1]) {
+            to -= 1;
+        }
+    }
+
+    let result = sequences[from..to].concat();
+    return_val!(args, wren_new_string(vm, &result));
+}
+
+fn string_trim_end(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let chars = wren_new_string(vm, WHITESPACE);
+    string_trim(vm, args, chars, false, true)
+}
+
+fn string_trim_end1(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    string_trim(vm, args, args[1], false, true)
+}
+
+fn string_trim_start(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let chars = wren_new_string(vm, WHITESPACE);
+    string_trim(vm, args, chars, true, false)
+}
+
+fn string_trim_start1(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    string_trim(vm, args, args[1], true, false)
+}
+
+fn system_clock(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let seconds = vm.start_time.elapsed().as_secs_f64();
+    return_val!(args, Value::Num(seconds));
+}
+
+fn system_gc(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.collect_garbage();
+    return_val!(args, Value::Null);
+}
+
+fn system_flush(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    vm.flush();
+    return_val!(args, Value::Null);
+}
+
+fn system_write_string(vm: &mut WrenVM, args: &mut [Value]) -> bool {
+    let text = String::from_utf8_lossy(&vm.heap.as_string(args[1].as_obj()).value).into_owned();
+    vm.write(&text);
+    return_val!(args, args[1]);
+}
+
+// The parts of the core library that are written in Wren itself, taken from
+// wren_core.wren. The rest of that file needs language features the compiler
+// doesn't support yet.
+const CORE_SOURCE: &str = r#"
+class Sequence {
+  count {
+    var result = 0
+    for (element in this) {
+      result = result + 1
+    }
+    return result
+  }
+
+  join() { join("") }
+
+  join(sep) {
+    var first = true
+    var result = ""
+
+    for (element in this) {
+      if (!first) result = result + sep
+      first = false
+      result = result + element.toString
+    }
+
+    return result
+  }
+
+  map(transformation) { MapSequence.new(this, transformation) }
+
+  skip(count) {
+    validateCount_(count)
+    return SkipSequence.new(this, count)
+  }
+
+  take(count) {
+    validateCount_(count)
+    return TakeSequence.new(this, count)
+  }
+
+  where(predicate) { WhereSequence.new(this, predicate) }
+
+  toList {
+    var result = List.new()
+    for (element in this) {
+      result.add(element)
+    }
+    return result
+  }
+
+  // Aborts the fiber unless [count] is a non-negative integer.
+  validateCount_(count) {
+    if (count is Num) {
+      if (count.isInteger) {
+        if (count >= 0) return
+      }
+    }
+
+    Fiber.abort("Count must be a non-negative integer.")
+  }
+}
+
+class MapSequence is Sequence {
+  construct new(sequence, fn) {
+    _sequence = sequence
+    _fn = fn
+  }
+
+  iterate(iterator) { _sequence.iterate(iterator) }
+  iteratorValue(iterator) { _fn.call(_sequence.iteratorValue(iterator)) }
+}
+
+class SkipSequence is Sequence {
+  construct new(sequence, count) {
+    _sequence = sequence
+    _count = count
+  }
+
+  iterate(iterator) {
+    if (iterator) return _sequence.iterate(iterator)
+
+    // Starting over, so step past the skipped elements first.
+    iterator = _sequence.iterate(iterator)
+    var count = _count
+    while (count > 0) {
+      if (!iterator) break
+      iterator = _sequence.iterate(iterator)
+      count = count - 1
+    }
+    return iterator
+  }
+
+  iteratorValue(iterator) { _sequence.iteratorValue(iterator) }
+}
+
+class TakeSequence is Sequence {
+  construct new(sequence, count) {
+    _sequence = sequence
+    _count = count
+  }
+
+  iterate(iterator) {
+    if (!iterator) _taken = 1 else _taken = _taken + 1
+    if (_taken > _count) return null
+    return _sequence.iterate(iterator)
+  }
+
+  iteratorValue(iterator) { _sequence.iteratorValue(iterator) }
+}
+
+class WhereSequence is Sequence {
+  construct new(sequence, fn) {
+    _sequence = sequence
+    _fn = fn
+  }
+
+  iterate(iterator) {
+    while (iterator = _sequence.iterate(iterator)) {
+      if (_fn.call(_sequence.iteratorValue(iterator))) break
+    }
+    return iterator
+  }
+
+  iteratorValue(iterator) { _sequence.iteratorValue(iterator) }
+}
+
+class String is Sequence {
+  bytes { StringByteSequence.new(this) }
+
+  // Joins the elements of [sequence] with this string between them. This
+  // can't just call [sequence]'s join(_), because [sequence] may be a string
+  // too.
+  join(sequence) {
+    var first = true
+    var result = ""
+
+    for (element in sequence) {
+      if (!first) result = result + this
+      first = false
+      result = result + element.toString
+    }
+
+    return result
+  }
+}
+
+class StringByteSequence is Sequence {
+  construct new(string) {
+    _string = string
+  }
+
+  [index] { _string.byteAt_(index) }
+  iterate(iterator) { _string.iterateByte_(iterator) }
+  iteratorValue(iterator) { _string.byteAt_(iterator) }
+
+  count { _string.byteCount_ }
+}
+
+class List is Sequence {
+  // Returns the number of elements for which [f] returns a true value. Unlike
+  // the count getter, this walks the whole list.
+  count(f) {
+    var result = 0
+    for (element in this) {
+      if (f.call(element)) result = result + 1
+    }
+    return result
+  }
+
+  // These send `==` to [element] rather than comparing the values directly, so
+  // that classes which override it are found by value.
+  contains(element) { indexOf(element) != -1 }
+
+  indexOf(element) {
+    for (i in 0...count) {
+      if (element == this[i]) return i
+    }
+    return -1
+  }
+
+  // Unlike wren_core.wren, this and insertAll(_,_) return the list rather than
+  // [other] so that calls can be chained.
+  addAll(other) {
+    for (element in other) add(element)
+    return this
+  }
+
+  insertAll(index, other) {
+    // Copy the elements first so that inserting a list into itself doesn't
+    // keep finding the elements it just inserted.
+    var elements = List.new()
+    for (element in other) elements.add(element)
+
+    // Like insert(_,_), -1 inserts after the last element.
+    if (index < 0) index = count + 1 + index
+    for (element in elements) {
+      insert(index, element)
+      index = index + 1
+    }
+    return this
+  }
+
+  sort() { sort {|low, high| low < high } }
+
+  // A bottom-up merge sort. Unlike the quicksort in wren_core.wren, it is
+  // stable: elements the comparer doesn't order keep their relative order.
+  sort(comparer) {
+    var buffer = List.new()
+    for (element in this) buffer.add(element)
+
+    var width = 1
+    while (width < count) {
+      var start = 0
+      while (start < count) {
+        merge_(start, width, comparer, buffer)
+        start = start + width * 2
+      }
+
+      for (i in 0...count) this[i] = buffer[i]
+      width = width * 2
+    }
+
+    return this
+  }
+
+  // Merges the sorted runs of [width] elements starting at [start] into
+  // [buffer].
+  merge_(start, width, comparer, buffer) {
+    var middle = start + width
+    if (middle > count) middle = count
+    var end = middle + width
+    if (end > count) end = count
+
+    var left = start
+    var right = middle
+    for (i in start...end) {
+      // Only take from the right run when its element comes strictly first, so
+      // that ties keep the left one in front.
+      var takeRight = left == middle
+      if (!takeRight) {
+        if (right < end) takeRight = comparer.call(this[right], this[left])
+      }
+
+      if (takeRight) {
+        buffer[i] = this[right]
+        right = right + 1
+      } else {
+        buffer[i] = this[left]
+        left = left + 1
+      }
+    }
+  }
+}
+
+class Map is Sequence {
+  toString {
+    var first = true
+    var result = "{"
+
+    var iterator = iterate(null)
+    while (iterator) {
+      if (!first) result = result + ", "
+      first = false
+      result = result + keyIteratorValue_(iterator).toString + ": " +
+          valueIteratorValue_(iterator).toString
+      iterator = iterate(iterator)
+    }
+
+    return result + "}"
+  }
+}
+
+class Range is Sequence {}
+
+class System {
+  static print() {
+    writeString_("\n")
+  }
+
+  static print(obj) {
+    writeObject_(obj)
+    writeString_("\n")
+    return obj
+  }
+
+  static printAll(sequence) {
+    for (object in sequence) writeObject_(object)
+    writeString_("\n")
+  }
+
+  static write(obj) {
+    writeObject_(obj)
+    return obj
+  }
+
+  static writeAll(sequence) {
+    for (object in sequence) writeObject_(object)
+  }
+
+  static writeObject_(obj) {
+    var string = obj.toString
+    if (string is String) {
+      writeString_(string)
+    } else {
+      writeString_("[invalid toString]")
+    }
+  }
+}
+"#;
+
+// Defines a new class named [name] with no superclass, metaclass, or methods
+// and stores it in a top-level variable of [module].
+fn define_class(vm: &mut WrenVM, module: ObjRef, name: &str) -> ObjRef {
+    let name_string = wren_new_string(vm, name.as_bytes()).as_obj();
+    let class_obj = wren_new_single_class(vm, 0, name_string);
+    vm.define_variable(module, name, Value::Obj(class_obj), None);
+    class_obj
+}
+
+// Defines a new class named [name] that inherits from [superclass] and stores
+// it in a top-level variable of [module]. This is what a
+// `class [name] is [superclass] {}` declaration in the core module does.
+fn define_core_class(vm: &mut WrenVM, module: ObjRef, name: &str, superclass: ObjRef) -> ObjRef {
+    let name_string = wren_new_string(vm, name.as_bytes()).as_obj();
+    let class_obj = wren_new_class(vm, superclass, 0, name_string);
+    vm.define_variable(module, name, Value::Obj(class_obj), None);
+    class_obj
+}
+
+pub(crate) fn wren_initialize_core(vm: &mut WrenVM) {
+    let core_module = wren_new_module(vm, None);
+
+    // The core module's key is null in the module map.
+    wren_map_set(vm, vm.modules, Value::Null, Value::Obj(core_module));
+
+    // Define the root Object class. This has to be done a little specially
+    // because it has no superclass.
+    let object_class = define_class(vm, core_module, "Object");
+    vm.object_class = Some(object_class);
+    primitive(vm, object_class, "!", object_not);
+    primitive(vm, object_class, "==(_)", object_eqeq);
+    primitive(vm, object_class, "!=(_)", object_bangeq);
+    primitive(vm, object_class, "is(_)", object_is);
+    primitive(vm, object_class, "toString", object_to_string);
+    primitive(vm, object_class, "type", object_type);
+
+    // Now we can define Class, which is a subclass of Object.
+    let class_class = define_class(vm, core_module, "Class");
+    vm.class_class = Some(class_class);
+    wren_bind_superclass(vm, class_class, object_class);
+    primitive(vm, class_class, "name", class_name);
+    primitive(vm, class_class, "supertype", class_supertype);
+    primitive(vm, class_class, "toString", class_to_string);
+
+    // Finally, we can define Object's metaclass which is a subclass of Class.
+    let object_metaclass = define_class(vm, core_module, "Object metaclass");
+
+    // Wire up the metaclass relationships now that all three classes are built.
+    vm.heap.get_mut(object_class).class_obj = Some(object_metaclass);
+    vm.heap.get_mut(object_metaclass).class_obj = Some(class_class);
+    vm.heap.get_mut(class_class).class_obj = Some(class_class);
+
+    wren_bind_superclass(vm, object_metaclass, class_class);
+
+    // The core class diagram ends up looking like this, where single lines point
+    // to a class's superclass, and double lines point to its metaclass:
+    //
+    //        .------------------------------------. .====.
+    //        |                  .---------------. | #    #
+    //        v                  |               v | v    #
+    //   .---------.   .-------------------.   .-------.  #
+    //   | Object  |==>| Object metaclass  |==>| Class |=="
+    //   '---------'   '-------------------'   '-------'
+    //        ^                                 ^ ^ ^ ^
+    //        |                  .--------------' # | #
+    //        |                  |                # | #
+    //   .---------.   .-------------------.      # | # -.
+    //   |  Base   |==>|  Base metaclass   |======" | #  |
+    //   '---------'   '-------------------'        | #  |
+    //        ^                                     | #  |
+    //        |                  .------------------' #  | Example classes
+    //        |                  |                    #  |
+    //   .---------.   .-------------------.          #  |
+    //   | Derived |==>| Derived metaclass |=========="  |
+    //   '---------'   '-------------------'            -'
+
+    primitive(vm, object_metaclass, "same(_,_)", object_same);
+
+    // The rest of the built-in classes. The collection types all inherit from
+    // Sequence, so they are declared in the Wren half of the core library.
+    let bool_class = define_core_class(vm, core_module, "Bool", object_class);
+    vm.bool_class = Some(bool_class);
+    primitive(vm, bool_class, "!", bool_not);
+    primitive(vm, bool_class, "toString", bool_to_string);
+
+    let bytes_class = define_core_class(vm, core_module, "Bytes", object_class);
+    vm.bytes_class = Some(bytes_class);
+    let bytes_metaclass = vm.heap.get(bytes_class).class_obj.unwrap();
+    primitive(vm, bytes_metaclass, "new()", bytes_new);
+    primitive(vm, bytes_metaclass, "fromString(_)", bytes_from_string);
+    primitive(vm, bytes_class, "add(_)", bytes_add);
+    primitive(vm, bytes_class, "count", bytes_count);
+    primitive(vm, bytes_class, "[_]", bytes_subscript);
+    primitive(vm, bytes_class, "[_]=(_)", bytes_subscript_setter);
+    primitive(vm, bytes_class, "toString", bytes_to_string);
+
+    let fiber_class = define_core_class(vm, core_module, "Fiber", object_class);
+    vm.fiber_class = Some(fiber_class);
+    let fiber_metaclass = vm.heap.get(fiber_class).class_obj.unwrap();
+    primitive(vm, fiber_metaclass, "abort(_)", fiber_abort);
+    primitive(vm, fiber_metaclass, "new(_)", fiber_new);
+    primitive(vm, fiber_metaclass, "suspend()", fiber_suspend);
+    primitive(vm, fiber_metaclass, "yield()", fiber_yield);
+    primitive(vm, fiber_metaclass, "yield(_)", fiber_yield1);
+    primitive(vm, fiber_class, "call()", fiber_call);
+    primitive(vm, fiber_class, "call(_)", fiber_call1);
+    primitive(vm, fiber_class, "isDone", fiber_is_done);
+    primitive(vm, fiber_class, "try()", fiber_try);
+    primitive(vm, fiber_class, "try(_)", fiber_try1);
+    primitive(vm, fiber_class, "toString", fiber_to_string);
+
+    let fn_class = define_core_class(vm, core_module, "Fn", object_class);
+    vm.fn_class = Some(fn_class);
+    let fn_metaclass = vm.heap.get(fn_class).class_obj.unwrap();
+    primitive(vm, fn_metaclass, "new(_)", fn_new);
+    primitive(vm, fn_class, "arity", fn_arity);
+    for num_args in 0..=MAX_PARAMETERS as usize {
+        let params = vec!["_"; num_args].join(",");
+        primitive(vm, fn_class, &format!("call({})", params), fn_call);
+    }
+    primitive(vm, fn_class, "toString", fn_to_string);
+
+    let null_class = define_core_class(vm, core_module, "Null", object_class);
+    vm.null_class = Some(null_class);
+    primitive(vm, null_class, "!", null_not);
+    primitive(vm, null_class, "toString", null_to_string);
+
+    let num_class = define_core_class(vm, core_module, "Num", object_class);
+    vm.num_class = Some(num_class);
+    let num_metaclass = vm.heap.get(num_class).class_obj.unwrap();
+    primitive(vm, num_metaclass, "fromString(_)", num_from_string);
+    primitive(vm, num_class, "-(_)", num_minus);
+    primitive(vm, num_class, "+(_)", num_plus);
+    primitive(vm, num_class, "*(_)", num_multiply);
+    primitive(vm, num_class, "/(_)", num_divide);
+    primitive(vm, num_class, "<(_)", num_lt);
+    primitive(vm, num_class, ">(_)", num_gt);
+    primitive(vm, num_class, "<=(_)", num_lte);
+    primitive(vm, num_class, ">=(_)", num_gte);
+    primitive(vm, num_class, "&(_)", num_bitwise_and);
+    primitive(vm, num_class, "|(_)", num_bitwise_or);
+    primitive(vm, num_class, "^(_)", num_bitwise_xor);
+    primitive(vm, num_class, "<<(_)", num_bitwise_left_shift);
+    primitive(vm, num_class, ">>(_)", num_bitwise_right_shift);
+    primitive(vm, num_class, "%(_)", num_mod);
+    primitive(vm, num_class, "-", num_negate);
+    primitive(vm, num_class, "~", num_bitwise_not);
+    primitive(vm, num_class, "fraction", num_fraction);
+    primitive(vm, num_class, "isInteger", num_is_integer);
+    primitive(vm, num_class, "truncate", num_truncate);
+    primitive(vm, num_class, "clamp(_,_)", num_clamp);
+    primitive(vm, num_class, "lerp(_,_)", num_lerp);
+    primitive(vm, num_class, "..(_)", num_dot_dot);
+    primitive(vm, num_class, "...(_)", num_dot_dot_dot);
+    primitive(vm, num_class, "toString", num_to_string);
+    primitive(vm, num_class, "toString(_)", num_to_string_radix);
+
+    // These are defined just so that 0 and -0 are equal, which is specified by
+    // IEEE 754 even though they have different bit representations.
+    primitive(vm, num_class, "==(_)", num_eqeq);
+    primitive(vm, num_class, "!=(_)", num_bangeq);
+
+    // Now that the built-in classes exist, run the Wren half of the core
+    // library in the core module and bind the primitives of the classes it
+    // defines. The host's source size, nesting and instruction limits are meant
+    // for its own scripts, so they don't apply here.
+    let max_source_length = vm.config.max_source_length.take();
+    let max_nesting_depth = std::mem::replace(&mut vm.config.max_nesting_depth, MAX_NESTING);
+    let closure = vm
+        .compile_source(None, CORE_SOURCE.as_bytes(), false, true)
+        .expect("Core library should compile.");
+    vm.config.max_source_length = max_source_length;
+    vm.config.max_nesting_depth = max_nesting_depth;
+    let fiber = wren_new_fiber(vm, closure);
+    let budget = vm.config.instruction_budget.take();
+    let result = vm.run_interpreter(fiber);
+    vm.config.instruction_budget = budget;
+    debug_assert_eq!(result, InterpretResult::Success, "Core library should run.");
+
+    let string_class = find_core_class(vm, core_module, "String");
+    vm.string_class = Some(string_class);
+    primitive(vm, string_class, "+(_)", string_plus);
+    primitive(vm, string_class, "*(_)", string_multiply);
+    primitive(vm, string_class, "[_]", string_subscript);
+    primitive(vm, string_class, "byteAt_(_)", string_byte_at);
+    primitive(vm, string_class, "byteCount_", string_byte_count);
+    primitive(vm, string_class, "contains(_)", string_contains);
+    primitive(vm, string_class, "indexOf(_)", string_index_of);
+    primitive(vm, string_class, "iterate(_)", string_iterate);
+    primitive(vm, string_class, "iterateByte_(_)", string_iterate_byte);
+    primitive(vm, string_class, "iteratorValue(_)", string_iterator_value);
+    primitive(vm, string_class, "split(_)", string_split);
+    primitive(vm, string_class, "toString", string_to_string);
+    primitive(vm, string_class, "trimEnd()", string_trim_end);
+    primitive(vm, string_class, "trimEnd(_)", string_trim_end1);
+    primitive(vm, string_class, "trimStart()", string_trim_start);
+    primitive(vm, string_class, "trimStart(_)", string_trim_start1);
+
+    let range_class = find_core_class(vm, core_module, "Range");
+    vm.range_class = Some(range_class);
+    primitive(vm, range_class, "from", range_from);
+    primitive(vm, range_class, "to", range_to);
+    primitive(vm, range_class, "min", range_min);
+    primitive(vm, range_class, "max", range_max);
+    primitive(vm, range_class, "isInclusive", range_is_inclusive);
+    primitive(vm, range_class, "contains(_)", range_contains);
+    primitive(vm, range_class, "iterate(_)", range_iterate);
+    primitive(vm, range_class, "iteratorValue(_)", range_iterator_value);
+    primitive(vm, range_class, "toString", range_to_string);
+
+    // While bootstrapping the core types, a number of string objects have been
+    // created, many of which were instantiated before stringClass was stored in
+    // the VM. Some of them *must* be created first -- the ObjClass for string
+    // itself has a reference to the ObjString for its name.
+    //
+    // These all currently have a `None` class, so go back and assign them now
+    // that the string class is known.
+    vm.heap.assign_string_class(vm.string_class);
+
+    let system_class = find_core_class(vm, core_module, "System");
+    let system_metaclass = vm.heap.get(system_class).class_obj.unwrap();
+    primitive(vm, system_metaclass, "clock", system_clock);
+    primitive(vm, system_metaclass, "flush()", system_flush);
+    primitive(vm, system_metaclass, "gc()", system_gc);
+    primitive(vm, system_metaclass, "writeString_(_)", system_write_string);
+
+    let list_class = find_core_class(vm, core_module, "List");
+    vm.list_class = Some(list_class);
+    let list_metaclass = vm.heap.get(list_class).class_obj.unwrap();
+    primitive(vm, list_metaclass, "new()", list_new);
+    primitive(vm, list_class, "[_]", list_subscript);
+    primitive(vm, list_class, "[_]=(_)", list_subscript_setter);
+    primitive(vm, list_class, "add(_)", list_add);
+    primitive(vm, list_class, "addCore_(_)", list_add_core);
+    primitive(vm, list_class, "clear()", list_clear);
+    primitive(vm, list_class, "count", list_count);
+    primitive(vm, list_class, "insert(_,_)", list_insert);
+    primitive(vm, list_class, "iterate(_)", list_iterate);
+    primitive(vm, list_class, "iteratorValue(_)", list_iterator_value);
+    primitive(vm, list_class, "remove(_)", list_remove_value);
+    primitive(vm, list_class, "removeAt(_)", list_remove_at);
+    primitive(vm, list_class, "reverse", list_reverse);
+    primitive(vm, list_class, "reversed", list_reversed);
+    primitive(vm, list_class, "swap(_,_)", list_swap);
+
+    let map_class = find_core_class(vm, core_module, "Map");
+    vm.map_class = Some(map_class);
+    let map_metaclass = vm.heap.get(map_class).class_obj.unwrap();
+    primitive(vm, map_metaclass, "new()", map_new);
+    primitive(vm, map_class, "[_]", map_subscript);
+    primitive(vm, map_class, "[_]=(_)", map_subscript_setter);
+    primitive(vm, map_class, "addAll(_)", map_add_all);
+    primitive(vm, map_class, "addCore_(_,_)", map_add_core);
+    primitive(vm, map_class, "clear()", map_clear);
+    primitive(vm, map_class, "containsKey(_)", map_contains_key);
+    primitive(vm, map_class, "count", map_count);
+    primitive(vm, map_class, "iterate(_)", map_iterate);
+    primitive(
+        vm,
+        map_class,
+        "keyIteratorValue_(_)",
+        map_key_iterator_value,
+    );
+    primitive(vm, map_class, "remove(_)", map_remove);
+    primitive(
+        vm,
+        map_class,
+        "valueIteratorValue_(_)",
+        map_value_iterator_value,
+    );
+}
+
+// Looks up the class stored in the top-level variable [name] of the core
+// module.
+fn find_core_class(vm: &WrenVM, module: ObjRef, name: &str) -> ObjRef {
+    let module = vm.heap.as_module(module);
+    let symbol = module.variable_names.find(name);
+    debug_assert!(symbol != -1, "Core class should be defined.");
+    module.variables[symbol as usize].as_obj()
+}
@@ -0,0 +1,63 @@
+use crate::value::{ObjRef, Value};
+use crate::vm::{ErrorType, StackFrame, WrenVM};
+
+// Prints the stack trace for the current fiber.
+//
+// Used when a fiber throws a runtime error which is not caught.
+pub(crate) fn wren_debug_print_stack_trace(vm: &mut WrenVM) {
+    // Bail if the host doesn't enable printing errors.
+    if vm.config.error_fn.is_none() {
+        return;
+    }
+
+    let fiber = vm.fiber.expect("Should have a fiber.");
+    let error = vm.heap.as_fiber(fiber).error;
+    let message = wren_debug_error_message(vm, error);
+    vm.report_error(ErrorType::Runtime, "", -1, &message);
+
+    for frame in wren_debug_stack_trace(vm, fiber) {
+        vm.report_error(
+            ErrorType::StackTrace,
+            &frame.module,
+            frame.line,
+            &frame.function,
+        );
+    }
+}
+
+// Returns the text reported for a fiber aborted with [error].
+pub(crate) fn wren_debug_error_message(vm: &WrenVM, error: Value) -> String {
+    if vm.heap.is_string(error) {
+        String::from_utf8_lossy(&vm.heap.as_string(error.as_obj()).value).into_owned()
+    } else {
+        // TODO: Print something a little useful here. Maybe the name of the error's
+        // class?
+        "[error object]".to_string()
+    }
+}
+
+// Collects the stack trace of [fiber], innermost call first.
+pub(crate) fn wren_debug_stack_trace(vm: &WrenVM, fiber: ObjRef) -> Vec<StackFrame> {
+    let mut trace = Vec::new();
+    for frame in vm.heap.as_fiber(fiber).frames.iter().rev() {
+        let fn_obj = vm.heap.as_closure(frame.closure).fn_obj;
+        let fn_obj = vm.heap.as_fn(fn_obj);
+
+        // The built-in core module has no name. We explicitly omit it from stack
+        // traces since we don't want to highlight to a user the implementation
+        // detail of what part of the core module is written in C and what is Wren.
+        let module_name = match vm.heap.as_module(fn_obj.module).name {
+            Some(name) => name,
+            None => continue,
+        };
+        let module_name = String::from_utf8_lossy(&vm.heap.as_string(module_name).value);
+
+        // -1 because IP has advanced past the instruction that it just executed.
+        trace.push(StackFrame {
+            module: module_name.into_owned(),
+            line: fn_obj.debug.source_lines[frame.ip - 1],
+            function: fn_obj.debug.name.clone(),
+        });
+    }
+    trace
+}
@@ -0,0 +1,16 @@
+mod common;
+mod compile;
+mod core;
+mod debug;
+mod opcodes;
+mod utils;
+mod value;
+mod verify;
+mod vm;
+
+pub use crate::compile::Tokens;
+pub use crate::vm::{
+    wren_get_version_number, AllocationFn, BindForeignMethodFn, Config, ErrorType, FiberResult,
+    ForeignMethodFn, InterpretResult, LoadModuleFn, ResolveModuleFn, RuntimeError, StackFrame,
+    StringValidation, WrenHandle, WrenVM, WrenValue,
+};
@@ -1,9 +1,87 @@
-mod common;
-mod compile;
-mod core;
-mod value;
-mod vm;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use starling::{wren_get_version_number, Config, ErrorType, InterpretResult, WrenVM};
+
+fn write(_vm: &mut WrenVM, text: &str) {
+    print!("{}", text);
+}
+
+fn report_error(_vm: &mut WrenVM, ty: ErrorType, module: &str, line: i32, message: &str) {
+    match ty {
+        ErrorType::Compile | ErrorType::Warning => {
+            eprintln!("[{} line {}] {}", module, line, message)
+        }
+        ErrorType::Runtime | ErrorType::Snippet => eprintln!("{}", message),
+        ErrorType::StackTrace => eprintln!("[{} line {}] in {}", module, line, message),
+    }
+}
+
+// Resolves an import that starts with "./" or "../" relative to the directory
+// of the importing module. Any other name is left as it is.
+fn resolve_module(_vm: &mut WrenVM, importer: &str, name: &str) -> Option<String> {
+    if !name.starts_with("./") && !name.starts_with("../") {
+        return Some(name.to_string());
+    }
+
+    let mut resolved = PathBuf::new();
+    let directory = Path::new(importer).parent().unwrap_or(Path::new(""));
+    for component in directory.join(name).components() {
+        match component {
+            Component::CurDir => {}
+            // Go up a directory, unless there are only ".." left to go up from.
+            Component::ParentDir => {
+                if resolved.file_name().is_some() {
+                    resolved.pop();
+                } else {
+                    resolved.push("..");
+                }
+            }
+            _ => resolved.push(component),
+        }
+    }
+    Some(resolved.to_string_lossy().into_owned())
+}
+
+// Reads the source of the module [name] from "[name].wren".
+fn load_module(_vm: &mut WrenVM, name: &str) -> Option<String> {
+    fs::read_to_string(format!("{}.wren", name)).ok()
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        println!("starling {}", wren_get_version_number());
+        println!("Usage: starling [file]");
+        return;
+    }
+
+    let path = &args[1];
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("Could not read file \"{}\": {}", path, err);
+            process::exit(66);
+        }
+    };
+
+    // Use the path without its extension as the module name.
+    let module = path.strip_suffix(".wren").unwrap_or(path);
+
+    let config = Config {
+        write_fn: Some(Box::new(write)),
+        error_fn: Some(Box::new(report_error)),
+        resolve_module_fn: Some(Box::new(resolve_module)),
+        load_module_fn: Some(Box::new(load_module)),
+        ..Config::default()
+    };
+    let mut vm = WrenVM::new(config);
+
+    match vm.interpret(module, &source) {
+        InterpretResult::Success | InterpretResult::Suspended => {}
+        InterpretResult::CompileError => process::exit(65),
+        InterpretResult::RuntimeError | InterpretResult::Budget => process::exit(70),
+    }
 }
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+// A bi-directional mapping between strings and integers. Used for method
+// signatures and module variable names, where the compiler and VM refer to
+// names by their index.
+#[derive(Default)]
+pub(crate) struct SymbolTable {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    // Adds [name] to the symbol table. Returns the index of it in the table.
+    pub(crate) fn add(&mut self, name: &str) -> usize {
+        let symbol = self.names.len();
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    // Adds [name] to the symbol table. Returns the index of it in the table.
+    // If it's already in the table, returns its existing index.
+    pub(crate) fn ensure(&mut self, name: &str) -> usize {
+        // See if the symbol is already defined.
+        let existing = self.find(name);
+        if existing != -1 {
+            return existing as usize;
+        }
+
+        // New symbol, so add it.
+        self.add(name)
+    }
+
+    // Looks up [name] in the symbol table. Returns its index if found or -1 if
+    // not.
+    pub(crate) fn find(&self, name: &str) -> i32 {
+        match self.indices.get(name) {
+            Some(&symbol) => symbol as i32,
+            None => -1,
+        }
+    }
+
+    pub(crate) fn name(&self, symbol: usize) -> &str {
+        &self.names[symbol]
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        self.names.len()
+    }
+}
+
+// Returns the number of bytes needed to encode [value] in UTF-8.
+//
+// Returns 0 if [value] is too large to encode.
+pub(crate) fn utf8_encode_num_bytes(value: i32) -> usize {
+    debug_assert!(value >= 0, "Cannot encode a negative value.");
+
+    if value <= 0x7f {
+        return 1;
+    }
+    if value <= 0x7ff {
+        return 2;
+    }
+    if value <= 0xffff {
+        return 3;
+    }
+    if value <= 0x10ffff {
+        return 4;
+    }
+    0
+}
+
+// Encodes value as a series of bytes in [bytes], which is assumed to be large
+// enough to hold the encoded result.
+pub(crate) fn utf8_encode(value: i32, bytes: &mut Vec<u8>) {
+    let value = value as u32;
+    if value <= 0x7f {
+        // Single byte (i.e. fits in ASCII).
+        bytes.push((value & 0x7f) as u8);
+    } else if value <= 0x7ff {
+        // Two byte sequence: 110xxxxx 10xxxxxx.
+        bytes.push((0xc0 | ((value & 0x7c0) >> 6)) as u8);
+        bytes.push((0x80 | (value & 0x3f)) as u8);
+    } else if value <= 0xffff {
+        // Three byte sequence: 1110xxxx 10xxxxxx 10xxxxxx.
+        bytes.push((0xe0 | ((value & 0xf000) >> 12)) as u8);
+        bytes.push((0x80 | ((value & 0xfc0) >> 6)) as u8);
+        bytes.push((0x80 | (value & 0x3f)) as u8);
+    } else if value <= 0x10ffff {
+        // Four byte sequence: 11110xxx 10xxxxxx 10xxxxxx 10xxxxxx.
+        bytes.push((0xf0 | ((value & 0x1c0000) >> 18)) as u8);
+        bytes.push((0x80 | ((value & 0x3f000) >> 12)) as u8);
+        bytes.push((0x80 | ((value & 0xfc0) >> 6)) as u8);
+        bytes.push((0x80 | (value & 0x3f)) as u8);
+    } else {
+        // Invalid Unicode value. See: http://tools.ietf.org/html/rfc3629
+        unreachable!();
+    }
+}
+
+// Returns the number of bytes in the UTF-8 sequence starting with [byte].
+//
+// If the character at that index is not the beginning of a UTF-8 sequence,
+// returns 0.
+pub(crate) fn utf8_decode_num_bytes(byte: u8) -> usize {
+    // If the byte starts with 10xxxxx, it's the middle of a UTF-8 sequence, so
+    // don't count it at all.
+    if (byte & 0xc0) == 0x80 {
+        return 0;
+    }
+
+    // The first byte's high bits tell us how many bytes are in the UTF-8
+    // sequence.
+    if (byte & 0xf8) == 0xf0 {
+        return 4;
+    }
+    if (byte & 0xf0) == 0xe0 {
+        return 3;
+    }
+    if (byte & 0xe0) == 0xc0 {
+        return 2;
+    }
+    1
+}
+
+// Decodes the UTF-8 sequence at the start of [bytes] and returns the code
+// point.
+//
+// Returns -1 if the bytes are not a valid UTF-8 sequence.
+pub(crate) fn utf8_decode(bytes: &[u8]) -> i32 {
+    // Single byte (i.e. fits in ASCII).
+    if bytes[0] <= 0x7f {
+        return bytes[0] as i32;
+    }
+
+    let (mut value, remaining_bytes) = if (bytes[0] & 0xe0) == 0xc0 {
+        // Two byte sequence: 110xxxxx 10xxxxxx.
+        ((bytes[0] & 0x1f) as i32, 1)
+    } else if (bytes[0] & 0xf0) == 0xe0 {
+        // Three byte sequence: 1110xxxx 10xxxxxx 10xxxxxx.
+        ((bytes[0] & 0x0f) as i32, 2)
+    } else if (bytes[0] & 0xf8) == 0xf0 {
+        // Four byte sequence: 11110xxx 10xxxxxx 10xxxxxx 10xxxxxx.
+        ((bytes[0] & 0x07) as i32, 3)
+    } else {
+        // Invalid UTF-8 sequence.
+        return -1;
+    };
+
+    // Don't read past the end of the buffer on truncated UTF-8.
+    if remaining_bytes > bytes.len() - 1 {
+        return -1;
+    }
+
+    for &byte in &bytes[1..=remaining_bytes] {
+        // Remaining bytes must be of form 10xxxxxx.
+        if (byte & 0xc0) != 0x80 {
+            return -1;
+        }
+
+        value = (value << 6) | (byte & 0x3f) as i32;
+    }
+
+    value
+}
+
+// Formats [value] the way C's `printf("%.14g", value)` does: rounded to 14
+// significant digits, without trailing zeros, and in exponent notation when
+// the exponent is less than -4 or at least 14. Assumes [value] is finite.
+pub(crate) fn format_double(value: f64) -> String {
+    const PRECISION: i32 = 14;
+
+    // Round to the precision first, since that can change the exponent (for
+    // example, 9.99999999999999 rounds up to 10).
+    let scientific = format!("{:.*e}", PRECISION as usize - 1, value);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("Scientific notation should have an exponent.");
+    let exponent: i32 = exponent.parse().expect("Exponent should be a number.");
+
+    if !(-4..PRECISION).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}e{}{:02}", trim_fraction(mantissa), sign, exponent.abs());
+    }
+
+    let decimals = (PRECISION - 1 - exponent) as usize;
+    trim_fraction(&format!("{:.*}", decimals, value)).to_string()
+}
+
+// Removes trailing zeros after the decimal point in [number], and the point
+// itself if nothing is left after it.
+fn trim_fraction(number: &str) -> &str {
+    if !number.contains('.') {
+        return number;
+    }
+    number.trim_end_matches('0').trim_end_matches('.')
+}
@@ -1,29 +1,1271 @@
-pub(crate) use i32 as Value;
-// pub(crate) use std::collections::HashMap as SymbolTable;
-// pub(crate) use Vec as Buffer;
+use crate::utils::{
+    format_double, utf8_decode, utf8_decode_num_bytes, utf8_encode, utf8_encode_num_bytes,
+    SymbolTable,
+};
+use crate::vm::{AllocationFn, ForeignMethodFn, WrenVM};
+
+// This defines the built-in types and their core representations in memory.
+// Since Wren is dynamically typed, any variable can hold a value of any type,
+// and the type can change at runtime. Implementing this efficiently is
+// critical for performance.
+//
+// Objects live in the VM's [Heap] and are referred to by an [ObjRef] handle.
+// Values themselves are small and copied around freely.
+
+// A handle to an object stored in the VM's heap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ObjRef(u32);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Obj(ObjRef),
+
+    // This is only used internally. It never appears as a real value in a
+    // running script.
+    Undefined,
+}
+
+impl Value {
+    pub(crate) fn is_undefined(self) -> bool {
+        matches!(self, Value::Undefined)
+    }
+
+    // Returns true if [self] is false or null, which are the only values a
+    // condition treats as false.
+    pub(crate) fn is_falsy(self) -> bool {
+        matches!(self, Value::Null | Value::Bool(false))
+    }
+
+    pub(crate) fn as_obj(self) -> ObjRef {
+        match self {
+            Value::Obj(obj) => obj,
+            _ => unreachable!("Value is not an object."),
+        }
+    }
+}
+
+pub(crate) struct ObjString {
+    // The raw bytes of the string. Wren strings are byte sequences and are
+    // not required to be valid UTF-8.
+    pub(crate) value: Vec<u8>,
+
+    // The hash value of the string's contents.
+    pub(crate) hash: u32,
+}
+
+// A first-class function object. A raw ObjFn can be used and invoked directly
+// if it has no upvalues (i.e. [numUpvalues] is zero). If it does use
+// upvalues, it must be wrapped in an [ObjClosure] first. The compiler is
+// responsible for emitting code to ensure that that happens.
+pub(crate) struct ObjFn {
+    pub(crate) code: Vec<u8>,
+    pub(crate) constants: Vec<Value>,
+
+    // The module where this function was defined.
+    pub(crate) module: ObjRef,
+
+    // The maximum number of stack slots this function may use.
+    pub(crate) max_slots: usize,
+
+    // The number of upvalues this function closes over.
+    pub(crate) num_upvalues: usize,
+
+    pub(crate) arity: i32,
+    pub(crate) debug: FnDebug,
+}
+
+impl ObjFn {
+    pub(crate) fn new(module: ObjRef, max_slots: usize) -> ObjFn {
+        ObjFn {
+            code: Vec::new(),
+            constants: Vec::new(),
+            module,
+            max_slots,
+            num_upvalues: 0,
+            arity: 0,
+            debug: FnDebug::default(),
+        }
+    }
+}
+
+// Stores debugging information for a function used for things like stack
+// traces.
+#[derive(Default)]
+pub(crate) struct FnDebug {
+    // The name of the function.
+    pub(crate) name: String,
+
+    // An array of line numbers. There is one element in this array for each
+    // bytecode in the function's bytecode array. The value of that element is
+    // the line in the source code that generated that instruction.
+    pub(crate) source_lines: Vec<i32>,
+}
+
+// The dynamically allocated data structure for a variable that has been used
+// by a closure. Whenever a function accesses a variable declared in an
+// enclosing function, it will get to it through this.
+//
+// An upvalue can be either "closed" or "open". An open upvalue points directly
+// to a value that is still stored on the fiber's stack because the local
+// variable is still in scope in the function where it's declared.
+//
+// When that local variable goes out of scope, the upvalue pointing to it will
+// be closed. When that happens, the value gets copied off the stack into the
+// upvalue itself. That way, it can have a longer lifetime than the stack
+// variable.
+pub(crate) struct ObjUpvalue {
+    // The fiber whose stack holds the variable while the upvalue is open.
+    pub(crate) fiber: ObjRef,
+
+    // The index of the variable in [fiber]'s stack while the upvalue is open.
+    pub(crate) slot: usize,
+
+    // The value of the variable once the upvalue has been closed, or `None`
+    // while it is still open.
+    pub(crate) closed: Option<Value>,
+}
+
+// An instance of a first-class function and the environment it has closed
+// over. Unlike [ObjFn], this has captured variables.
+pub(crate) struct ObjClosure {
+    // The function that this closure is an instance of.
+    pub(crate) fn_obj: ObjRef,
+
+    // The upvalues this function has closed over.
+    pub(crate) upvalues: Vec<ObjRef>,
+}
+
+pub(crate) struct CallFrame {
+    // Index of the current instruction in the function's bytecode.
+    pub(crate) ip: usize,
+
+    // The closure being executed.
+    pub(crate) closure: ObjRef,
+
+    // Index of the first stack slot used by this call frame. This will contain
+    // the receiver, followed by the function's parameters, then local
+    // variables and temporaries.
+    pub(crate) stack_start: usize,
+}
+
+pub(crate) struct ObjFiber {
+    // The stack of value slots. This is used for holding local variables and
+    // temporaries while the fiber is executing.
+    pub(crate) stack: Vec<Value>,
+
+    // The stack of call frames. This is a dynamic array that grows as needed
+    // but never shrinks.
+    pub(crate) frames: Vec<CallFrame>,
+
+    // The upvalues that are still pointing to slots in [stack], ordered by
+    // the slot they point to, from the bottom of the stack to the top.
+    pub(crate) open_upvalues: Vec<ObjRef>,
+
+    // The fiber that ran this one. If this fiber is yielded, control will resume
+    // to this one. May be `None`.
+    pub(crate) caller: Option<ObjRef>,
+
+    // The error value, if this fiber was aborted. Otherwise, this is null.
+    pub(crate) error: Value,
+
+    pub(crate) state: FiberState,
+
+    // If the host ran this fiber and it ran out of instruction budget, the
+    // fiber that was running when it did. That is either this one or one that
+    // this fiber is waiting on, and it's where the next resume carries on.
+    pub(crate) interrupted: Option<ObjRef>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FiberState {
+    // The fiber is being run from another fiber using a call to `try()`.
+    Try,
+
+    // The fiber was directly invoked by the host or by `call()`, or hasn't been
+    // run yet.
+    Other,
+}
+
+// The type of a primitive function.
+//
+// Primitives are similar to foreign functions, but have more direct access to
+// VM internals. It is passed the arguments in [args]. If it returns a value,
+// it places it in `args[0]` and returns `true`. If it causes a runtime error
+// or modifies the running fiber, it returns `false`.
+pub(crate) type Primitive = fn(vm: &mut WrenVM, args: &mut [Value]) -> bool;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Method {
+    // A primitive method implemented in Rust in the VM. Unlike foreign methods,
+    // this can directly manipulate the fiber's stack.
+    Primitive(Primitive),
+
+    // A host-provided method that reads its arguments from and writes its
+    // result to the slots.
+    Foreign(ForeignMethodFn),
+
+    // A normal user-defined method.
+    Block(ObjRef),
+}
+
+pub(crate) struct ObjClass {
+    pub(crate) superclass: Option<ObjRef>,
+
+    // The number of fields needed for an instance of this class, including all
+    // of its superclass fields.
+    pub(crate) num_fields: i32,
+
+    // The table of methods that are defined in or inherited by this class.
+    // Methods are called by symbol, and the symbol directly maps to an index in
+    // this table. This makes method calls fast at the expense of empty cells in
+    // the list for methods the class doesn't support.
+    //
+    // You can think of it as a hash table that never has collisions but has a
+    // really low load factor. Since methods are pretty small (just a type and a
+    // pointer), this should be a worthwhile trade-off.
+    pub(crate) methods: Vec<Option<Method>>,
+
+    // The name of the class.
+    pub(crate) name: ObjRef,
+}
+
+// A growable buffer of raw bytes, for binary data that isn't text.
+pub(crate) struct ObjBytes {
+    // The bytes in the buffer.
+    pub(crate) value: Vec<u8>,
+}
+
+pub(crate) struct ObjInstance {
+    pub(crate) fields: Vec<Value>,
+}
+
+pub(crate) struct ObjList {
+    // The elements in the list.
+    pub(crate) elements: Vec<Value>,
+}
 
 pub(crate) struct ObjModule {
-    // obj: Obj,
+    // The currently defined top-level variables.
+    pub(crate) variables: Vec<Value>,
 
-// The currently defined top-level variables.
-// variables: ValueBuffer,
+    // Symbol table for the names of all module variables. Indexes here directly
+    // correspond to entries in [variables].
+    pub(crate) variable_names: SymbolTable,
+
+    // The name of the module.
+    pub(crate) name: Option<ObjRef>,
+}
 
-// Symbol table for the names of all module variables. Indexes here directly
-// correspond to entries in [variables].
-// variableNames: SymbolTable,
+#[derive(Clone, Copy)]
+pub(crate) struct MapEntry {
+    // The entry's key, or UNDEFINED_VAL if the entry is not in use.
+    pub(crate) key: Value,
 
-// The name of the module.
-// name: ObjString,
+    // The value associated with the key. If the key is UNDEFINED_VAL, this will
+    // be false to indicate an open available entry or true to indicate a
+    // tombstone -- an entry that was previously in use but was then deleted.
+    pub(crate) value: Value,
 }
 
+// A hash table mapping keys to values.
+//
+// We use something very simple: open addressing with linear probing. The hash
+// table is an array of entries. Each entry is a key-value pair. If the key is
+// the special UNDEFINED_VAL, it indicates no value is currently in that slot.
+// Otherwise, it's a valid key, and the value is the value associated with it.
+//
+// When entries are added, the array is dynamically scaled by GROW_FACTOR to
+// keep the number of filled slots under MAP_LOAD_PERCENT. Likewise, if the map
+// gets empty enough, it will be resized to a smaller array. When this happens,
+// all existing entries are rehashed and re-added to the new array.
+//
+// When an entry is removed, its slot is replaced with a "tombstone". This is an
+// entry whose key is UNDEFINED_VAL and whose value is TRUE_VAL. When probing
+// for a key, we will continue past tombstones, because the desired key may be
+// found after them if the key that was removed was part of a prior collision.
+// When the array gets resized, all tombstones are discarded.
+#[derive(Default)]
 pub(crate) struct ObjMap {
-    // todo
+    // The number of entries in the map.
+    pub(crate) count: usize,
+
+    // Pointer to a contiguous array of [capacity] entries.
+    pub(crate) entries: Vec<MapEntry>,
 }
 
-pub(crate) struct ObjFn {
-    // todo
+pub(crate) struct ObjRange {
+    // The beginning of the range.
+    pub(crate) from: f64,
+
+    // The end of the range. May be greater or less than [from].
+    pub(crate) to: f64,
+
+    // True if [to] is included in the range.
+    pub(crate) is_inclusive: bool,
 }
 
-pub(crate) struct ObjString {
-    // todo
+pub(crate) enum ObjType {
+    Bytes(ObjBytes),
+    Class(ObjClass),
+    Closure(ObjClosure),
+    Fiber(ObjFiber),
+    Fn(ObjFn),
+    Instance(ObjInstance),
+    List(ObjList),
+    Map(ObjMap),
+    Module(ObjModule),
+    Range(ObjRange),
+    String(ObjString),
+    Upvalue(ObjUpvalue),
+}
+
+// Base struct for all heap-allocated objects.
+pub(crate) struct Obj {
+    // The object's class.
+    pub(crate) class_obj: Option<ObjRef>,
+
+    pub(crate) ty: ObjType,
+}
+
+impl Obj {
+    // The number of bytes this object and the buffers it owns take up.
+    fn size(&self) -> usize {
+        use std::mem::size_of;
+
+        let buffers = match &self.ty {
+            ObjType::Bytes(bytes) => bytes.value.capacity(),
+            ObjType::Class(class_obj) => class_obj.methods.capacity() * size_of::<Option<Method>>(),
+            ObjType::Closure(closure) => closure.upvalues.capacity() * size_of::<ObjRef>(),
+            ObjType::Fiber(fiber) => {
+                fiber.stack.capacity() * size_of::<Value>()
+                    + fiber.frames.capacity() * size_of::<CallFrame>()
+                    + fiber.open_upvalues.capacity() * size_of::<ObjRef>()
+            }
+            ObjType::Fn(fn_obj) => {
+                fn_obj.code.capacity()
+                    + fn_obj.constants.capacity() * size_of::<Value>()
+                    + fn_obj.debug.source_lines.capacity() * size_of::<i32>()
+            }
+            ObjType::Instance(instance) => instance.fields.capacity() * size_of::<Value>(),
+            ObjType::List(list) => list.elements.capacity() * size_of::<Value>(),
+            ObjType::Map(map) => map.entries.capacity() * size_of::<MapEntry>(),
+            ObjType::Module(module) => module.variables.capacity() * size_of::<Value>(),
+            ObjType::String(string) => string.value.capacity(),
+            ObjType::Range(_) | ObjType::Upvalue(_) => 0,
+        };
+        size_of::<Obj>() + buffers
+    }
+}
+
+// Owns every object allocated by the VM. Objects are addressed by the index
+// of the slot they occupy, and freed slots are reused by later allocations.
+#[derive(Default)]
+pub(crate) struct Heap {
+    objects: Vec<Option<Obj>>,
+    free_slots: Vec<u32>,
+
+    // The embedder's allocation hook, told about every object allocated and
+    // freed through [record_allocation].
+    allocation_fn: Option<AllocationFn>,
+
+    // The total number of bytes the live objects take up. This is only
+    // approximate between collections, since objects grow after they're
+    // allocated, and is recounted exactly each time the GC runs.
+    pub(crate) bytes_allocated: usize,
+}
+
+impl Heap {
+    pub(crate) fn new(allocation_fn: Option<AllocationFn>) -> Heap {
+        Heap {
+            allocation_fn,
+            ..Heap::default()
+        }
+    }
+
+    // The single funnel every object allocation and free is recorded through,
+    // like `wrenReallocate`. An [old_size] of zero means a new object is allocated
+    // and a [new_size] of zero means an object is freed.
+    fn record_allocation(&mut self, old_size: usize, new_size: usize) {
+        self.bytes_allocated = (self.bytes_allocated + new_size).saturating_sub(old_size);
+        if let Some(allocation_fn) = self.allocation_fn.as_mut() {
+            allocation_fn(old_size, new_size);
+        }
+    }
+
+    pub(crate) fn allocate(&mut self, class_obj: Option<ObjRef>, ty: ObjType) -> ObjRef {
+        let obj = Obj { class_obj, ty };
+        self.record_allocation(0, obj.size());
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.objects[slot as usize] = Some(obj);
+                ObjRef(slot)
+            }
+            None => {
+                self.objects.push(Some(obj));
+                ObjRef(self.objects.len() as u32 - 1)
+            }
+        }
+    }
+
+    // The number of objects allocated and not yet freed.
+    pub(crate) fn object_count(&self) -> usize {
+        self.objects.len() - self.free_slots.len()
+    }
+
+    pub(crate) fn get(&self, obj: ObjRef) -> &Obj {
+        self.objects[obj.0 as usize]
+            .as_ref()
+            .expect("Object should be live.")
+    }
+
+    pub(crate) fn get_mut(&mut self, obj: ObjRef) -> &mut Obj {
+        self.objects[obj.0 as usize]
+            .as_mut()
+            .expect("Object should be live.")
+    }
+
+    // Frees every object that can't be reached from [roots] and returns the
+    // number of objects freed.
+    pub(crate) fn collect(&mut self, roots: &[Value]) -> usize {
+        let mut is_marked = vec![false; self.objects.len()];
+        let mut gray = Vec::new();
+        for &root in roots {
+            gray_value(&mut gray, root);
+        }
+
+        // Keep blackening objects until there are no more gray ones.
+        while let Some(obj) = gray.pop() {
+            if is_marked[obj.0 as usize] {
+                continue;
+            }
+            is_marked[obj.0 as usize] = true;
+            self.blacken_object(obj, &mut gray);
+        }
+
+        let mut num_freed = 0;
+        let mut bytes_live = 0;
+        for (slot, &marked) in is_marked.iter().enumerate() {
+            let size = match &self.objects[slot] {
+                Some(obj) => obj.size(),
+                None => continue,
+            };
+            if marked {
+                bytes_live += size;
+            } else {
+                self.objects[slot] = None;
+                self.free_slots.push(slot as u32);
+                self.record_allocation(size, 0);
+                num_freed += 1;
+            }
+        }
+        self.bytes_allocated = bytes_live;
+        num_freed
+    }
+
+    // Adds every object that [obj] refers to to the [gray] stack so that it
+    // gets marked too.
+    fn blacken_object(&self, obj: ObjRef, gray: &mut Vec<ObjRef>) {
+        let obj = self.get(obj);
+        gray.extend(obj.class_obj);
+
+        match &obj.ty {
+            ObjType::Class(class_obj) => {
+                gray.extend(class_obj.superclass);
+                gray.push(class_obj.name);
+                for method in class_obj.methods.iter().flatten() {
+                    if let Method::Block(closure) = method {
+                        gray.push(*closure);
+                    }
+                }
+            }
+            ObjType::Closure(closure) => {
+                gray.push(closure.fn_obj);
+                gray.extend(&closure.upvalues);
+            }
+            ObjType::Fiber(fiber) => {
+                for &value in &fiber.stack {
+                    gray_value(gray, value);
+                }
+                gray.extend(fiber.frames.iter().map(|frame| frame.closure));
+                gray.extend(&fiber.open_upvalues);
+                gray.extend(fiber.caller);
+                gray.extend(fiber.interrupted);
+                gray_value(gray, fiber.error);
+            }
+            ObjType::Fn(fn_obj) => {
+                for &constant in &fn_obj.constants {
+                    gray_value(gray, constant);
+                }
+                gray.push(fn_obj.module);
+            }
+            ObjType::Instance(instance) => {
+                for &field in &instance.fields {
+                    gray_value(gray, field);
+                }
+            }
+            ObjType::List(list) => {
+                for &element in &list.elements {
+                    gray_value(gray, element);
+                }
+            }
+            ObjType::Map(map) => {
+                for entry in &map.entries {
+                    gray_value(gray, entry.key);
+                    gray_value(gray, entry.value);
+                }
+            }
+            ObjType::Module(module) => {
+                for &variable in &module.variables {
+                    gray_value(gray, variable);
+                }
+                gray.extend(module.name);
+            }
+            ObjType::Upvalue(upvalue) => match upvalue.closed {
+                Some(value) => gray_value(gray, value),
+
+                // An open upvalue's variable lives on its fiber's stack.
+                None => gray.push(upvalue.fiber),
+            },
+            ObjType::Bytes(_) | ObjType::Range(_) | ObjType::String(_) => {}
+        }
+    }
+
+    // Gives every string that was allocated without a class (because it was
+    // created before String was defined) the class [string_class].
+    pub(crate) fn assign_string_class(&mut self, string_class: Option<ObjRef>) {
+        for obj in self.objects.iter_mut().flatten() {
+            if let ObjType::String(_) = obj.ty {
+                obj.class_obj = string_class;
+            }
+        }
+    }
+}
+
+// Adds [value] to the [gray] stack if it is an object.
+fn gray_value(gray: &mut Vec<ObjRef>, value: Value) {
+    if let Value::Obj(obj) = value {
+        gray.push(obj);
+    }
+}
+
+macro_rules! define_accessors {
+    ($($variant:ident, $ty:ty, $is:ident, $as_ref:ident, $as_mut:ident;)*) => {
+        impl Heap {
+            $(
+                #[allow(dead_code)]
+                pub(crate) fn $is(&self, value: Value) -> bool {
+                    match value {
+                        Value::Obj(obj) => matches!(self.get(obj).ty, ObjType::$variant(_)),
+                        _ => false,
+                    }
+                }
+
+                pub(crate) fn $as_ref(&self, obj: ObjRef) -> &$ty {
+                    match &self.get(obj).ty {
+                        ObjType::$variant(inner) => inner,
+                        _ => unreachable!(concat!("Object should be a ", stringify!($variant), ".")),
+                    }
+                }
+
+                #[allow(dead_code)]
+                pub(crate) fn $as_mut(&mut self, obj: ObjRef) -> &mut $ty {
+                    match &mut self.get_mut(obj).ty {
+                        ObjType::$variant(inner) => inner,
+                        _ => unreachable!(concat!("Object should be a ", stringify!($variant), ".")),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+define_accessors! {
+    Bytes, ObjBytes, is_bytes, as_bytes, as_bytes_mut;
+    Class, ObjClass, is_class, as_class, as_class_mut;
+    Closure, ObjClosure, is_closure, as_closure, as_closure_mut;
+    Fiber, ObjFiber, is_fiber, as_fiber, as_fiber_mut;
+    Fn, ObjFn, is_fn, as_fn, as_fn_mut;
+    Instance, ObjInstance, is_instance, as_instance, as_instance_mut;
+    List, ObjList, is_list, as_list, as_list_mut;
+    Map, ObjMap, is_map, as_map, as_map_mut;
+    Module, ObjModule, is_module, as_module, as_module_mut;
+    Range, ObjRange, is_range, as_range, as_range_mut;
+    String, ObjString, is_string, as_string, as_string_mut;
+    Upvalue, ObjUpvalue, is_upvalue, as_upvalue, as_upvalue_mut;
+}
+
+// The maximum percentage of map entries that can be filled before the map is
+// grown. A lower load takes more memory but reduces collisions which makes
+// lookup faster.
+const MAP_LOAD_PERCENT: usize = 75;
+
+// The number of entries allocated when a map is first created.
+const MIN_CAPACITY: usize = 16;
+
+// The rate at which a collection's capacity grows when the size exceeds the
+// current capacity. The new capacity will be determined by *multiplying* the
+// old capacity by this. Growing geometrically is necessary to ensure that
+// adding to a collection has O(1) amortized complexity.
+const GROW_FACTOR: usize = 2;
+
+// Creates a new string object and copies [text] into it.
+pub(crate) fn wren_new_string(vm: &mut WrenVM, text: &[u8]) -> Value {
+    let string = ObjString {
+        value: text.to_vec(),
+        hash: hash_string(vm.hash_basis, text),
+    };
+    Value::Obj(vm.heap.allocate(vm.string_class, ObjType::String(string)))
+}
+
+// Creates a new string by concatenating the contents of [parts].
+pub(crate) fn wren_string_format(vm: &mut WrenVM, parts: &[&[u8]]) -> Value {
+    let text = parts.concat();
+    wren_new_string(vm, &text)
+}
+
+// Creates a new string containing the textual representation of [value].
+pub(crate) fn wren_num_to_string(vm: &mut WrenVM, value: f64) -> Value {
+    // Edge case: If the value is NaN or infinity, handle it ourselves so the
+    // output doesn't depend on how Rust happens to format them.
+    if value.is_nan() {
+        return wren_new_string(vm, b"nan");
+    }
+
+    if value.is_infinite() {
+        let text: &[u8] = if value > 0.0 {
+            b"infinity"
+        } else {
+            b"-infinity"
+        };
+        return wren_new_string(vm, text);
+    }
+
+    // Negative zero keeps its sign, as it does in C, so it prints as "-0".
+    let text = format_double(value);
+    wren_new_string(vm, text.as_bytes())
+}
+
+// Creates a new string containing the code points in [source] starting at
+// byte [start] and stepping [step] bytes at a time for [count] bytes. Bytes that
+// don't begin a UTF-8 sequence are skipped, so the result only ever contains
+// whole code points.
+pub(crate) fn wren_new_string_from_range(
+    vm: &mut WrenVM,
+    source: ObjRef,
+    start: usize,
+    count: usize,
+    step: isize,
+) -> Value {
+    let from = &vm.heap.as_string(source).value;
+    let index = |i: usize| (start as isize + i as isize * step) as usize;
+
+    let length = (0..count)
+        .map(|i| utf8_decode_num_bytes(from[index(i)]))
+        .sum();
+
+    let mut text = Vec::with_capacity(length);
+    for i in 0..count {
+        let code_point = utf8_decode(&from[index(i)..]);
+        if code_point != -1 {
+            utf8_encode(code_point, &mut text);
+        }
+    }
+
+    wren_new_string(vm, &text)
+}
+
+// Returns a new string containing the UTF-8 encoding of the code point that
+// starts at byte [index] in [string]. If that isn't the start of a valid UTF-8
+// sequence, the string just contains the single raw byte.
+pub(crate) fn wren_string_code_point_at(vm: &mut WrenVM, string: ObjRef, index: usize) -> Value {
+    let value = &vm.heap.as_string(string).value;
+    debug_assert!(index < value.len(), "Index out of bounds.");
+
+    let code_point = utf8_decode(&value[index..]);
+    if code_point == -1 {
+        // If it isn't a valid UTF-8 sequence, treat it as a single raw byte.
+        let byte = value[index];
+        return wren_new_string(vm, &[byte]);
+    }
+
+    let mut bytes = Vec::with_capacity(utf8_encode_num_bytes(code_point));
+    utf8_encode(code_point, &mut bytes);
+    wren_new_string(vm, &bytes)
+}
+
+// Searches for [needle] in [haystack] starting at byte offset [start].
+//
+// Returns the byte offset of the first match, or `None` if it wasn't found.
+pub(crate) fn wren_string_find(
+    haystack: &ObjString,
+    needle: &ObjString,
+    start: usize,
+) -> Option<usize> {
+    // Edge case: An empty needle is always found.
+    if needle.value.is_empty() {
+        return Some(start);
+    }
+
+    // If the needle goes past the haystack it won't be found.
+    if start + needle.value.len() > haystack.value.len() {
+        return None;
+    }
+
+    // If the startIndex is too far it also won't be found.
+    if start >= haystack.value.len() {
+        return None;
+    }
+
+    haystack.value[start..]
+        .windows(needle.value.len())
+        .position(|window| window == needle.value.as_slice())
+        .map(|index| start + index)
+}
+
+// Creates a new "raw" class. It has no metaclass or superclass whatsoever.
+// This is only used for bootstrapping the initial Object and Class classes,
+// which are a little special.
+pub(crate) fn wren_new_single_class(vm: &mut WrenVM, num_fields: i32, name: ObjRef) -> ObjRef {
+    let class_obj = ObjClass {
+        superclass: None,
+        num_fields,
+        methods: Vec::new(),
+        name,
+    };
+    vm.heap.allocate(None, ObjType::Class(class_obj))
+}
+
+// Makes [superclass] the superclass of [subclass], and causes subclass to
+// inherit its methods. This should be called before any methods are defined
+// on subclass.
+pub(crate) fn wren_bind_superclass(vm: &mut WrenVM, subclass: ObjRef, superclass: ObjRef) {
+    let (super_fields, methods) = {
+        let superclass = vm.heap.as_class(superclass);
+        (superclass.num_fields, superclass.methods.clone())
+    };
+
+    let subclass = vm.heap.as_class_mut(subclass);
+    subclass.superclass = Some(superclass);
+
+    // Include the superclass in the total number of fields.
+    subclass.num_fields += super_fields;
+
+    // Inherit methods from its superclass.
+    for (symbol, method) in methods.into_iter().enumerate() {
+        if let Some(method) = method {
+            wren_bind_method(subclass, symbol, method);
+        }
+    }
+}
+
+// Creates a new class object as well as its associated metaclass.
+pub(crate) fn wren_new_class(
+    vm: &mut WrenVM,
+    superclass: ObjRef,
+    num_fields: i32,
+    name: ObjRef,
+) -> ObjRef {
+    // Create the metaclass.
+    let name_text = vm.heap.as_string(name).value.clone();
+    let metaclass_name = wren_string_format(vm, &[&name_text, b" metaclass"]).as_obj();
+    let metaclass = wren_new_single_class(vm, 0, metaclass_name);
+    vm.heap.get_mut(metaclass).class_obj = vm.class_class;
+
+    // Metaclasses always inherit Class and do not parallel the non-metaclass
+    // hierarchy.
+    let class_class = vm.class_class.expect("Class should be defined.");
+    wren_bind_superclass(vm, metaclass, class_class);
+
+    let class_obj = wren_new_single_class(vm, num_fields, name);
+    vm.heap.get_mut(class_obj).class_obj = Some(metaclass);
+    wren_bind_superclass(vm, class_obj, superclass);
+
+    class_obj
+}
+
+// Binds [method] to [symbol] in [class_obj], growing its method table if
+// needed.
+pub(crate) fn wren_bind_method(class_obj: &mut ObjClass, symbol: usize, method: Method) {
+    // Make sure the buffer is big enough to contain the symbol's index.
+    if symbol >= class_obj.methods.len() {
+        class_obj.methods.resize(symbol + 1, None);
+    }
+
+    class_obj.methods[symbol] = Some(method);
+}
+
+// Looks up the method for [symbol] on [class_obj], walking up the superclass
+// chain if the class itself has none.
+//
+// Inherited methods are normally copied down when a class is created, so the
+// walk only matters for methods bound on a superclass after its subclasses
+// were created, like the core classes' primitives.
+pub(crate) fn wren_find_method(vm: &WrenVM, class_obj: ObjRef, symbol: usize) -> Option<Method> {
+    let mut class_obj = Some(class_obj);
+    while let Some(current) = class_obj {
+        let current = vm.heap.as_class(current);
+        if let Some(method) = current.methods.get(symbol).copied().flatten() {
+            return Some(method);
+        }
+        class_obj = current.superclass;
+    }
+    None
+}
+
+// Creates a new instance of the given [class_obj].
+pub(crate) fn wren_new_instance(vm: &mut WrenVM, class_obj: ObjRef) -> Value {
+    // Initialize fields to null.
+    let num_fields = vm.heap.as_class(class_obj).num_fields;
+    let instance = ObjInstance {
+        fields: vec![Value::Null; num_fields as usize],
+    };
+    Value::Obj(
+        vm.heap
+            .allocate(Some(class_obj), ObjType::Instance(instance)),
+    )
+}
+
+// Creates a new list with [num_elements] elements, which are all initialized
+// to null.
+pub(crate) fn wren_new_list(vm: &mut WrenVM, num_elements: usize) -> ObjRef {
+    let list = ObjList {
+        elements: vec![Value::Null; num_elements],
+    };
+    vm.heap.allocate(vm.list_class, ObjType::List(list))
+}
+
+// Creates a new byte buffer containing a copy of [bytes].
+pub(crate) fn wren_new_bytes(vm: &mut WrenVM, bytes: &[u8]) -> ObjRef {
+    let bytes = ObjBytes {
+        value: bytes.to_vec(),
+    };
+    vm.heap.allocate(vm.bytes_class, ObjType::Bytes(bytes))
+}
+
+// Creates a new empty map.
+pub(crate) fn wren_new_map(vm: &mut WrenVM) -> ObjRef {
+    vm.heap
+        .allocate(vm.map_class, ObjType::Map(ObjMap::default()))
+}
+
+// Returns true if [value] can be used as a map key. Only the built-in
+// immutable types can be, since a key's hash must never change.
+pub(crate) fn wren_map_is_valid_key(vm: &WrenVM, value: Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Num(_) => true,
+        Value::Obj(_) => {
+            vm.heap.is_class(value) || vm.heap.is_range(value) || vm.heap.is_string(value)
+        }
+        Value::Undefined => false,
+    }
+}
+
+// Creates a new range from [from] to [to].
+pub(crate) fn wren_new_range(vm: &mut WrenVM, from: f64, to: f64, is_inclusive: bool) -> Value {
+    let range = ObjRange {
+        from,
+        to,
+        is_inclusive,
+    };
+    Value::Obj(vm.heap.allocate(vm.range_class, ObjType::Range(range)))
+}
+
+// Creates a new empty function. Before being used, it must have code,
+// constants, etc. added to it.
+pub(crate) fn wren_new_function(vm: &mut WrenVM, fn_obj: ObjFn) -> ObjRef {
+    vm.heap.allocate(vm.fn_class, ObjType::Fn(fn_obj))
+}
+
+// Creates a new closure object that invokes [fn]. Allocates room for its
+// upvalues, but assumes outside code will populate it.
+pub(crate) fn wren_new_closure(vm: &mut WrenVM, fn_obj: ObjRef) -> ObjRef {
+    let upvalues = Vec::with_capacity(vm.heap.as_fn(fn_obj).num_upvalues);
+    let closure = ObjClosure { fn_obj, upvalues };
+    vm.heap.allocate(vm.fn_class, ObjType::Closure(closure))
+}
+
+// Creates a new open upvalue pointing to the variable in [slot] of [fiber]'s
+// stack.
+pub(crate) fn wren_new_upvalue(vm: &mut WrenVM, fiber: ObjRef, slot: usize) -> ObjRef {
+    let upvalue = ObjUpvalue {
+        fiber,
+        slot,
+        closed: None,
+    };
+    vm.heap.allocate(None, ObjType::Upvalue(upvalue))
+}
+
+// Creates a new fiber object that will invoke [closure].
+pub(crate) fn wren_new_fiber(vm: &mut WrenVM, closure: ObjRef) -> ObjRef {
+    let fn_obj = vm.heap.as_closure(closure).fn_obj;
+
+    // Add one slot for the unused implicit receiver slot that the compiler
+    // assumes all functions have.
+    let mut stack = Vec::with_capacity(vm.heap.as_fn(fn_obj).max_slots + 1);
+
+    // The first slot always holds the closure.
+    stack.push(Value::Obj(closure));
+
+    // Initialize the first call frame.
+    let frames = vec![CallFrame {
+        ip: 0,
+        closure,
+        stack_start: 0,
+    }];
+
+    let fiber = ObjFiber {
+        stack,
+        frames,
+        open_upvalues: Vec::new(),
+        caller: None,
+        error: Value::Null,
+        state: FiberState::Other,
+        interrupted: None,
+    };
+    vm.heap.allocate(vm.fiber_class, ObjType::Fiber(fiber))
+}
+
+// Creates a new module.
+pub(crate) fn wren_new_module(vm: &mut WrenVM, name: Option<ObjRef>) -> ObjRef {
+    let module = ObjModule {
+        variables: Vec::new(),
+        variable_names: SymbolTable::default(),
+        name,
+    };
+    vm.heap.allocate(None, ObjType::Module(module))
+}
+
+// The FNV-1a offset basis, which string hashes start from when the host
+// doesn't pick a seed.
+const FNV_OFFSET_BASIS: u32 = 2166136261;
+
+// Returns the value string hashes start from for [seed]. Without one, this is
+// the standard FNV offset basis, so hashes are the same across runs.
+pub(crate) fn wren_hash_basis(seed: Option<u64>) -> u32 {
+    match seed {
+        Some(seed) => hash_bytes(FNV_OFFSET_BASIS, &seed.to_le_bytes()),
+        None => FNV_OFFSET_BASIS,
+    }
+}
+
+// FNV-1a hash, starting from [basis]. See:
+// http://www.isthe.com/chongo/tech/comp/fnv/
+fn hash_string(basis: u32, text: &[u8]) -> u32 {
+    // This is O(n) on the length of the string, but we only call this when a
+    // new string is created. Since the creation is also O(n) (to copy/initialize
+    // all the bytes), we allow this here.
+    hash_bytes(basis, text)
+}
+
+fn hash_bytes(mut hash: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+fn hash_bits(mut hash: u64) -> u32 {
+    // From v8's ComputeLongHash() which in turn cites:
+    // Thomas Wang, Integer Hash Functions.
+    // http://www.concentric.net/~Ttwang/tech/inthash.htm
+    hash = (!hash).wrapping_add(hash << 18); // hash = (hash << 18) - hash - 1;
+    hash ^= hash >> 31;
+    hash = hash.wrapping_mul(21); // hash = (hash + (hash << 2)) + (hash << 4);
+    hash ^= hash >> 11;
+    hash = hash.wrapping_add(hash << 6);
+    hash ^= hash >> 22;
+    (hash & 0x3fffffff) as u32
+}
+
+// Generates a hash code for [num].
+fn hash_number(num: f64) -> u32 {
+    // 0 and -0 are equal but have different bits, so make sure they hash the
+    // same. Otherwise they would land in different map buckets.
+    let num = if num == 0.0 { 0.0 } else { num };
+
+    // Hash the raw bits of the value.
+    hash_bits(num.to_bits())
+}
+
+// Generates a hash code for [object].
+fn hash_object(vm: &WrenVM, object: ObjRef) -> u32 {
+    match &vm.heap.get(object).ty {
+        ObjType::Range(range) => hash_number(range.from) ^ hash_number(range.to),
+        ObjType::String(string) => string.hash,
+        _ => unreachable!("Only immutable objects can be hashed."),
+    }
+}
+
+// Generates a hash code for [value], which must be one of the built-in
+// immutable types: null, bool, class, num, range, or string.
+fn hash_value(vm: &WrenVM, value: Value) -> u32 {
+    match value {
+        Value::Bool(false) => 0,
+        Value::Null => 1,
+        Value::Num(num) => hash_number(num),
+        Value::Bool(true) => 2,
+        Value::Obj(object) => hash_object(vm, object),
+        Value::Undefined => unreachable!(),
+    }
+}
+
+// Looks for an entry with [key] in an array of [entries].
+//
+// If found, returns `Ok` with its index. Otherwise, returns `Err` with the
+// index of the entry where the key/value pair should be inserted.
+fn find_entry(vm: &WrenVM, entries: &[MapEntry], key: Value) -> Result<usize, usize> {
+    let capacity = entries.len();
+
+    // Figure out where to insert it in the table. Use open addressing and
+    // basic linear probing.
+    let start_index = hash_value(vm, key) as usize % capacity;
+    let mut index = start_index;
+
+    // If we pass a tombstone and don't end up finding the key, its entry will
+    // be re-used for the insert.
+    let mut tombstone = None;
+
+    // Walk the probe sequence until we've tried every slot.
+    loop {
+        let entry = &entries[index];
+
+        if entry.key.is_undefined() {
+            // If we found an empty slot, the key is not in the table. If we found a
+            // slot that contains a deleted key, we have to keep looking.
+            if let Value::Bool(false) = entry.value {
+                // We found an empty slot, so we've reached the end of the probe
+                // sequence without finding the key. If we passed a tombstone, then
+                // that's where we should insert the item, otherwise, put it here at
+                // the end of the sequence.
+                return Err(tombstone.unwrap_or(index));
+            } else if tombstone.is_none() {
+                // We found a tombstone. We need to keep looking in case the key is
+                // after it, but we'll use this entry as the insertion point if the
+                // key ends up not being found.
+                tombstone = Some(index);
+            }
+        } else if wren_values_equal(vm, entry.key, key) {
+            // We found the key.
+            return Ok(index);
+        }
+
+        // Try the next slot.
+        index = (index + 1) % capacity;
+        if index == start_index {
+            break;
+        }
+    }
+
+    // If we get here, the table is full of tombstones. Return the first one we
+    // found.
+    Err(tombstone.expect("Map should have tombstones or empty entries."))
+}
+
+// Inserts [key] and [value] in the array of [entries].
+//
+// Returns `true` if this is the first time [key] was added to the map.
+fn insert_entry(vm: &WrenVM, entries: &mut [MapEntry], key: Value, value: Value) -> bool {
+    debug_assert!(
+        !entries.is_empty(),
+        "Should ensure capacity before inserting."
+    );
+
+    match find_entry(vm, entries, key) {
+        Ok(index) => {
+            // Already present, so just replace the value.
+            entries[index].value = value;
+            false
+        }
+        Err(index) => {
+            entries[index] = MapEntry { key, value };
+            true
+        }
+    }
+}
+
+// Updates [map]'s entry array to [capacity].
+fn resize_map(vm: &mut WrenVM, map: ObjRef, capacity: usize) {
+    // Create the new empty hash table.
+    let mut entries = vec![
+        MapEntry {
+            key: Value::Undefined,
+            value: Value::Bool(false),
+        };
+        capacity
+    ];
+
+    // Re-add the existing entries.
+    let old_entries = std::mem::take(&mut vm.heap.as_map_mut(map).entries);
+    for entry in old_entries {
+        // Don't copy empty entries or tombstones.
+        if entry.key.is_undefined() {
+            continue;
+        }
+
+        insert_entry(vm, &mut entries, entry.key, entry.value);
+    }
+
+    // Replace the array.
+    vm.heap.as_map_mut(map).entries = entries;
+}
+
+// Looks up [key] in [map]. If found, returns the value. Otherwise, returns
+// `UNDEFINED_VAL`.
+pub(crate) fn wren_map_get(vm: &WrenVM, map: ObjRef, key: Value) -> Value {
+    let entries = &vm.heap.as_map(map).entries;
+
+    // If there is no entry array (an empty map), we definitely won't find it.
+    if entries.is_empty() {
+        return Value::Undefined;
+    }
+
+    match find_entry(vm, entries, key) {
+        Ok(index) => entries[index].value,
+        Err(_) => Value::Undefined,
+    }
+}
+
+// Associates [key] with [value] in [map].
+pub(crate) fn wren_map_set(vm: &mut WrenVM, map: ObjRef, key: Value, value: Value) {
+    // If the map is getting too full, make room first.
+    let (count, capacity) = {
+        let map = vm.heap.as_map(map);
+        (map.count, map.entries.len())
+    };
+    if count + 1 > capacity * MAP_LOAD_PERCENT / 100 {
+        // Figure out the new hash table size.
+        let capacity = (capacity * GROW_FACTOR).max(MIN_CAPACITY);
+        resize_map(vm, map, capacity);
+    }
+
+    let mut entries = std::mem::take(&mut vm.heap.as_map_mut(map).entries);
+    let added = insert_entry(vm, &mut entries, key, value);
+
+    let map = vm.heap.as_map_mut(map);
+    map.entries = entries;
+    if added {
+        // A new key was added.
+        map.count += 1;
+    }
+}
+
+// Removes [key] from [map], if present. Returns the value for the key if found
+// or `NULL_VAL` otherwise.
+pub(crate) fn wren_map_remove_key(vm: &mut WrenVM, map: ObjRef, key: Value) -> Value {
+    let index = {
+        let entries = &vm.heap.as_map(map).entries;
+        if entries.is_empty() {
+            return Value::Null;
+        }
+        match find_entry(vm, entries, key) {
+            Ok(index) => index,
+            Err(_) => return Value::Null,
+        }
+    };
+
+    // Remove the entry from the map. Set this value to true, which marks it as a
+    // deleted slot. When searching for a key, we will stop on empty slots, but
+    // continue past deleted slots.
+    let map_obj = vm.heap.as_map_mut(map);
+    let value = map_obj.entries[index].value;
+    map_obj.entries[index] = MapEntry {
+        key: Value::Undefined,
+        value: Value::Bool(true),
+    };
+    map_obj.count -= 1;
+
+    let (count, capacity) = (map_obj.count, map_obj.entries.len());
+    if count == 0 {
+        // Removed the last item, so free the array.
+        wren_map_clear(vm, map);
+    } else if capacity > MIN_CAPACITY && count < capacity / GROW_FACTOR * MAP_LOAD_PERCENT / 100 {
+        // The map is getting empty, so shrink the entry array back down.
+        let capacity = (capacity / GROW_FACTOR).max(MIN_CAPACITY);
+        resize_map(vm, map, capacity);
+    }
+
+    value
+}
+
+// Removes all entries from [map].
+pub(crate) fn wren_map_clear(vm: &mut WrenVM, map: ObjRef) {
+    let map = vm.heap.as_map_mut(map);
+    map.entries = Vec::new();
+    map.count = 0;
+}
+
+// Returns true if [a] and [b] are strictly the same value. This is identity
+// for object values, and value equality for unboxed values.
+pub(crate) fn wren_values_same(a: Value, b: Value) -> bool {
+    match (a, b) {
+        (Value::Null, Value::Null) => true,
+        (Value::Undefined, Value::Undefined) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Num(a), Value::Num(b)) => a == b,
+        (Value::Obj(a), Value::Obj(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Returns true if [a] and [b] are equivalent. Immutable values (null, bools,
+// numbers, ranges, and strings) are equal if they have the same data. All
+// other values are equal if they are identical objects.
+pub(crate) fn wren_values_equal(vm: &WrenVM, a: Value, b: Value) -> bool {
+    if wren_values_same(a, b) {
+        return true;
+    }
+
+    // If we get here, it's only possible for two heap-allocated immutable objects
+    // to be equal.
+    let (a, b) = match (a, b) {
+        (Value::Obj(a), Value::Obj(b)) => (a, b),
+        _ => return false,
+    };
+
+    match (&vm.heap.get(a).ty, &vm.heap.get(b).ty) {
+        (ObjType::Range(a), ObjType::Range(b)) => {
+            a.from == b.from && a.to == b.to && a.is_inclusive == b.is_inclusive
+        }
+        (ObjType::String(a), ObjType::String(b)) => a.hash == b.hash && a.value == b.value,
+
+        // All other types are only equal if they are same, which they aren't if
+        // we get here.
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Config;
+
+    fn hash_with_seed(seed: Option<u64>, text: &[u8]) -> u32 {
+        let mut vm = WrenVM::new(Config {
+            hash_seed: seed,
+            ..Config::default()
+        });
+        let string = wren_new_string(&mut vm, text);
+        vm.heap.as_string(string.as_obj()).hash
+    }
+
+    #[test]
+    fn hash_seed_changes_string_hashes() {
+        assert_eq!(hash_with_seed(None, b"key"), hash_with_seed(None, b"key"));
+        assert_eq!(
+            hash_with_seed(Some(1), b"key"),
+            hash_with_seed(Some(1), b"key")
+        );
+        assert_ne!(
+            hash_with_seed(Some(1), b"key"),
+            hash_with_seed(Some(2), b"key")
+        );
+        assert_ne!(
+            hash_with_seed(None, b"key"),
+            hash_with_seed(Some(1), b"key")
+        );
+    }
 }
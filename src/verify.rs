@@ -0,0 +1,290 @@
+use crate::compile::get_byte_count_for_arguments;
+use crate::opcodes::Code;
+use crate::value::{Heap, ObjFn};
+
+// Why [verify_chunk] rejected a function's bytecode.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct VerifyError {
+    // The name of the function with the bad bytecode.
+    pub(crate) function: String,
+
+    // The offset of the bad instruction in the function's bytecode.
+    pub(crate) offset: usize,
+
+    // The source line of the bad instruction, or -1 if it has none.
+    pub(crate) line: i32,
+
+    pub(crate) message: &'static str,
+}
+
+// Checks that the bytecode of [fn_obj], and of every function nested in its
+// constants, is safe for the interpreter to run.
+//
+// The interpreter trusts its bytecode completely, so code that didn't come
+// straight from the compiler could index past the end of the code, the
+// constant table or the stack. This makes sure that every instruction and its
+// arguments are in bounds, every jump lands on an instruction, every constant
+// and upvalue index is valid, no instruction reads more slots than the stack
+// holds along any path to it, and that the code ends in a return.
+pub(crate) fn verify_chunk(heap: &Heap, fn_obj: &ObjFn) -> Result<(), VerifyError> {
+    let error = |offset: usize, message: &'static str| VerifyError {
+        function: fn_obj.debug.name.clone(),
+        offset,
+        line: fn_obj.debug.source_lines.get(offset).copied().unwrap_or(-1),
+        message,
+    };
+
+    let code = &fn_obj.code;
+
+    // The code must end with a return followed by the END pseudo-instruction.
+    // Finding where the instructions start also checks that each one is known
+    // and that its arguments fit in the code.
+    let mut starts = vec![false; code.len()];
+    let mut last = None;
+    let mut ended = false;
+    let mut ip = 0;
+    while ip < code.len() {
+        let instruction = match Code::try_from_byte(code[ip]) {
+            Some(instruction) => instruction,
+            None => return Err(error(ip, "Unknown instruction.")),
+        };
+
+        // The closure's function has to be checked before its arguments can be
+        // counted, since the number of upvalues comes from that function.
+        if instruction == Code::CLOSURE {
+            if ip + 2 >= code.len() {
+                return Err(error(ip, "Instruction runs past the end of the code."));
+            }
+            let constant = read_short(code, ip + 1);
+            match fn_obj.constants.get(constant) {
+                Some(&value) if heap.is_fn(value) => {}
+                Some(_) => return Err(error(ip, "Closure constant must be a function.")),
+                None => return Err(error(ip, "Constant index out of bounds.")),
+            }
+        }
+
+        let next = ip + 1 + get_byte_count_for_arguments(heap, fn_obj, ip);
+        if next > code.len() {
+            return Err(error(ip, "Instruction runs past the end of the code."));
+        }
+
+        starts[ip] = true;
+        if instruction == Code::END {
+            if next != code.len() {
+                return Err(error(ip, "Code continues after the end."));
+            }
+            ended = true;
+            break;
+        }
+        last = Some(instruction);
+        ip = next;
+    }
+
+    if !ended || last != Some(Code::RETURN) {
+        return Err(error(ip, "Code must end in a return."));
+    }
+
+    // Follow every path through the code, tracking how many slots are on the
+    // stack at each instruction. The function starts with the closure or the
+    // receiver in slot zero, followed by its parameters.
+    let mut depths: Vec<Option<i32>> = vec![None; code.len()];
+    let mut pending = vec![(0, fn_obj.arity + 1)];
+    while let Some((ip, depth)) = pending.pop() {
+        match depths[ip] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(error(ip, "Stack depth differs between paths.")),
+            None => depths[ip] = Some(depth),
+        }
+
+        let instruction = Code::from_byte(code[ip]);
+        let next = ip + 1 + get_byte_count_for_arguments(heap, fn_obj, ip);
+        let after = depth + instruction.stack_effect();
+
+        if depth < num_operands(instruction) || after < 0 {
+            return Err(error(ip, "Stack underflow."));
+        }
+
+        match instruction {
+            Code::CONSTANT | Code::IMPORT_MODULE | Code::IMPORT_VARIABLE
+                if read_short(code, ip + 1) >= fn_obj.constants.len() =>
+            {
+                return Err(error(ip, "Constant index out of bounds."));
+            }
+
+            Code::LOAD_MODULE_VAR | Code::STORE_MODULE_VAR => {
+                let module = heap.as_module(fn_obj.module);
+                if read_short(code, ip + 1) >= module.variables.len() {
+                    return Err(error(ip, "Module variable index out of bounds."));
+                }
+            }
+
+            Code::LOAD_LOCAL_0
+            | Code::LOAD_LOCAL_1
+            | Code::LOAD_LOCAL_2
+            | Code::LOAD_LOCAL_3
+            | Code::LOAD_LOCAL_4
+            | Code::LOAD_LOCAL_5
+            | Code::LOAD_LOCAL_6
+            | Code::LOAD_LOCAL_7
+            | Code::LOAD_LOCAL_8 => {
+                let slot = instruction as i32 - Code::LOAD_LOCAL_0 as i32;
+                if slot >= depth {
+                    return Err(error(ip, "Local slot out of bounds."));
+                }
+            }
+
+            Code::LOAD_LOCAL | Code::STORE_LOCAL if code[ip + 1] as i32 >= depth => {
+                return Err(error(ip, "Local slot out of bounds."));
+            }
+
+            Code::LOAD_UPVALUE | Code::STORE_UPVALUE
+                if code[ip + 1] as usize >= fn_obj.num_upvalues =>
+            {
+                return Err(error(ip, "Upvalue index out of bounds."));
+            }
+
+            Code::CLOSURE => {
+                // Each upvalue captures either a local of this function or one of
+                // its upvalues.
+                let mut argument = ip + 3;
+                while argument < next {
+                    let is_local = code[argument] != 0;
+                    let index = code[argument + 1] as usize;
+                    if is_local && index as i32 >= depth {
+                        return Err(error(ip, "Local slot out of bounds."));
+                    }
+                    if !is_local && index >= fn_obj.num_upvalues {
+                        return Err(error(ip, "Upvalue index out of bounds."));
+                    }
+                    argument += 2;
+                }
+            }
+
+            _ => {}
+        }
+
+        let mut jump_to = |target: Option<usize>, depth: i32| match target {
+            Some(target) if target < code.len() && starts[target] => {
+                pending.push((target, depth));
+                Ok(())
+            }
+            _ => Err(error(ip, "Jump target out of bounds.")),
+        };
+
+        let offset = || read_short(code, ip + 1);
+        match instruction {
+            Code::JUMP => jump_to(next.checked_add(offset()), after)?,
+            Code::LOOP => jump_to(next.checked_sub(offset()), after)?,
+
+            Code::JUMP_IF => {
+                jump_to(next.checked_add(offset()), after)?;
+                jump_to(Some(next), after)?;
+            }
+
+            // These leave the value they test on the stack when they jump.
+            Code::JUMP_IF_NULL | Code::AND | Code::OR => {
+                jump_to(next.checked_add(offset()), depth)?;
+                jump_to(Some(next), after)?;
+            }
+
+            Code::RETURN => {}
+
+            // The END pseudo-instruction is never executed, so something must
+            // have fallen through into it.
+            Code::END => return Err(error(ip, "Code must end in a return.")),
+
+            _ => jump_to(Some(next), after)?,
+        }
+    }
+
+    for &constant in &fn_obj.constants {
+        if heap.is_fn(constant) {
+            verify_chunk(heap, heap.as_fn(constant.as_obj()))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Reads the big-endian 16-bit argument at [ip] in [code].
+fn read_short(code: &[u8], ip: usize) -> usize {
+    ((code[ip] as usize) << 8) | code[ip + 1] as usize
+}
+
+// The number of stack slots [instruction] reads, which must all be on the
+// stack before it runs.
+fn num_operands(instruction: Code) -> i32 {
+    match instruction {
+        Code::CALL_0 | Code::SUPER_0 => 1,
+        Code::CALL_1 | Code::SUPER_1 => 2,
+        Code::CALL_2 | Code::SUPER_2 => 3,
+        Code::CALL_3 | Code::SUPER_3 => 4,
+        Code::CALL_4 | Code::SUPER_4 => 5,
+        Code::CALL_5 | Code::SUPER_5 => 6,
+        Code::CALL_6 | Code::SUPER_6 => 7,
+        Code::CALL_7 | Code::SUPER_7 => 8,
+        Code::CALL_8 | Code::SUPER_8 => 9,
+        Code::CALL_9 | Code::SUPER_9 => 10,
+        Code::CALL_10 | Code::SUPER_10 => 11,
+        Code::CALL_11 | Code::SUPER_11 => 12,
+        Code::CALL_12 | Code::SUPER_12 => 13,
+        Code::CALL_13 | Code::SUPER_13 => 14,
+        Code::CALL_14 | Code::SUPER_14 => 15,
+        Code::CALL_15 | Code::SUPER_15 => 16,
+        Code::CALL_16 | Code::SUPER_16 => 17,
+
+        Code::STORE_FIELD
+        | Code::CLASS
+        | Code::FOREIGN_CLASS
+        | Code::END_CLASS
+        | Code::METHOD_INSTANCE
+        | Code::METHOD_STATIC => 2,
+
+        Code::STORE_LOCAL
+        | Code::STORE_UPVALUE
+        | Code::STORE_MODULE_VAR
+        | Code::STORE_FIELD_THIS
+        | Code::LOAD_FIELD
+        | Code::POP
+        | Code::JUMP_IF
+        | Code::JUMP_IF_NULL
+        | Code::AND
+        | Code::OR
+        | Code::CLOSE_UPVALUE
+        | Code::RETURN => 1,
+
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::wren_compile;
+    use crate::value::wren_new_module;
+    use crate::vm::{Config, WrenVM};
+
+    #[test]
+    fn jump_past_the_end_is_rejected() {
+        let mut vm = WrenVM::new(Config::default());
+        let module = wren_new_module(&mut vm, None);
+        let fn_obj = wren_compile(&mut vm, module, b"var a = 1\nif (a) a = 2\n", false, false)
+            .expect("Source should compile.");
+        assert_eq!(verify_chunk(&vm.heap, vm.heap.as_fn(fn_obj)), Ok(()));
+
+        // Point the if statement's jump far past the end of the code.
+        let code = &vm.heap.as_fn(fn_obj).code;
+        let mut ip = 0;
+        while Code::from_byte(code[ip]) != Code::JUMP_IF {
+            ip += 1 + get_byte_count_for_arguments(&vm.heap, vm.heap.as_fn(fn_obj), ip);
+        }
+        let code = &mut vm.heap.as_fn_mut(fn_obj).code;
+        code[ip + 1] = 0xff;
+        code[ip + 2] = 0xff;
+
+        let error = verify_chunk(&vm.heap, vm.heap.as_fn(fn_obj)).unwrap_err();
+        assert_eq!(error.offset, ip);
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "Jump target out of bounds.");
+    }
+}
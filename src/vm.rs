@@ -1,7 +1,2292 @@
-const WREN_VERSION_NUMBER: &'static str = env!("CARGO_PKG_VERSION");
+use std::time::Instant;
+
+use crate::common::{MAX_FIELDS, MAX_MODULE_VARS, MAX_PARAMETERS};
+use crate::compile::{wren_bind_method_code, wren_compile, wren_tokenize, Tokens, MAX_NESTING};
+use crate::core::wren_initialize_core;
+use crate::debug::{
+    wren_debug_error_message, wren_debug_print_stack_trace, wren_debug_stack_trace,
+};
+use crate::opcodes::Code;
+use crate::utils::SymbolTable;
+use crate::value::*;
+use crate::verify::verify_chunk;
+
+const WREN_VERSION_NUMBER: &str = env!("CARGO_PKG_VERSION");
 
 pub fn wren_get_version_number() -> &'static str {
-    return WREN_VERSION_NUMBER;
+    WREN_VERSION_NUMBER
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorType {
+    // A syntax or resolution error detected at compile time.
+    Compile,
+
+    // The error message for a runtime error.
+    Runtime,
+
+    // One entry of a runtime error's stack trace.
+    StackTrace,
+
+    // A problem detected at compile time that does not prevent the code from
+    // running, like unreachable code.
+    Warning,
+
+    // The source line a compile error or warning points at, with a row of
+    // carets under the offending code.
+    Snippet,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterpretResult {
+    Success,
+    CompileError,
+    RuntimeError,
+
+    // Execution ran out of its instruction budget before finishing.
+    Budget,
+
+    // A fiber called `Fiber.suspend()`. It waits, along with any fibers that
+    // called it, until the host continues it with [WrenVM::resume_suspended].
+    Suspended,
+}
+
+// A value copied out of the VM for the host.
+//
+// Unlike the VM's own values, this owns its data, so it stays valid no matter
+// what the VM does afterwards.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WrenValue {
+    Null,
+    Bool(bool),
+    Num(f64),
+    String(String),
+
+    // Any other object, described by the name of its class.
+    Object(String),
+}
+
+// A reference to a Wren object that the host holds on to.
+//
+// The object stays alive, even if nothing in the VM refers to it anymore,
+// until the handle is given back with [WrenVM::release_handle].
+#[derive(Debug)]
+pub struct WrenHandle {
+    // Index of the handle's value in the VM's [handles].
+    index: usize,
+}
+
+// One entry of a runtime error's stack trace, innermost call first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackFrame {
+    // The resolved name of the module the function is in.
+    pub module: String,
+
+    // The line the function was executing when the error happened.
+    pub line: i32,
+
+    // The name of the function, like the method's signature.
+    pub function: String,
+}
+
+// A runtime error that aborted code the host called into.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    // The value the fiber was aborted with, usually a string message.
+    pub error: WrenValue,
+
+    // Where the fiber that raised the error was when it did. This is empty when
+    // the call failed before any Wren code ran.
+    pub stack_trace: Vec<StackFrame>,
+}
+
+// How a fiber run by [WrenVM::resume] handed control back to the host.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FiberResult {
+    // The fiber yielded this value and can be resumed again.
+    Yielded(WrenValue),
+
+    // The fiber's function returned this value.
+    Completed(WrenValue),
+
+    // The fiber was aborted with this error, usually a string message.
+    Errored(WrenValue),
+
+    // The fiber ran out of instruction budget before handing control back.
+    Budget,
+
+    // The fiber, or one it called, was suspended with `Fiber.suspend()`.
+    Suspended,
+}
+
+// The [InterpretResult] for a host call that ran a fiber and got [result].
+fn interpret_result(result: &FiberResult) -> InterpretResult {
+    match result {
+        FiberResult::Errored(_) => InterpretResult::RuntimeError,
+        FiberResult::Budget => InterpretResult::Budget,
+        FiberResult::Suspended => InterpretResult::Suspended,
+        FiberResult::Yielded(_) | FiberResult::Completed(_) => InterpretResult::Success,
+    }
+}
+
+// Displays a string of text to the user.
+pub type WriteFn = Box<dyn FnMut(&mut WrenVM, &str)>;
+
+// Receives a string of text printed by Wren code, to display, rewrite or send
+// elsewhere as the host likes.
+pub type PrintFn = Box<dyn FnMut(&str)>;
+
+// Observes the VM's memory management.
+//
+// This is called once with an [old_size] of zero and the object's [new_size]
+// each time the VM allocates an object, and once with the object's
+// [old_size] and a [new_size] of zero each time the garbage collector frees
+// one.
+//
+// Unlike C Wren's `reallocateFn`, this can't supply the memory: objects are
+// ordinary Rust values and always come from the global allocator. A host that
+// needs a custom allocator has to install it as the `#[global_allocator]`.
+pub type AllocationFn = Box<dyn FnMut(usize, usize)>;
+
+// Reports an error to the user.
+//
+// An error detected during compile time is reported by calling this once with
+// [ty] `Compile`, the resolved name of the [module] and [line] where the error
+// occurs, and the compiler's error [message]. Warnings are reported the same
+// way with [ty] `Warning`.
+//
+// When the error or warning points at some code, it is followed by a call with
+// [ty] `Snippet`, the same [module] and [line], and a [message] of two lines:
+// the source line, with tabs expanded to [Config::tab_width] spaces, and a row
+// of carets under the offending code. Hosts that show no source can ignore
+// these.
+//
+// A runtime error is reported by calling this once with [ty] `Runtime`, no
+// [module] or [line], and the runtime error's [message]. After that, a series
+// of [ty] `StackTrace` calls are made for each line in the stack trace. Each
+// of those has the resolved [module] and [line] where the method or function
+// is defined and [message] is the name of the method or function.
+pub type ErrorFn = Box<dyn FnMut(&mut WrenVM, ErrorType, &str, i32, &str)>;
+
+// Gives the host a chance to canonicalize the imported module [name],
+// potentially taking into account the (previously resolved) name of the
+// [importer] module that contains the import. Typically, this is used to
+// implement relative imports.
+pub type ResolveModuleFn = Box<dyn FnMut(&mut WrenVM, &str, &str) -> Option<String>>;
+
+// Loads and returns the source code for the module [name].
+pub type LoadModuleFn = Box<dyn FnMut(&mut WrenVM, &str) -> Option<String>>;
+
+// A function callable from Wren code, but implemented in Rust.
+//
+// It reads the receiver and arguments from slots zero and up, and stores its
+// return value in slot zero. To fail instead, it stores the error, usually a
+// string message, in a slot and passes that slot to [WrenVM::abort_fiber]
+// before returning. Whatever it left in slot zero is then ignored.
+pub type ForeignMethodFn = fn(&mut WrenVM);
+
+// Returns a pointer to a foreign method on [class_name] in [module] with
+// [signature].
+pub type BindForeignMethodFn =
+    Box<dyn FnMut(&mut WrenVM, &str, &str, bool, &str) -> Option<ForeignMethodFn>>;
+
+// How strings the host builds from raw bytes are checked for UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringValidation {
+    // The bytes are stored as they are. Like Wren itself, strings may hold any
+    // sequence of bytes.
+    Bytes,
+
+    // Invalid UTF-8 aborts the current fiber with a runtime error.
+    Strict,
+
+    // Each invalid sequence is replaced with U+FFFD REPLACEMENT CHARACTER.
+    Lossy,
+}
+
+pub struct Config {
+    // The callback Wren will use to report allocating and freeing objects. See
+    // [AllocationFn] for why it only observes them.
+    //
+    // If this is `None`, Wren still keeps count of the bytes it has allocated
+    // but nothing is notified.
+    pub allocation_fn: Option<AllocationFn>,
+
+    // The number of bytes Wren will allocate before triggering the first garbage
+    // collection.
+    //
+    // Defaults to 10MB.
+    pub initial_heap_size: usize,
+
+    // After a collection occurs, the threshold for the next collection is
+    // determined based on the number of bytes remaining in use. This allows Wren
+    // to shrink its memory usage automatically after reclaiming a large amount
+    // of memory.
+    //
+    // This can be used to ensure that the heap does not get too small, which can
+    // in turn lead to a large number of collections afterwards as the heap grows
+    // back to a usable size.
+    //
+    // Defaults to 1MB.
+    pub min_heap_size: usize,
+
+    // Wren will resize the heap automatically as the number of bytes remaining
+    // in use after a collection changes. This number determines the amount of
+    // additional memory Wren will use after a collection, as a percentage of the
+    // current heap size.
+    //
+    // For example, say that this is 50. After a garbage collection, when there
+    // are 400 bytes of memory still in use, the next collection will be
+    // triggered after a total of 600 bytes are allocated (including the 400
+    // already in use.)
+    //
+    // Setting this to a smaller number wastes less memory, but triggers more
+    // frequent garbage collections.
+    //
+    // Defaults to 50.
+    pub heap_growth_percent: usize,
+
+    // The callback Wren uses to display text when `System.print()` or the other
+    // related functions are called.
+    //
+    // If this is `None`, Wren discards any printed text.
+    pub write_fn: Option<WriteFn>,
+
+    // Whether printed text is collected and handed to [write_fn] in one go
+    // instead of as soon as it's printed.
+    //
+    // Buffered text is written when `System.flush()` or [WrenVM::flush] is
+    // called, and when the VM is dropped. Defaults to `false`.
+    pub buffer_output: bool,
+
+    // The callback Wren uses instead of [write_fn] for text printed by
+    // `System.print()` and the other related functions.
+    //
+    // It is called with each piece of text as soon as it's written, so
+    // `System.print("hi")` calls it once with "hi" and once with "\n". When this
+    // is set, printed text is never buffered or passed to [write_fn].
+    pub print_fn: Option<PrintFn>,
+
+    // The callback Wren uses to report errors.
+    //
+    // When an error occurs, this will be called with the module name, line
+    // number, and an error message. If this is `None`, Wren doesn't report any
+    // errors.
+    pub error_fn: Option<ErrorFn>,
+
+    // The callback Wren uses to resolve a module name.
+    //
+    // Some host applications may wish to support "relative" imports, where the
+    // meaning of an import string depends on the module that contains it. To
+    // support that without baking any policy into Wren itself, the VM gives the
+    // host a chance to resolve an import string.
+    //
+    // Before an import is loaded, it calls this, passing in the name of the
+    // module that contains the import and the import string. The host app can
+    // look at both of those and produce a new "canonical" string that uniquely
+    // identifies the module. This string is then used as the name of the module
+    // going forward. It is what is passed to [load_module_fn], how duplicate
+    // imports of the same module are detected, and how the module is reported
+    // in stack traces.
+    //
+    // If this is `None`, the original import string is treated as the resolved
+    // string. If an import cannot be resolved by the host, it should return
+    // `None` and Wren will report that as a runtime error.
+    pub resolve_module_fn: Option<ResolveModuleFn>,
+
+    // The callback Wren uses to load a module.
+    //
+    // Since Wren does not talk directly to the file system, it relies on the
+    // host to physically locate and read the source code for a module. The
+    // first time an import appears, Wren will call this and pass in the
+    // resolved name of the module being imported. It should return the source
+    // code for that module.
+    //
+    // This will only be called once for any given module name. Wren caches the
+    // result internally so subsequent imports of the same module will use the
+    // previous source and not request it again.
+    //
+    // If a module with the given name could not be found by the host, it should
+    // return `None` and Wren will report that as a runtime error.
+    pub load_module_fn: Option<LoadModuleFn>,
+
+    // The callback Wren uses to find a foreign method and bind it to a class.
+    //
+    // When a foreign method is declared in a class, this will be called with the
+    // module name, class name, whether the method is static, and the method's
+    // signature. It should return the function that implements it. If it returns
+    // `None`, or this is `None`, defining the class aborts with a runtime error.
+    pub bind_foreign_method_fn: Option<BindForeignMethodFn>,
+
+    // The maximum number of call frames a fiber can have on its stack at once.
+    //
+    // Calling a function or method when the fiber is already this deep aborts
+    // it with a "Stack overflow." runtime error instead of letting runaway
+    // recursion use up all of the host's memory.
+    //
+    // Defaults to 65536.
+    pub max_call_depth: usize,
+
+    // The maximum number of bytecode instructions a single call to the
+    // interpreter may execute.
+    //
+    // When the budget runs out, execution stops with `InterpretResult::Budget`
+    // and the running fiber is left where it stopped. The host can get it from
+    // [WrenVM::take_interrupted_fiber] and continue it with a fresh budget with
+    // [WrenVM::resume]. This is useful for running untrusted scripts that might
+    // never finish. If this is `None`, there is no limit.
+    pub instruction_budget: Option<u64>,
+
+    // How many columns a tab counts as when compile errors quote the source
+    // line they're on and underline the offending code.
+    //
+    // Defaults to 1.
+    pub tab_width: usize,
+
+    // How [WrenVM::set_slot_bytes] treats bytes that aren't valid UTF-8.
+    //
+    // Defaults to `StringValidation::Bytes`.
+    pub string_validation: StringValidation,
+
+    // A seed mixed into the hash of every string, so that scripts can't predict
+    // which string map keys collide.
+    //
+    // Servers running untrusted scripts should pass a random value. If this is
+    // `None`, a fixed seed is used and hashes are the same on every run.
+    pub hash_seed: Option<u64>,
+
+    // Whether to check the bytecode of newly compiled code before running it.
+    //
+    // Code with jumps, constants, locals or upvalues out of bounds, that pops
+    // more than is on the stack, or that doesn't end in a return is reported
+    // as a compile error instead of being run. The compiler never produces such
+    // code, so this guards against bugs in it and, once bytecode can be loaded
+    // from elsewhere, against code it didn't produce. Defaults to `false`.
+    pub verify_bytecode: bool,
+
+    // The maximum length in bytes of the source code compiled at once, such as
+    // the source passed to [WrenVM::interpret] or an imported module.
+    //
+    // Longer source is reported as a "Source too large." compile error without
+    // being lexed. If this is `None`, there is no limit.
+    pub max_source_length: Option<usize>,
+
+    // How deeply expressions and statements can nest inside each other.
+    //
+    // The compiler is recursive, so deeper nesting is reported as an
+    // "Expression nesting too deep." compile error instead of overflowing the
+    // native stack. Raising this much past the default needs a larger stack.
+    //
+    // Defaults to 256.
+    pub max_nesting_depth: usize,
+}
+
+impl Default for Config {
+    // Initializes a configuration with all of its default values.
+    fn default() -> Config {
+        Config {
+            allocation_fn: None,
+            initial_heap_size: 1024 * 1024 * 10,
+            min_heap_size: 1024 * 1024,
+            heap_growth_percent: 50,
+            write_fn: None,
+            buffer_output: false,
+            print_fn: None,
+            error_fn: None,
+            resolve_module_fn: None,
+            load_module_fn: None,
+            bind_foreign_method_fn: None,
+            max_call_depth: 65536,
+            instruction_budget: None,
+            tab_width: 1,
+            string_validation: StringValidation::Bytes,
+            hash_seed: None,
+            verify_bytecode: false,
+            max_source_length: None,
+            max_nesting_depth: MAX_NESTING,
+        }
+    }
+}
+
+pub struct WrenVM {
+    pub(crate) bool_class: Option<ObjRef>,
+    pub(crate) bytes_class: Option<ObjRef>,
+    pub(crate) class_class: Option<ObjRef>,
+    pub(crate) fiber_class: Option<ObjRef>,
+    pub(crate) fn_class: Option<ObjRef>,
+    pub(crate) list_class: Option<ObjRef>,
+    pub(crate) map_class: Option<ObjRef>,
+    pub(crate) null_class: Option<ObjRef>,
+    pub(crate) num_class: Option<ObjRef>,
+    pub(crate) object_class: Option<ObjRef>,
+    pub(crate) range_class: Option<ObjRef>,
+    pub(crate) string_class: Option<ObjRef>,
+
+    pub(crate) heap: Heap,
+
+    pub(crate) config: Config,
+
+    // Text printed since the last flush when [Config::buffer_output] is on.
+    output: String,
+
+    // The value every string hash starts from, derived from the configured seed.
+    pub(crate) hash_basis: u32,
+
+    // The loaded modules. Each key is an ObjString (except for the implicit "core"
+    // module, whose key is null) for the module's name and the value is the ObjModule
+    // for the module.
+    pub(crate) modules: ObjRef,
+
+    // The most recently imported module. More specifically, the module whose
+    // code has most recently finished executing.
+    //
+    // Not treated like a GC root since the module is already in [modules].
+    last_module: Option<ObjRef>,
+
+    // The method names of every method defined by any class. Since Wren
+    // methods are dispatched by name, the compiler and VM refer to each
+    // signature by its index in this table.
+    pub(crate) method_names: SymbolTable,
+
+    // The fiber that is currently running.
+    pub(crate) fiber: Option<ObjRef>,
+
+    // The fiber that called `Fiber.suspend()` and is waiting for the host to
+    // resume it, if any.
+    pub(crate) suspended: Option<ObjRef>,
+
+    // The fiber the host most recently ran that ran out of instruction budget,
+    // until the host takes it with [take_interrupted_fiber].
+    interrupted: Option<ObjRef>,
+
+    // During a foreign method call, this is the index of the slot on the running
+    // fiber's stack where the foreign method's receiver and arguments start.
+    // Outside of one, this is `None`.
+    api_stack: Option<usize>,
+
+    // When the VM was created. `System.clock` counts seconds from here.
+    pub(crate) start_time: Instant,
+
+    // The value returned by the last fiber the host ran to completion, or
+    // `None` if the last one didn't complete.
+    last_value: Option<Value>,
+
+    // The message and stack trace of the last runtime error that nothing
+    // caught, or `None` and empty if the last code the host ran didn't abort.
+    last_error: Option<String>,
+    last_trace: Vec<StackFrame>,
+
+    // The values of the host's [WrenHandle]s, indexed by handle. Released handles
+    // leave a `None` behind, which is reused by the next new handle.
+    handles: Vec<Option<Value>>,
+
+    // The number of bytes that are allocated before the next garbage collection
+    // is triggered.
+    next_gc: usize,
+}
+
+impl WrenVM {
+    pub fn new(mut config: Config) -> WrenVM {
+        let mut heap = Heap::new(config.allocation_fn.take());
+        let modules = heap.allocate(None, ObjType::Map(ObjMap::default()));
+
+        let mut vm = WrenVM {
+            bool_class: None,
+            bytes_class: None,
+            class_class: None,
+            fiber_class: None,
+            fn_class: None,
+            list_class: None,
+            map_class: None,
+            null_class: None,
+            num_class: None,
+            object_class: None,
+            range_class: None,
+            string_class: None,
+            heap,
+            output: String::new(),
+            hash_basis: wren_hash_basis(config.hash_seed),
+            config,
+            modules,
+            last_module: None,
+            method_names: SymbolTable::default(),
+            fiber: None,
+            suspended: None,
+            interrupted: None,
+            api_stack: None,
+            start_time: Instant::now(),
+            last_value: None,
+            last_error: None,
+            last_trace: Vec::new(),
+            handles: Vec::new(),
+            next_gc: 0,
+        };
+        vm.next_gc = vm.config.initial_heap_size;
+
+        wren_initialize_core(&mut vm);
+        vm
+    }
+
+    // Runs [source], a string of Wren source code in a new fiber in the VM in
+    // the context of resolved [module].
+    pub fn interpret(&mut self, module: &str, source: &str) -> InterpretResult {
+        self.last_value = None;
+        self.clear_last_error();
+
+        let closure = match self.compile_source(Some(module), source.as_bytes(), false, true) {
+            Some(closure) => closure,
+            None => return InterpretResult::CompileError,
+        };
+
+        let fiber = wren_new_fiber(self, closure);
+        let result = self.run_host_fiber(fiber, fiber);
+        interpret_result(&result)
+    }
+
+    // Runs [source] in the already loaded [module], so that it sees the
+    // variables the module's earlier code defined and can define more of its
+    // own. Top-level variables that already exist can be assigned but not
+    // declared again.
+    //
+    // Unlike [interpret], this never creates a module. If [module] hasn't been
+    // loaded, nothing is compiled and that is reported as a runtime error.
+    pub fn eval_in_module(&mut self, module: &str, source: &str) -> InterpretResult {
+        if !self.has_module(module) {
+            self.last_value = None;
+            self.clear_last_error();
+
+            let message = format!("Module '{}' is not loaded.", module);
+            self.report_error(ErrorType::Runtime, "", -1, &message);
+            self.last_error = Some(message);
+            return InterpretResult::RuntimeError;
+        }
+
+        self.interpret(module, source)
+    }
+
+    // Returns the value that the code last run by the host finished with. For a
+    // module, that's the value of a top-level `return` statement, or null if it
+    // has none.
+    //
+    // Returns `None` if nothing has run yet, or if the last run didn't finish
+    // because of an error, yield or exhausted budget.
+    pub fn last_value(&self) -> Option<WrenValue> {
+        self.last_value.map(|value| self.to_wren_value(value))
+    }
+
+    // Looks up the top-level variable [name] in resolved [module] and returns a
+    // handle to its value.
+    //
+    // Returns `None` if the module hasn't been loaded or has no such variable.
+    pub fn get_variable(&mut self, module: &str, name: &str) -> Option<WrenHandle> {
+        let module_name = wren_new_string(self, module.as_bytes());
+        let module = self.get_module(module_name)?;
+
+        let module = self.heap.as_module(module);
+        let symbol = module.variable_names.find(name);
+        if symbol == -1 {
+            return None;
+        }
+        let value = module.variables[symbol as usize];
+        Some(self.make_handle(value))
+    }
+
+    // Gives [handle] back to the VM, letting the garbage collector free its
+    // value once nothing else refers to it.
+    pub fn release_handle(&mut self, handle: WrenHandle) {
+        self.handles[handle.index] = None;
+    }
+
+    // Calls the function that [handle] refers to with [args] and runs it to
+    // completion, returning what it returns. If it yields to the host instead,
+    // this returns the yielded value and the rest of the function never runs.
+    // If it suspends, this returns null and [resume_suspended] continues it.
+    //
+    // Extra arguments are discarded, like with `Fn.call`. Only null, bool,
+    // number and string arguments can be passed.
+    pub fn call_function(
+        &mut self,
+        handle: &WrenHandle,
+        args: &[WrenValue],
+    ) -> Result<WrenValue, RuntimeError> {
+        self.last_value = None;
+        self.clear_last_error();
+
+        let function = self.handles[handle.index].expect("Handle should not be released.");
+        if !self.heap.is_closure(function) {
+            return Err(self.host_error("Handle must hold a function."));
+        }
+
+        let closure = function.as_obj();
+        let arity = self.heap.as_fn(self.heap.as_closure(closure).fn_obj).arity as usize;
+        if args.len() < arity {
+            return Err(self.host_error("Function expects more arguments."));
+        }
+
+        let mut values = Vec::with_capacity(arity);
+        for arg in &args[..arity] {
+            match self.copy_wren_value(arg) {
+                Some(value) => values.push(value),
+                None => return Err(self.host_error("Cannot pass an object to a function.")),
+            }
+        }
+
+        // The function's parameters go right after the closure in its first slot.
+        let fiber = wren_new_fiber(self, closure);
+        self.heap.as_fiber_mut(fiber).stack.extend(values);
+
+        match self.run_host_fiber(fiber, fiber) {
+            FiberResult::Errored(error) => Err(RuntimeError {
+                error,
+                stack_trace: self.last_trace.clone(),
+            }),
+            FiberResult::Budget => Err(self.host_error("Out of instruction budget.")),
+            FiberResult::Suspended => Ok(WrenValue::Null),
+            FiberResult::Completed(value) | FiberResult::Yielded(value) => Ok(value),
+        }
+    }
+
+    // Runs the fiber that [fiber] refers to until it yields back to the host,
+    // suspends, returns, aborts or runs out of instruction budget. This lets the
+    // host drive fibers it got from Wren code, like a generator or a scheduler
+    // would.
+    //
+    // The first time a fiber runs, [value] is passed to its function if that
+    // takes a parameter. After that, [value] becomes the result of the
+    // `Fiber.yield` call the fiber is waiting in. Only null, bool, number and
+    // string values can be passed. If the fiber ran out of instruction budget,
+    // it carries on from exactly where it stopped and [value] is ignored.
+    pub fn resume(&mut self, fiber: &WrenHandle, value: WrenValue) -> FiberResult {
+        self.last_value = None;
+        self.clear_last_error();
+
+        let fiber = self.handles[fiber.index].expect("Handle should not be released.");
+        if !self.heap.is_fiber(fiber) {
+            return FiberResult::Errored(WrenValue::String("Handle must hold a fiber.".into()));
+        }
+        let fiber = fiber.as_obj();
+
+        let fiber_obj = self.heap.as_fiber(fiber);
+        if !matches!(fiber_obj.error, Value::Null) {
+            return FiberResult::Errored(self.to_wren_value(fiber_obj.error));
+        }
+
+        if fiber_obj.frames.is_empty() {
+            let error = WrenValue::String("Cannot resume a finished fiber.".into());
+            return FiberResult::Errored(error);
+        }
+
+        // A fiber that another fiber ran is waiting for that one to finish.
+        if fiber_obj.caller.is_some() {
+            let error = WrenValue::String("Fiber has already been called.".into());
+            return FiberResult::Errored(error);
+        }
+
+        let value = match self.copy_wren_value(&value) {
+            Some(value) => value,
+            None => {
+                let error = WrenValue::String("Cannot pass an object to a fiber.".into());
+                return FiberResult::Errored(error);
+            }
+        };
+
+        match self.heap.as_fiber_mut(fiber).interrupted.take() {
+            Some(running) => self.run_host_fiber(fiber, running),
+            None => {
+                self.send_to_fiber(fiber, value);
+                self.run_host_fiber(fiber, fiber)
+            }
+        }
+    }
+
+    // Returns a handle to the fiber that most recently ran out of instruction
+    // budget, so that [resume] can continue it. That's the fiber the host ran,
+    // such as the one [interpret] made for its module. Returns `None` if no
+    // fiber has run out since the last call.
+    pub fn take_interrupted_fiber(&mut self) -> Option<WrenHandle> {
+        let fiber = self.interrupted.take()?;
+        Some(self.make_handle(Value::Obj(fiber)))
+    }
+
+    // Continues the fiber that suspended itself with `Fiber.suspend()`, making
+    // the `suspend()` call return null. When it finishes, the fiber that called
+    // it carries on, and so on, just as if it had never stopped.
+    //
+    // If no fiber is suspended, this reports a runtime error.
+    pub fn resume_suspended(&mut self) -> InterpretResult {
+        self.last_value = None;
+        self.clear_last_error();
+
+        let fiber = match self.suspended.take() {
+            Some(fiber) => fiber,
+            None => {
+                let message = "No fiber is suspended.";
+                self.report_error(ErrorType::Runtime, "", -1, message);
+                self.last_error = Some(message.to_string());
+                return InterpretResult::RuntimeError;
+            }
+        };
+
+        // The run is finished once the outermost fiber waiting on this one is.
+        let mut root = fiber;
+        while let Some(caller) = self.heap.as_fiber(root).caller {
+            root = caller;
+        }
+
+        let result = self.run_host_fiber(root, fiber);
+        interpret_result(&result)
+    }
+
+    // Returns `true` if a fiber is waiting in `Fiber.suspend()` for the host to
+    // call [resume_suspended].
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    // Stores [value] in a new handle.
+    fn make_handle(&mut self, value: Value) -> WrenHandle {
+        let index = match self.handles.iter().position(Option::is_none) {
+            Some(index) => {
+                self.handles[index] = Some(value);
+                index
+            }
+            None => {
+                self.handles.push(Some(value));
+                self.handles.len() - 1
+            }
+        };
+        WrenHandle { index }
+    }
+
+    // Creates a [RuntimeError] for a host call that failed before any Wren code
+    // ran.
+    fn host_error(&self, message: &str) -> RuntimeError {
+        RuntimeError {
+            error: WrenValue::String(message.to_string()),
+            stack_trace: Vec::new(),
+        }
+    }
+
+    // Copies [value] from the host into the VM. Returns `None` for an
+    // [WrenValue::Object], which only describes an object.
+    fn copy_wren_value(&mut self, value: &WrenValue) -> Option<Value> {
+        match value {
+            WrenValue::Null => Some(Value::Null),
+            WrenValue::Bool(value) => Some(Value::Bool(*value)),
+            WrenValue::Num(value) => Some(Value::Num(*value)),
+            WrenValue::String(value) => Some(wren_new_string(self, value.as_bytes())),
+            WrenValue::Object(_) => None,
+        }
+    }
+
+    // Copies [value] into an owned [WrenValue].
+    fn to_wren_value(&self, value: Value) -> WrenValue {
+        match value {
+            Value::Null | Value::Undefined => WrenValue::Null,
+            Value::Bool(value) => WrenValue::Bool(value),
+            Value::Num(value) => WrenValue::Num(value),
+            Value::Obj(obj) if self.heap.is_string(value) => {
+                let string = &self.heap.as_string(obj).value;
+                WrenValue::String(String::from_utf8_lossy(string).into_owned())
+            }
+            Value::Obj(_) => {
+                let name = self.heap.as_class(self.get_class(value)).name;
+                let name = &self.heap.as_string(name).value;
+                WrenValue::Object(String::from_utf8_lossy(name).into_owned())
+            }
+        }
+    }
+
+    // Lexes [source] into tokens without compiling or running it. If
+    // [collect_comments] is `true`, the `//` comments directly above each
+    // declaration are returned too, for tools such as documentation generators.
+    pub fn tokenize(&mut self, source: &str, collect_comments: bool) -> Tokens {
+        wren_tokenize(self, source.as_bytes(), collect_comments)
+    }
+
+    // Unloads every module the host has run, keeping only the core library, so
+    // that unrelated scripts can reuse the VM without creating it again. The
+    // dropped modules and their variables are freed right away.
+    pub fn reset_user_modules(&mut self) {
+        let core_module = self
+            .get_module(Value::Null)
+            .expect("Core module should be loaded.");
+
+        let modules = wren_new_map(self);
+        wren_map_set(self, modules, Value::Null, Value::Obj(core_module));
+        self.modules = modules;
+        self.last_value = None;
+        self.last_module = None;
+        self.suspended = None;
+        self.interrupted = None;
+        self.clear_last_error();
+
+        self.collect_garbage();
+    }
+
+    // Returns the number of objects currently allocated, reachable or not. After
+    // [collect_garbage], that's exactly the live ones. Meant for tests of the
+    // garbage collector.
+    pub fn debug_object_count(&self) -> usize {
+        self.heap.object_count()
+    }
+
+    // Returns the approximate number of bytes the allocated objects take up.
+    // Like [debug_object_count], it is exact right after [collect_garbage].
+    pub fn debug_bytes_allocated(&self) -> usize {
+        self.heap.bytes_allocated
+    }
+
+    // Returns the message of the runtime error that aborted the code the host
+    // last ran, or `None` if it didn't abort or never got to run.
+    pub fn last_error_message(&self) -> Option<String> {
+        self.last_error.clone()
+    }
+
+    // Returns the stack trace of the runtime error that aborted the code the
+    // host last ran, innermost call first. This is empty if nothing aborted.
+    pub fn last_stack_trace(&self) -> Vec<StackFrame> {
+        self.last_trace.clone()
+    }
+
+    fn clear_last_error(&mut self) {
+        self.last_error = None;
+        self.last_trace.clear();
+    }
+
+    // Returns `true` if a module named [name] has been loaded, either by the
+    // host running code in it or by an import.
+    pub fn has_module(&self, name: &str) -> bool {
+        self.module_keys().any(|key| key == name.as_bytes())
+    }
+
+    // Returns the names of every loaded module, sorted. The implicit core
+    // module has no name and isn't included.
+    pub fn module_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .module_keys()
+            .map(|key| String::from_utf8_lossy(key).into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // The names of the loaded modules other than core, in the order they sit in
+    // the module map.
+    fn module_keys(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.heap
+            .as_map(self.modules)
+            .entries
+            .iter()
+            .filter_map(move |entry| match entry.key {
+                Value::Obj(key) => Some(self.heap.as_string(key).value.as_slice()),
+                _ => None,
+            })
+    }
+
+    // Returns the number of slots available to the current foreign method.
+    pub fn get_slot_count(&self) -> usize {
+        let api_stack = self.api_stack.expect("Must be in a foreign method.");
+        let fiber = self.fiber.expect("Should have a fiber.");
+        self.heap.as_fiber(fiber).stack.len() - api_stack
+    }
+
+    // Ensures that the foreign method stack has at least [num_slots] available
+    // for use, growing the stack if needed.
+    pub fn ensure_slots(&mut self, num_slots: usize) {
+        let api_stack = self.api_stack.expect("Must be in a foreign method.");
+        let fiber = self.fiber.expect("Should have a fiber.");
+        let stack = &mut self.heap.as_fiber_mut(fiber).stack;
+        if stack.len() < api_stack + num_slots {
+            stack.resize(api_stack + num_slots, Value::Null);
+        }
+    }
+
+    // Reads a boolean value from [slot].
+    //
+    // It is an error to call this if the slot does not contain a boolean value.
+    pub fn get_slot_bool(&self, slot: usize) -> bool {
+        match self.slot(slot) {
+            Value::Bool(value) => value,
+            _ => panic!("Slot must hold a bool."),
+        }
+    }
+
+    // Reads a number from [slot].
+    //
+    // It is an error to call this if the slot does not contain a number.
+    pub fn get_slot_double(&self, slot: usize) -> f64 {
+        match self.slot(slot) {
+            Value::Num(value) => value,
+            _ => panic!("Slot must hold a number."),
+        }
+    }
+
+    // Reads a string from [slot].
+    //
+    // It is an error to call this if the slot does not contain a string.
+    pub fn get_slot_string(&self, slot: usize) -> String {
+        let value = self.slot(slot);
+        assert!(self.heap.is_string(value), "Slot must hold a string.");
+        String::from_utf8_lossy(&self.heap.as_string(value.as_obj()).value).into_owned()
+    }
+
+    // Stores the boolean [value] in [slot].
+    pub fn set_slot_bool(&mut self, slot: usize, value: bool) {
+        self.set_slot(slot, Value::Bool(value));
+    }
+
+    // Stores the numeric [value] in [slot].
+    pub fn set_slot_double(&mut self, slot: usize, value: f64) {
+        self.set_slot(slot, Value::Num(value));
+    }
+
+    // Stores null in [slot].
+    pub fn set_slot_null(&mut self, slot: usize) {
+        self.set_slot(slot, Value::Null);
+    }
+
+    // Stores a new string with the contents of [text] in [slot].
+    pub fn set_slot_string(&mut self, slot: usize, text: &str) {
+        let value = wren_new_string(self, text.as_bytes());
+        self.set_slot(slot, value);
+    }
+
+    // Stores a new string with the contents of [bytes] in [slot].
+    //
+    // Unlike [set_slot_string], [bytes] may not be valid UTF-8. How that is
+    // handled depends on [Config::string_validation]. In strict mode, invalid
+    // bytes store null instead and abort the current fiber.
+    pub fn set_slot_bytes(&mut self, slot: usize, bytes: &[u8]) {
+        let value = match self.config.string_validation {
+            StringValidation::Bytes => wren_new_string(self, bytes),
+            StringValidation::Lossy => {
+                wren_new_string(self, String::from_utf8_lossy(bytes).as_bytes())
+            }
+            StringValidation::Strict => {
+                if std::str::from_utf8(bytes).is_err() {
+                    let error = wren_new_string(self, b"String must be valid UTF-8.");
+                    self.set_error(error);
+                    self.set_slot(slot, Value::Null);
+                    return;
+                }
+                wren_new_string(self, bytes)
+            }
+        };
+        self.set_slot(slot, value);
+    }
+
+    // Aborts the fiber running the current foreign method with the value in
+    // [slot] as its error, usually a string message. A `Fiber.try()` further
+    // up the chain of fibers can catch it.
+    pub fn abort_fiber(&mut self, slot: usize) {
+        let error = self.slot(slot);
+        self.set_error(error);
+    }
+
+    // Returns the index of [slot] on the running fiber's stack.
+    fn validate_api_slot(&self, slot: usize) -> usize {
+        assert!(slot < self.get_slot_count(), "Not that many slots.");
+        self.api_stack.expect("Must be in a foreign method.") + slot
+    }
+
+    fn slot(&self, slot: usize) -> Value {
+        let index = self.validate_api_slot(slot);
+        let fiber = self.fiber.expect("Should have a fiber.");
+        self.heap.as_fiber(fiber).stack[index]
+    }
+
+    fn set_slot(&mut self, slot: usize, value: Value) {
+        let index = self.validate_api_slot(slot);
+        let fiber = self.fiber.expect("Should have a fiber.");
+        self.heap.as_fiber_mut(fiber).stack[index] = value;
+    }
+
+    // Runs the host's fiber [root] from [start], which is either [root] itself or
+    // a fiber that it is waiting on, until control comes back to the host.
+    fn run_host_fiber(&mut self, root: ObjRef, start: ObjRef) -> FiberResult {
+        let suspended = self.suspended;
+        let result = self.run_interpreter(start);
+
+        let root_obj = self.heap.as_fiber(root);
+        match result {
+            InterpretResult::RuntimeError => {
+                FiberResult::Errored(self.to_wren_value(root_obj.error))
+            }
+            InterpretResult::Budget => {
+                let running = self.fiber.expect("Should have a fiber.");
+                self.heap.as_fiber_mut(root).interrupted = Some(running);
+                self.interrupted = Some(root);
+                FiberResult::Budget
+            }
+            _ if self.suspended != suspended => FiberResult::Suspended,
+            _ if root_obj.frames.is_empty() => {
+                let value = root_obj.stack[0];
+                self.last_value = Some(value);
+                FiberResult::Completed(self.to_wren_value(value))
+            }
+            _ => {
+                let value = *root_obj.stack.last().unwrap();
+                FiberResult::Yielded(self.to_wren_value(value))
+            }
+        }
+    }
+
+    // Compiles [source], a string of Wren source code located in [module], to an
+    // [ObjClosure] that will execute that code when invoked. Returns `None` if
+    // the source contains any syntax errors.
+    //
+    // If [is_expression] is `true`, [source] should be a single expression, and
+    // this compiles it to a function that evaluates and returns that expression.
+    // Otherwise, [source] should be a series of top level statements.
+    //
+    // If [print_errors] is `true`, any compile errors are output to stderr.
+    // Otherwise, they are silently discarded.
+    pub(crate) fn compile_source(
+        &mut self,
+        module: Option<&str>,
+        source: &[u8],
+        is_expression: bool,
+        print_errors: bool,
+    ) -> Option<ObjRef> {
+        let name = match module {
+            Some(module) => wren_new_string(self, module.as_bytes()),
+            None => Value::Null,
+        };
+
+        self.compile_in_module(name, source, is_expression, print_errors)
+    }
+
+    fn compile_in_module(
+        &mut self,
+        name: Value,
+        source: &[u8],
+        is_expression: bool,
+        print_errors: bool,
+    ) -> Option<ObjRef> {
+        // See if the module has already been loaded.
+        let module = match self.get_module(name) {
+            Some(module) => module,
+            None => {
+                let module_name = match name {
+                    Value::Obj(name) => Some(name),
+                    _ => None,
+                };
+                let module = wren_new_module(self, module_name);
+
+                // Store it in the VM's module registry so we don't load it twice.
+                wren_map_set(self, self.modules, name, Value::Obj(module));
+
+                // Implicitly import the core module.
+                let core_module = self
+                    .get_module(Value::Null)
+                    .expect("Core module should be loaded.");
+                let count = self.heap.as_module(core_module).variables.len();
+                for i in 0..count {
+                    let core = self.heap.as_module(core_module);
+                    let name = core.variable_names.name(i).to_string();
+                    let value = core.variables[i];
+                    self.define_variable(module, &name, value, None);
+                }
+
+                module
+            }
+        };
+
+        let fn_obj = wren_compile(self, module, source, is_expression, print_errors)?;
+        if self.config.verify_bytecode {
+            if let Err(error) = verify_chunk(&self.heap, self.heap.as_fn(fn_obj)) {
+                if print_errors {
+                    let module_name = match self.heap.as_module(module).name {
+                        Some(name) => {
+                            String::from_utf8_lossy(&self.heap.as_string(name).value).into_owned()
+                        }
+                        None => String::new(),
+                    };
+                    let message = format!(
+                        "Invalid bytecode in '{}' at offset {}: {}",
+                        error.function, error.offset, error.message
+                    );
+                    self.report_error(ErrorType::Compile, &module_name, error.line, &message);
+                }
+                return None;
+            }
+        }
+        Some(wren_new_closure(self, fn_obj))
+    }
+
+    // Lets the host resolve the module [name] imported from [importer], then
+    // loads it.
+    //
+    // Returns the module if it was already loaded, or a closure that runs the
+    // newly compiled module's body. If the module can't be resolved, loaded or
+    // compiled, sets the fiber's error and returns null.
+    fn import_module(&mut self, importer: ObjRef, name: Value) -> Value {
+        let name = self.resolve_module(importer, name);
+        if self.has_error() {
+            return Value::Null;
+        }
+
+        // If the module is already loaded, we don't need to do anything.
+        if let Some(existing) = self.get_module(name) {
+            return Value::Obj(existing);
+        }
+
+        // Let the host try to provide the module.
+        let name_text =
+            String::from_utf8_lossy(&self.heap.as_string(name.as_obj()).value).into_owned();
+        let mut source = None;
+        if let Some(mut load_fn) = self.config.load_module_fn.take() {
+            source = load_fn(self, &name_text);
+            self.config.load_module_fn = Some(load_fn);
+        }
+
+        let source = match source {
+            Some(source) => source,
+            None => {
+                let error = wren_new_string(
+                    self,
+                    format!("Could not load module '{}'.", name_text).as_bytes(),
+                );
+                self.set_error(error);
+                return Value::Null;
+            }
+        };
+
+        match self.compile_in_module(name, source.as_bytes(), false, true) {
+            // Return the closure that executes the module.
+            Some(closure) => Value::Obj(closure),
+            None => {
+                let error = wren_new_string(
+                    self,
+                    format!("Could not compile module '{}'.", name_text).as_bytes(),
+                );
+                self.set_error(error);
+                Value::Null
+            }
+        }
+    }
+
+    // Returns the name that the host resolves the module [name] imported from
+    // [importer] to. If the host doesn't care to resolve, leaves the name alone.
+    fn resolve_module(&mut self, importer: ObjRef, name: Value) -> Value {
+        let mut resolve_fn = match self.config.resolve_module_fn.take() {
+            Some(resolve_fn) => resolve_fn,
+            None => return name,
+        };
+
+        let importer = match self.heap.as_module(importer).name {
+            Some(importer) => {
+                String::from_utf8_lossy(&self.heap.as_string(importer).value).into_owned()
+            }
+            None => String::new(),
+        };
+        let name_text =
+            String::from_utf8_lossy(&self.heap.as_string(name.as_obj()).value).into_owned();
+        let resolved = resolve_fn(self, &importer, &name_text);
+        self.config.resolve_module_fn = Some(resolve_fn);
+
+        match resolved {
+            // If they resolved to the exact same string, we don't need to copy it.
+            Some(resolved) if resolved == name_text => name,
+            Some(resolved) => wren_new_string(self, resolved.as_bytes()),
+            None => {
+                let error = wren_new_string(
+                    self,
+                    format!(
+                        "Could not resolve module '{}' imported from '{}'.",
+                        name_text, importer
+                    )
+                    .as_bytes(),
+                );
+                self.set_error(error);
+                Value::Null
+            }
+        }
+    }
+
+    // Looks up the variable [name] in [module]. If it isn't defined there, sets
+    // the fiber's error and returns null.
+    fn get_module_variable(&mut self, module: ObjRef, name: Value) -> Value {
+        let name = String::from_utf8_lossy(&self.heap.as_string(name.as_obj()).value).into_owned();
+        let module_obj = self.heap.as_module(module);
+        let symbol = module_obj.variable_names.find(&name);
+
+        // It's a runtime error if the imported variable does not exist.
+        if symbol != -1 {
+            return module_obj.variables[symbol as usize];
+        }
+
+        let module_name = match module_obj.name {
+            Some(module_name) => {
+                String::from_utf8_lossy(&self.heap.as_string(module_name).value).into_owned()
+            }
+            None => String::new(),
+        };
+        let error = wren_new_string(
+            self,
+            format!(
+                "Could not find a variable named '{}' in module '{}'.",
+                name, module_name
+            )
+            .as_bytes(),
+        );
+        self.set_error(error);
+        Value::Null
+    }
+
+    // Looks up a previously-loaded module.
+    //
+    // Returns `None` if no module with that name has been loaded.
+    fn get_module(&self, name: Value) -> Option<ObjRef> {
+        match wren_map_get(self, self.modules, name) {
+            Value::Undefined => None,
+            module => Some(module.as_obj()),
+        }
+    }
+
+    // Frees every object that can no longer be reached from the VM: the loaded
+    // modules, the core classes, the running and suspended fibers, the last
+    // value returned to the host and the host's handles.
+    //
+    // The interpreter also does this by itself whenever the heap grows past the
+    // threshold that [Config::initial_heap_size], [Config::min_heap_size] and
+    // [Config::heap_growth_percent] set, so hosts only need to call it to
+    // reclaim memory at a moment of their choosing.
+    pub fn collect_garbage(&mut self) {
+        let mut roots = vec![Value::Obj(self.modules)];
+        let classes = [
+            self.bool_class,
+            self.bytes_class,
+            self.class_class,
+            self.fiber_class,
+            self.fn_class,
+            self.list_class,
+            self.map_class,
+            self.null_class,
+            self.num_class,
+            self.object_class,
+            self.range_class,
+            self.string_class,
+        ];
+        roots.extend(
+            classes
+                .iter()
+                .flatten()
+                .map(|&class_obj| Value::Obj(class_obj)),
+        );
+        roots.extend(self.fiber.map(Value::Obj));
+        roots.extend(self.suspended.map(Value::Obj));
+        roots.extend(self.interrupted.map(Value::Obj));
+        roots.extend(self.last_value);
+        roots.extend(self.handles.iter().flatten());
+
+        self.heap.collect(&roots);
+
+        // Calculate the next gc point, this is the current allocation plus
+        // a configured percentage of the current allocation.
+        let bytes_allocated = self.heap.bytes_allocated;
+        self.next_gc = bytes_allocated + bytes_allocated * self.config.heap_growth_percent / 100;
+        if self.next_gc < self.config.min_heap_size {
+            self.next_gc = self.config.min_heap_size;
+        }
+    }
+
+    // Returns the class of [value].
+    pub(crate) fn get_class(&self, value: Value) -> ObjRef {
+        let class_obj = match value {
+            Value::Null => self.null_class,
+            Value::Bool(_) => self.bool_class,
+            Value::Num(_) => self.num_class,
+            Value::Obj(obj) => self.heap.get(obj).class_obj,
+            Value::Undefined => unreachable!("Undefined values have no class."),
+        };
+        class_obj.expect("Value should have a class.")
+    }
+
+    // Adds a new implicitly declared top-level variable named [name] to [module]
+    // based on a use site occurring on [line].
+    //
+    // Does not check to see if a variable with that name is already declared or
+    // defined. Returns the symbol for the new variable or -2 if there are too
+    // many variables defined.
+    pub(crate) fn declare_variable(&mut self, module: ObjRef, name: &str, line: i32) -> i32 {
+        let module = self.heap.as_module_mut(module);
+        if module.variables.len() == MAX_MODULE_VARS as usize {
+            return -2;
+        }
+
+        // Implicitly defined variables get a "value" that is the line where the
+        // variable is first used. We'll use that later to report an error on the
+        // right line.
+        module.variables.push(Value::Num(line as f64));
+        module.variable_names.add(name) as i32
+    }
+
+    // Adds a new top-level variable named [name] to [module], and optionally
+    // populates it with [value].
+    //
+    // Returns the symbol for the new variable, -1 if a variable with the given
+    // name is already defined, or -2 if there are too many variables defined.
+    // Returns -3 if this is a top-level lowercase variable (localname) that was
+    // used before being defined, and sets [line] to the line of the first use.
+    pub(crate) fn define_variable(
+        &mut self,
+        module: ObjRef,
+        name: &str,
+        value: Value,
+        line: Option<&mut i32>,
+    ) -> i32 {
+        let module = self.heap.as_module_mut(module);
+        if module.variables.len() == MAX_MODULE_VARS as usize {
+            return -2;
+        }
+
+        // See if the variable is already explicitly or implicitly declared.
+        let symbol = module.variable_names.find(name);
+        if symbol == -1 {
+            // Brand new variable.
+            let symbol = module.variable_names.add(name);
+            module.variables.push(value);
+            return symbol as i32;
+        }
+
+        match module.variables[symbol as usize] {
+            // An implicitly declared variable's value will always be a number.
+            // Now we have a real definition.
+            Value::Num(first_use) => {
+                module.variables[symbol as usize] = value;
+
+                // If this was a localname we want to error if it was referenced
+                // before this definition.
+                if name.starts_with(|c: char| c.is_ascii_lowercase()) {
+                    if let Some(line) = line {
+                        *line = first_use as i32;
+                    }
+                    return -3;
+                }
+
+                symbol
+            }
+
+            // Already explicitly declared.
+            _ => -1,
+        }
+    }
+
+    // Displays [text] through the user's [PrintFn] if there is one. Otherwise,
+    // passes it to their [WriteFn], or buffers it to be displayed on the next
+    // flush if output is buffered.
+    pub(crate) fn write(&mut self, text: &str) {
+        if let Some(print_fn) = self.config.print_fn.as_mut() {
+            print_fn(text);
+            return;
+        }
+
+        if self.config.buffer_output {
+            self.output.push_str(text);
+            return;
+        }
+
+        if let Some(mut write_fn) = self.config.write_fn.take() {
+            write_fn(self, text);
+            self.config.write_fn = Some(write_fn);
+        }
+    }
+
+    // Hands any buffered output to the user's [WriteFn]. Does nothing if output
+    // isn't buffered or nothing has been printed since the last flush.
+    pub fn flush(&mut self) {
+        if self.output.is_empty() {
+            return;
+        }
+
+        let output = std::mem::take(&mut self.output);
+        if let Some(mut write_fn) = self.config.write_fn.take() {
+            write_fn(self, &output);
+            self.config.write_fn = Some(write_fn);
+        }
+    }
+
+    // Reports an error through the user's [ErrorFn], if one was provided.
+    pub(crate) fn report_error(&mut self, ty: ErrorType, module: &str, line: i32, message: &str) {
+        if let Some(mut error_fn) = self.config.error_fn.take() {
+            error_fn(self, ty, module, line, message);
+            self.config.error_fn = Some(error_fn);
+        }
+    }
+
+    // Aborts the current fiber with [error], which is usually a string message.
+    pub(crate) fn set_error(&mut self, error: Value) {
+        let fiber = self.fiber.expect("Should have a fiber.");
+        self.heap.as_fiber_mut(fiber).error = error;
+    }
+
+    // Returns true if the current fiber has been aborted with an error.
+    pub(crate) fn has_error(&self) -> bool {
+        let fiber = self.fiber.expect("Should have a fiber.");
+        !matches!(self.heap.as_fiber(fiber).error, Value::Null)
+    }
+
+    // Handles the current fiber having aborted because of an error.
+    //
+    // Walks the call chain of fibers, aborting each one until it hits a fiber that
+    // handles the error. If none do, tells the VM to stop.
+    fn runtime_error(&mut self) {
+        debug_assert!(self.has_error(), "Should only call this after an error.");
+
+        let mut current = self.fiber.expect("Should have a fiber.");
+        let error = self.heap.as_fiber(current).error;
+
+        while let Some(caller) = self.heap.as_fiber(current).caller {
+            // Every fiber along the call chain gets aborted with the same error.
+            self.heap.as_fiber_mut(current).error = error;
+
+            // If the caller ran this fiber using "try", give it the error and stop.
+            if self.heap.as_fiber(current).state == FiberState::Try {
+                // Make the caller's try method return the error message.
+                *self.heap.as_fiber_mut(caller).stack.last_mut().unwrap() = error;
+                self.fiber = Some(caller);
+                return;
+            }
+
+            // Otherwise, unhook the caller since we will never resume and return to it.
+            self.heap.as_fiber_mut(current).caller = None;
+            current = caller;
+        }
+
+        // The fiber at the bottom of the chain is aborted too.
+        self.heap.as_fiber_mut(current).error = error;
+
+        // If we got here, nothing caught the error, so show the stack trace.
+        let fiber = self.fiber.expect("Should have a fiber.");
+        self.last_error = Some(wren_debug_error_message(self, error));
+        self.last_trace = wren_debug_stack_trace(self, fiber);
+        wren_debug_print_stack_trace(self);
+        self.fiber = None;
+    }
+
+    // Aborts the current fiber with an appropriate method not found error for a
+    // method with [symbol] on [class_obj].
+    fn method_not_found(&mut self, class_obj: ObjRef, symbol: usize) {
+        let class_name = self.heap.as_class(class_obj).name;
+        let class_name = self.heap.as_string(class_name).value.clone();
+        let signature = self.method_names.name(symbol).to_string();
+        let error = wren_string_format(
+            self,
+            &[
+                &class_name,
+                b" does not implement '",
+                signature.as_bytes(),
+                b"'.",
+            ],
+        );
+        self.set_error(error);
+    }
+
+    // Hands [value] to [fiber] before it runs. If the fiber is being started for
+    // the first time, it is bound to its function's parameter if it has one.
+    // Otherwise, it is the result of the yield the fiber is suspended in.
+    pub(crate) fn send_to_fiber(&mut self, fiber: ObjRef, value: Value) {
+        let closure = self.heap.as_fiber(fiber).frames[0].closure;
+        let arity = self.heap.as_fn(self.heap.as_closure(closure).fn_obj).arity;
+
+        let fiber = self.heap.as_fiber_mut(fiber);
+        if fiber.frames.len() == 1 && fiber.frames[0].ip == 0 {
+            if arity == 1 {
+                fiber.stack.push(value);
+            }
+        } else {
+            *fiber.stack.last_mut().unwrap() = value;
+        }
+    }
+
+    // Captures the local variable in [slot] of [fiber]'s stack into an upvalue.
+    // If that local is already in an upvalue, the existing one will be used.
+    // (This is important to ensure that multiple closures closing over the same
+    // variable actually see the same variable.) Otherwise, it will create a new
+    // open upvalue and add it to the fiber's list of upvalues.
+    fn capture_upvalue(&mut self, fiber: ObjRef, slot: usize) -> ObjRef {
+        // Walk towards the bottom of the stack until we find a previously existing
+        // upvalue or pass where it should be.
+        let open_upvalues = &self.heap.as_fiber(fiber).open_upvalues;
+        let mut index = open_upvalues.len();
+        while index > 0 {
+            let upvalue = open_upvalues[index - 1];
+            let upvalue_slot = self.heap.as_upvalue(upvalue).slot;
+
+            // Found an existing upvalue for this local.
+            if upvalue_slot == slot {
+                return upvalue;
+            }
+
+            if upvalue_slot < slot {
+                break;
+            }
+            index -= 1;
+        }
+
+        // We've walked past this local on the stack, so there must not be an
+        // upvalue for it already. Make a new one and link it in in the right
+        // place to keep the list sorted.
+        let upvalue = wren_new_upvalue(self, fiber, slot);
+        self.heap
+            .as_fiber_mut(fiber)
+            .open_upvalues
+            .insert(index, upvalue);
+        upvalue
+    }
+
+    // Closes any open upvalues that have been created for stack slots at [last]
+    // and above.
+    fn close_upvalues(&mut self, fiber: ObjRef, last: usize) {
+        while let Some(&upvalue) = self.heap.as_fiber(fiber).open_upvalues.last() {
+            let slot = self.heap.as_upvalue(upvalue).slot;
+            if slot < last {
+                break;
+            }
+
+            // Move the value into the upvalue itself and point the upvalue to it.
+            let fiber_obj = self.heap.as_fiber_mut(fiber);
+            let value = fiber_obj.stack[slot];
+            fiber_obj.open_upvalues.pop();
+            self.heap.as_upvalue_mut(upvalue).closed = Some(value);
+        }
+    }
+
+    // Returns the current value of the variable that [upvalue] refers to.
+    fn upvalue_value(&self, upvalue: ObjRef) -> Value {
+        let upvalue = self.heap.as_upvalue(upvalue);
+        match upvalue.closed {
+            Some(value) => value,
+            None => self.heap.as_fiber(upvalue.fiber).stack[upvalue.slot],
+        }
+    }
+
+    // Stores [value] in the variable that [upvalue] refers to.
+    fn set_upvalue_value(&mut self, upvalue: ObjRef, value: Value) {
+        let upvalue_obj = self.heap.as_upvalue_mut(upvalue);
+        match upvalue_obj.closed.as_mut() {
+            Some(closed) => *closed = value,
+            None => {
+                let (fiber, slot) = (upvalue_obj.fiber, upvalue_obj.slot);
+                self.heap.as_fiber_mut(fiber).stack[slot] = value;
+            }
+        }
+    }
+
+    // Pushes a new call frame onto [fiber] to invoke [closure], whose receiver
+    // and arguments start at [stack_start] on the fiber's stack.
+    //
+    // Returns `false` and aborts the fiber if it is already as deep as the
+    // configured maximum call depth.
+    pub(crate) fn push_call_frame(
+        &mut self,
+        fiber: ObjRef,
+        closure: ObjRef,
+        stack_start: usize,
+    ) -> bool {
+        if self.heap.as_fiber(fiber).frames.len() >= self.config.max_call_depth {
+            let error = wren_new_string(self, b"Stack overflow.");
+            self.set_error(error);
+            return false;
+        }
+
+        self.heap.as_fiber_mut(fiber).frames.push(CallFrame {
+            ip: 0,
+            closure,
+            stack_start,
+        });
+        true
+    }
+
+    // Checks that [superclass] is a valid object to inherit from. That means it
+    // must be a class and cannot be the class of any built-in type.
+    //
+    // Also validates that it doesn't result in a class with too many fields and
+    // the other limitations foreign classes have.
+    //
+    // If successful, returns `null`. Otherwise, returns a string for the runtime
+    // error message.
+    fn validate_superclass(&mut self, name: Value, superclass: Value, num_fields: i32) -> Value {
+        let name = self.heap.as_string(name.as_obj()).value.clone();
+
+        // Make sure the superclass is a class.
+        if !self.heap.is_class(superclass) {
+            return wren_string_format(
+                self,
+                &[
+                    b"Class '",
+                    &name,
+                    b"' cannot inherit from a non-class object.",
+                ],
+            );
+        }
+
+        // Make sure it doesn't inherit from a sealed built-in type. Primitive methods
+        // on these classes assume the instance is one of the other Obj___ types and
+        // will fail horribly if it's actually an ObjInstance.
+        let superclass = superclass.as_obj();
+        let sealed = [
+            self.bytes_class,
+            self.class_class,
+            self.fiber_class,
+            self.fn_class,
+            self.list_class,
+            self.map_class,
+            self.range_class,
+            self.string_class,
+            self.bool_class,
+            self.null_class,
+            self.num_class,
+        ];
+        if sealed.contains(&Some(superclass)) {
+            let superclass_name = self.heap.as_class(superclass).name;
+            let superclass_name = self.heap.as_string(superclass_name).value.clone();
+            return wren_string_format(
+                self,
+                &[
+                    b"Class '",
+                    &name,
+                    b"' cannot inherit from built-in class '",
+                    &superclass_name,
+                    b"'.",
+                ],
+            );
+        }
+
+        if self.heap.as_class(superclass).num_fields + num_fields > MAX_FIELDS {
+            return wren_string_format(
+                self,
+                &[
+                    b"Class '",
+                    &name,
+                    b"' may not have more than 255 fields, including inherited ones.",
+                ],
+            );
+        }
+
+        Value::Null
+    }
+
+    // Creates a new class from the name and superclass on top of [fiber]'s stack,
+    // leaving the class in their place.
+    fn create_class(&mut self, fiber: ObjRef, num_fields: i32) {
+        // Pull the name and superclass off the stack.
+        let superclass = self.heap.as_fiber_mut(fiber).stack.pop().unwrap();
+        let name = *self.heap.as_fiber(fiber).stack.last().unwrap();
+
+        let error = self.validate_superclass(name, superclass, num_fields);
+        self.set_error(error);
+        if self.has_error() {
+            return;
+        }
+
+        let class_obj = wren_new_class(self, superclass.as_obj(), num_fields, name.as_obj());
+        *self.heap.as_fiber_mut(fiber).stack.last_mut().unwrap() = Value::Obj(class_obj);
+    }
+
+    // Defines [method] on [class_obj] for [symbol]. If [instruction] is
+    // `METHOD_STATIC`, the method is defined on the class's metaclass instead.
+    //
+    // A string [method] is the signature of a foreign method declared in
+    // [module], which the host is asked to provide.
+    fn bind_method(
+        &mut self,
+        instruction: Code,
+        symbol: usize,
+        module: ObjRef,
+        class_obj: ObjRef,
+        method: Value,
+    ) {
+        let class_name = self.heap.as_class(class_obj).name;
+        let is_static = instruction == Code::METHOD_STATIC;
+        let class_obj = if is_static {
+            self.heap
+                .get(class_obj)
+                .class_obj
+                .expect("Class should have a metaclass.")
+        } else {
+            class_obj
+        };
+
+        let method = if self.heap.is_string(method) {
+            let signature = self.heap.as_string(method.as_obj()).value.clone();
+            let signature = String::from_utf8_lossy(&signature).into_owned();
+            let class_name = &self.heap.as_string(class_name).value;
+            let class_name = String::from_utf8_lossy(class_name).into_owned();
+            match self.find_foreign_method(module, &class_name, is_static, &signature) {
+                Some(foreign) => Method::Foreign(foreign),
+                None => return,
+            }
+        } else {
+            // Patch up the bytecode now that we know the superclass.
+            let closure = method.as_obj();
+            let fn_obj = self.heap.as_closure(closure).fn_obj;
+            wren_bind_method_code(self, class_obj, fn_obj);
+
+            Method::Block(closure)
+        };
+
+        wren_bind_method(self.heap.as_class_mut(class_obj), symbol, method);
+    }
+
+    // Looks up the host's implementation of the foreign method [signature] on
+    // the class named [class_name], which is declared in [module].
+    //
+    // If the host doesn't provide one, aborts the current fiber and returns
+    // `None`.
+    fn find_foreign_method(
+        &mut self,
+        module: ObjRef,
+        class_name: &str,
+        is_static: bool,
+        signature: &str,
+    ) -> Option<ForeignMethodFn> {
+        let module_name = match self.heap.as_module(module).name {
+            Some(name) => String::from_utf8_lossy(&self.heap.as_string(name).value).into_owned(),
+            None => "core".to_string(),
+        };
+
+        let method = match self.config.bind_foreign_method_fn.take() {
+            Some(mut bind_fn) => {
+                let method = bind_fn(self, &module_name, class_name, is_static, signature);
+                self.config.bind_foreign_method_fn = Some(bind_fn);
+                method
+            }
+            None => None,
+        };
+
+        if method.is_none() {
+            let error = wren_string_format(
+                self,
+                &[
+                    b"Could not find foreign method '",
+                    signature.as_bytes(),
+                    b"' for class ",
+                    class_name.as_bytes(),
+                    b" in module '",
+                    module_name.as_bytes(),
+                    b"'.",
+                ],
+            );
+            self.set_error(error);
+        }
+        method
+    }
+
+    // Invokes [foreign] with the [num_args] values on top of [fiber]'s stack,
+    // including the receiver, as its slots. Leaves the method's result in place
+    // of them.
+    fn call_foreign(&mut self, fiber: ObjRef, foreign: ForeignMethodFn, num_args: usize) {
+        debug_assert!(
+            self.api_stack.is_none(),
+            "Cannot already be in foreign call."
+        );
+        let api_stack = self.heap.as_fiber(fiber).stack.len() - num_args;
+        self.api_stack = Some(api_stack);
+
+        foreign(self);
+
+        // Discard the stack slots for the arguments and temporaries but leave one
+        // for the result.
+        self.heap.as_fiber_mut(fiber).stack.truncate(api_stack + 1);
+        self.api_stack = None;
+    }
+
+    // The main bytecode interpreter loop. This is where the magic happens. It is
+    // also, as you can imagine, highly performance critical.
+    pub(crate) fn run_interpreter(&mut self, fiber: ObjRef) -> InterpretResult {
+        let mut fiber = fiber;
+
+        // Remember the current fiber so we can find it if a GC happens.
+        self.fiber = Some(fiber);
+
+        // Hoist these into local variables. They are accessed frequently in the loop
+        // but assigned less frequently. Keeping them in locals and updating them when
+        // a call frame has been pushed or popped gives a large speed boost.
+        let mut ip: usize;
+        let mut stack_start: usize;
+        let mut closure: ObjRef;
+        let mut fn_obj: ObjRef;
+
+        macro_rules! push {
+            ($value:expr) => {{
+                let value = $value;
+                self.heap.as_fiber_mut(fiber).stack.push(value);
+            }};
+        }
+
+        macro_rules! pop {
+            () => {
+                self.heap
+                    .as_fiber_mut(fiber)
+                    .stack
+                    .pop()
+                    .expect("Stack should not underflow.")
+            };
+        }
+
+        macro_rules! peek {
+            () => {
+                *self
+                    .heap
+                    .as_fiber(fiber)
+                    .stack
+                    .last()
+                    .expect("Stack should not be empty.")
+            };
+        }
+
+        macro_rules! read_byte {
+            () => {{
+                let byte = self.heap.as_fn(fn_obj).code[ip];
+                ip += 1;
+                byte
+            }};
+        }
+
+        macro_rules! read_short {
+            () => {{
+                let code = &self.heap.as_fn(fn_obj).code;
+                let short = ((code[ip] as usize) << 8) | code[ip + 1] as usize;
+                ip += 2;
+                short
+            }};
+        }
+
+        // Use this before a CallFrame is pushed to store the local variables back
+        // into the current one.
+        macro_rules! store_frame {
+            () => {{
+                self.heap
+                    .as_fiber_mut(fiber)
+                    .frames
+                    .last_mut()
+                    .expect("Fiber should have a frame.")
+                    .ip = ip;
+            }};
+        }
+
+        // Terminates the current fiber with error string [error]. If another calling
+        // fiber is willing to catch the error, transfers control to it, otherwise
+        // exits the interpreter.
+        macro_rules! runtime_error {
+            () => {{
+                store_frame!();
+                self.runtime_error();
+                match self.fiber {
+                    Some(current) => fiber = current,
+                    None => return InterpretResult::RuntimeError,
+                }
+                load_frame!();
+                continue;
+            }};
+        }
+
+        // Use this after a CallFrame has been pushed or popped to refresh the local
+        // variables.
+        macro_rules! load_frame {
+            () => {{
+                let frame = self
+                    .heap
+                    .as_fiber(fiber)
+                    .frames
+                    .last()
+                    .expect("Fiber should have a frame.");
+                ip = frame.ip;
+                stack_start = frame.stack_start;
+                closure = frame.closure;
+                fn_obj = self.heap.as_closure(closure).fn_obj;
+            }};
+        }
+
+        load_frame!();
+
+        let mut budget = self.config.instruction_budget;
+
+        loop {
+            if let Some(remaining) = budget.as_mut() {
+                if *remaining == 0 {
+                    store_frame!();
+                    return InterpretResult::Budget;
+                }
+                *remaining -= 1;
+            }
+
+            // Between instructions, every live object is reachable from the
+            // fibers, so this is where a collection can safely happen.
+            if self.heap.bytes_allocated > self.next_gc {
+                self.collect_garbage();
+            }
+
+            let instruction = Code::from_byte(read_byte!());
+            match instruction {
+                Code::LOAD_LOCAL_0
+                | Code::LOAD_LOCAL_1
+                | Code::LOAD_LOCAL_2
+                | Code::LOAD_LOCAL_3
+                | Code::LOAD_LOCAL_4
+                | Code::LOAD_LOCAL_5
+                | Code::LOAD_LOCAL_6
+                | Code::LOAD_LOCAL_7
+                | Code::LOAD_LOCAL_8 => {
+                    let slot = instruction as usize - Code::LOAD_LOCAL_0 as usize;
+                    push!(self.heap.as_fiber(fiber).stack[stack_start + slot]);
+                }
+
+                Code::LOAD_LOCAL => {
+                    let slot = read_byte!() as usize;
+                    push!(self.heap.as_fiber(fiber).stack[stack_start + slot]);
+                }
+
+                Code::STORE_LOCAL => {
+                    let slot = read_byte!() as usize;
+                    let value = peek!();
+                    self.heap.as_fiber_mut(fiber).stack[stack_start + slot] = value;
+                }
+
+                Code::LOAD_UPVALUE => {
+                    let upvalue = self.heap.as_closure(closure).upvalues[read_byte!() as usize];
+                    push!(self.upvalue_value(upvalue));
+                }
+
+                Code::STORE_UPVALUE => {
+                    let upvalue = self.heap.as_closure(closure).upvalues[read_byte!() as usize];
+                    self.set_upvalue_value(upvalue, peek!());
+                }
+
+                Code::CONSTANT => {
+                    let constant = read_short!();
+                    push!(self.heap.as_fn(fn_obj).constants[constant]);
+                }
+
+                Code::NULL => push!(Value::Null),
+                Code::FALSE => push!(Value::Bool(false)),
+                Code::TRUE => push!(Value::Bool(true)),
+
+                Code::POP => {
+                    pop!();
+                }
+
+                Code::LOAD_MODULE_VAR => {
+                    let slot = read_short!();
+                    let module = self.heap.as_fn(fn_obj).module;
+                    push!(self.heap.as_module(module).variables[slot]);
+                }
+
+                Code::STORE_MODULE_VAR => {
+                    let slot = read_short!();
+                    let value = peek!();
+                    let module = self.heap.as_fn(fn_obj).module;
+                    self.heap.as_module_mut(module).variables[slot] = value;
+                }
+
+                Code::LOAD_FIELD_THIS => {
+                    let field = read_byte!() as usize;
+                    let receiver = self.heap.as_fiber(fiber).stack[stack_start];
+                    debug_assert!(
+                        self.heap.is_instance(receiver),
+                        "Receiver should be instance."
+                    );
+                    let instance = self.heap.as_instance(receiver.as_obj());
+                    debug_assert!(field < instance.fields.len(), "Out of bounds field.");
+                    push!(instance.fields[field]);
+                }
+
+                Code::STORE_FIELD_THIS => {
+                    let field = read_byte!() as usize;
+                    let receiver = self.heap.as_fiber(fiber).stack[stack_start];
+                    debug_assert!(
+                        self.heap.is_instance(receiver),
+                        "Receiver should be instance."
+                    );
+                    let value = peek!();
+                    let instance = self.heap.as_instance_mut(receiver.as_obj());
+                    debug_assert!(field < instance.fields.len(), "Out of bounds field.");
+                    instance.fields[field] = value;
+                }
+
+                Code::LOAD_FIELD => {
+                    let field = read_byte!() as usize;
+                    let receiver = pop!();
+                    debug_assert!(
+                        self.heap.is_instance(receiver),
+                        "Receiver should be instance."
+                    );
+                    let instance = self.heap.as_instance(receiver.as_obj());
+                    debug_assert!(field < instance.fields.len(), "Out of bounds field.");
+                    push!(instance.fields[field]);
+                }
+
+                Code::STORE_FIELD => {
+                    let field = read_byte!() as usize;
+                    let receiver = pop!();
+                    debug_assert!(
+                        self.heap.is_instance(receiver),
+                        "Receiver should be instance."
+                    );
+                    let value = peek!();
+                    let instance = self.heap.as_instance_mut(receiver.as_obj());
+                    debug_assert!(field < instance.fields.len(), "Out of bounds field.");
+                    instance.fields[field] = value;
+                }
+
+                Code::CALL_0
+                | Code::CALL_1
+                | Code::CALL_2
+                | Code::CALL_3
+                | Code::CALL_4
+                | Code::CALL_5
+                | Code::CALL_6
+                | Code::CALL_7
+                | Code::CALL_8
+                | Code::CALL_9
+                | Code::CALL_10
+                | Code::CALL_11
+                | Code::CALL_12
+                | Code::CALL_13
+                | Code::CALL_14
+                | Code::CALL_15
+                | Code::CALL_16 => {
+                    // Add one for the implicit receiver argument.
+                    let num_args = instruction as usize - Code::CALL_0 as usize + 1;
+                    let symbol = read_short!();
+
+                    // The receiver is the first argument.
+                    let args_start = self.heap.as_fiber(fiber).stack.len() - num_args;
+                    let receiver = self.heap.as_fiber(fiber).stack[args_start];
+                    let class_obj = self.get_class(receiver);
+
+                    // If neither the class nor its superclasses have the method, bail.
+                    let method = match wren_find_method(self, class_obj, symbol) {
+                        Some(method) => method,
+                        None => {
+                            self.method_not_found(class_obj, symbol);
+                            runtime_error!();
+                        }
+                    };
+
+                    match method {
+                        Method::Primitive(primitive) => {
+                            let mut args = [Value::Null; MAX_PARAMETERS as usize + 1];
+                            let args = &mut args[..num_args];
+                            args.copy_from_slice(&self.heap.as_fiber(fiber).stack[args_start..]);
+
+                            // Store the frame first in case the primitive pushes a new one.
+                            store_frame!();
+
+                            if primitive(self, args) {
+                                // The result is now in the first arg slot. Discard the other
+                                // stack slots.
+                                let stack = &mut self.heap.as_fiber_mut(fiber).stack;
+                                stack.truncate(args_start + 1);
+                                stack[args_start] = args[0];
+                            } else {
+                                // An error, fiber switch, or call frame change occurred.
+                                // If we don't have a fiber to switch to, stop interpreting.
+                                fiber = match self.fiber {
+                                    Some(next) => next,
+                                    None => return InterpretResult::Success,
+                                };
+                                if self.has_error() {
+                                    runtime_error!();
+                                }
+                                load_frame!();
+                            }
+                        }
+
+                        Method::Foreign(foreign) => {
+                            store_frame!();
+                            self.call_foreign(fiber, foreign, num_args);
+                            if self.has_error() {
+                                runtime_error!();
+                            }
+                        }
+
+                        Method::Block(closure) => {
+                            store_frame!();
+                            if !self.push_call_frame(fiber, closure, args_start) {
+                                runtime_error!();
+                            }
+                            load_frame!();
+                        }
+                    }
+                }
+
+                Code::JUMP => {
+                    let offset = read_short!();
+                    ip += offset;
+                }
+
+                Code::LOOP => {
+                    // Jump back to the top of the loop.
+                    let offset = read_short!();
+                    ip -= offset;
+                }
+
+                Code::JUMP_IF => {
+                    let offset = read_short!();
+                    let condition = pop!();
+                    if condition.is_falsy() {
+                        ip += offset;
+                    }
+                }
+
+                Code::JUMP_IF_NULL => {
+                    let offset = read_short!();
+                    if matches!(peek!(), Value::Null) {
+                        ip += offset;
+                    }
+                }
+
+                Code::CLOSE_UPVALUE => {
+                    // Close the upvalue for the local if we have one.
+                    let top = self.heap.as_fiber(fiber).stack.len();
+                    self.close_upvalues(fiber, top - 1);
+                    pop!();
+                }
+
+                Code::RETURN => {
+                    let result = pop!();
+
+                    // Close any upvalues still in scope.
+                    self.close_upvalues(fiber, stack_start);
+
+                    let fiber_obj = self.heap.as_fiber_mut(fiber);
+                    fiber_obj.frames.pop();
+
+                    // If the fiber is complete, end it.
+                    if fiber_obj.frames.is_empty() {
+                        // Store the final result value at the beginning of the stack so the
+                        // host can get it.
+                        fiber_obj.stack.truncate(1);
+                        fiber_obj.stack[0] = result;
+
+                        // See if there's another fiber to return to. If not, we're done.
+                        fiber = match fiber_obj.caller.take() {
+                            Some(caller) => caller,
+                            None => return InterpretResult::Success,
+                        };
+                        self.fiber = Some(fiber);
+
+                        // Store the result in the resuming fiber.
+                        *self.heap.as_fiber_mut(fiber).stack.last_mut().unwrap() = result;
+                    } else {
+                        // Discard the stack slots for the call frame and store the result of
+                        // the block in the first slot, which is where the caller expects it.
+                        fiber_obj.stack.truncate(stack_start);
+                        fiber_obj.stack.push(result);
+                    }
+                    load_frame!();
+                }
+
+                Code::CLOSURE => {
+                    // Create the closure and push it on the stack before creating upvalues
+                    // so that it doesn't get collected.
+                    let function = self.heap.as_fn(fn_obj).constants[read_short!()].as_obj();
+                    let new_closure = wren_new_closure(self, function);
+                    push!(Value::Obj(new_closure));
+
+                    // Capture upvalues, if any.
+                    for _ in 0..self.heap.as_fn(function).num_upvalues {
+                        let is_local = read_byte!() != 0;
+                        let index = read_byte!() as usize;
+                        let upvalue = if is_local {
+                            // Make an new upvalue to close over the parent's local variable.
+                            self.capture_upvalue(fiber, stack_start + index)
+                        } else {
+                            // Use the same upvalue as the current call frame.
+                            self.heap.as_closure(closure).upvalues[index]
+                        };
+                        self.heap.as_closure_mut(new_closure).upvalues.push(upvalue);
+                    }
+                }
+
+                Code::CONSTRUCT => {
+                    let this = self.heap.as_fiber(fiber).stack[stack_start];
+                    debug_assert!(self.heap.is_class(this), "'this' should be a class.");
+                    let instance = wren_new_instance(self, this.as_obj());
+                    self.heap.as_fiber_mut(fiber).stack[stack_start] = instance;
+                }
+
+                Code::CLASS => {
+                    let num_fields = read_byte!() as i32;
+                    self.create_class(fiber, num_fields);
+                    if self.has_error() {
+                        runtime_error!();
+                    }
+                }
+
+                Code::METHOD_INSTANCE | Code::METHOD_STATIC => {
+                    let symbol = read_short!();
+                    let class_obj = pop!().as_obj();
+                    let method = pop!();
+                    let module = self.heap.as_fn(fn_obj).module;
+                    self.bind_method(instruction, symbol, module, class_obj, method);
+                    if self.has_error() {
+                        runtime_error!();
+                    }
+                }
+
+                Code::END_MODULE => {
+                    self.last_module = Some(self.heap.as_fn(fn_obj).module);
+                    push!(Value::Null);
+                }
+
+                Code::IMPORT_MODULE => {
+                    // Make a slot on the stack for the module's fiber to place the return
+                    // value. It will be popped after this fiber is resumed. Store the
+                    // imported module's closure in the slot in case a GC happens when
+                    // invoking the closure.
+                    let name = self.heap.as_fn(fn_obj).constants[read_short!()];
+                    let importer = self.heap.as_fn(fn_obj).module;
+                    let result = self.import_module(importer, name);
+                    push!(result);
+                    if self.has_error() {
+                        runtime_error!();
+                    }
+
+                    // If we get a closure, call it to execute the module body.
+                    if self.heap.is_closure(result) {
+                        store_frame!();
+                        let module_start = self.heap.as_fiber(fiber).stack.len() - 1;
+                        if !self.push_call_frame(fiber, result.as_obj(), module_start) {
+                            runtime_error!();
+                        }
+                        load_frame!();
+                    } else {
+                        // The module has already been loaded. Remember it so we can import
+                        // variables from it if needed.
+                        self.last_module = Some(result.as_obj());
+                    }
+                }
+
+                Code::IMPORT_VARIABLE => {
+                    let variable = self.heap.as_fn(fn_obj).constants[read_short!()];
+                    let module = self
+                        .last_module
+                        .expect("Should have already imported module.");
+                    let result = self.get_module_variable(module, variable);
+                    if self.has_error() {
+                        runtime_error!();
+                    }
+                    push!(result);
+                }
+
+                // A CODE_END should always be preceded by a CODE_RETURN. If we get here,
+                // the compiler generated wrong code.
+                Code::END => unreachable!(),
+
+                _ => unimplemented!("Instruction {:?} is not supported yet.", instruction),
+            }
+        }
+    }
 }
 
-pub struct WrenVM;
+impl Drop for WrenVM {
+    // Writes out whatever output is still buffered.
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,664 @@
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use starling::{
+    Config, FiberResult, InterpretResult, StackFrame, StringValidation, WrenVM, WrenValue,
+};
+
+#[test]
+fn tokenize_collects_the_comment_above_a_declaration() {
+    let mut vm = WrenVM::new(Config::default());
+    let source = "System.print(1)\n\n// The answer.\nvar answer = 42 // Not collected.\n";
+
+    let tokens = vm.tokenize(source, true);
+    assert_eq!(tokens.comments, [(3, "The answer.".to_string())]);
+    assert!(tokens.tokens.contains(&(4, "answer".to_string())));
+
+    let tokens = vm.tokenize(source, false);
+    assert!(tokens.comments.is_empty());
+}
+
+#[test]
+fn host_drives_a_generator_fiber() {
+    let (mut vm, _output, _errors) = common::vm_with(Config::default());
+    let source = r#"
+var counter = Fiber.new {|start|
+  var n = start
+  while (n < start + 3) {
+    var step = Fiber.yield(n)
+    n = n + step
+  }
+  return "done"
+}
+"#;
+    assert_eq!(vm.interpret("main", source), InterpretResult::Success);
+    let counter = vm.get_variable("main", "counter").unwrap();
+
+    let mut seen = Vec::new();
+    let mut result = vm.resume(&counter, WrenValue::Num(10.0));
+    while let FiberResult::Yielded(value) = result {
+        seen.push(value);
+        result = vm.resume(&counter, WrenValue::Num(1.0));
+    }
+    assert_eq!(
+        seen,
+        [
+            WrenValue::Num(10.0),
+            WrenValue::Num(11.0),
+            WrenValue::Num(12.0)
+        ]
+    );
+    assert_eq!(
+        result,
+        FiberResult::Completed(WrenValue::String("done".into()))
+    );
+
+    assert_eq!(
+        vm.resume(&counter, WrenValue::Null),
+        FiberResult::Errored(WrenValue::String("Cannot resume a finished fiber.".into()))
+    );
+}
+
+#[test]
+fn resume_reports_errors() {
+    let (mut vm, _output, errors) = common::vm_with(Config::default());
+    let source = "var bad = Fiber.new { Fiber.abort(\"Oops.\") }\nvar number = 1\n";
+    assert_eq!(vm.interpret("main", source), InterpretResult::Success);
+
+    let bad = vm.get_variable("main", "bad").unwrap();
+    let oops = FiberResult::Errored(WrenValue::String("Oops.".into()));
+    assert_eq!(vm.resume(&bad, WrenValue::Null), oops);
+    assert_eq!(errors.borrow()[0], "Oops.");
+    assert_eq!(vm.resume(&bad, WrenValue::Null), oops);
+
+    let number = vm.get_variable("main", "number").unwrap();
+    assert_eq!(
+        vm.resume(&number, WrenValue::Null),
+        FiberResult::Errored(WrenValue::String("Handle must hold a fiber.".into()))
+    );
+}
+
+fn budget_config(budget: u64) -> Config {
+    Config {
+        instruction_budget: Some(budget),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn instruction_budget_halts_an_infinite_loop() {
+    let (mut vm, output, _errors) = common::vm_with(budget_config(1000));
+    let result = vm.interpret("main", "System.print(\"start\")\nwhile (true) {}\n");
+    assert_eq!(result, InterpretResult::Budget);
+    assert_eq!(output.borrow().as_str(), "start\n");
+
+    // It stays stopped each time it is continued.
+    let fiber = vm.take_interrupted_fiber().unwrap();
+    assert_eq!(vm.resume(&fiber, WrenValue::Null), FiberResult::Budget);
+    assert_eq!(vm.resume(&fiber, WrenValue::Null), FiberResult::Budget);
+}
+
+#[test]
+fn interrupted_fiber_resumes_where_it_stopped() {
+    let (mut vm, output, _errors) = common::vm_with(budget_config(1000));
+    let source = r#"
+var inner = Fiber.new {
+  var i = 0
+  while (i < 1000) i = i + 1
+  return i
+}
+System.print(inner.call())
+return "end"
+"#;
+    assert_eq!(vm.interpret("main", source), InterpretResult::Budget);
+    assert!(vm.last_value().is_none());
+
+    let fiber = vm.take_interrupted_fiber().unwrap();
+    assert!(vm.take_interrupted_fiber().is_none());
+
+    let mut stops = 1;
+    let mut result = vm.resume(&fiber, WrenValue::Null);
+    while result == FiberResult::Budget {
+        stops += 1;
+        vm.collect_garbage();
+        result = vm.resume(&fiber, WrenValue::Null);
+    }
+    assert!(stops > 2);
+    assert_eq!(
+        result,
+        FiberResult::Completed(WrenValue::String("end".into()))
+    );
+    assert_eq!(output.borrow().as_str(), "1000\n");
+    assert_eq!(vm.last_value(), Some(WrenValue::String("end".into())));
+}
+
+#[test]
+fn last_value_reads_back_a_module_return() {
+    let mut vm = WrenVM::new(Config::default());
+    assert!(vm.last_value().is_none());
+
+    assert_eq!(
+        vm.interpret("main", "return 6 * 7\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(vm.last_value(), Some(WrenValue::Num(42.0)));
+
+    assert_eq!(
+        vm.interpret("other", "var x = 1\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(vm.last_value(), Some(WrenValue::Null));
+
+    assert_eq!(
+        vm.interpret("main", "return [1]\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(vm.last_value(), Some(WrenValue::Object("List".into())));
+}
+
+#[test]
+fn allocation_hook_observes_strings_being_created() {
+    let allocated = Rc::new(Cell::new(0));
+    let freed = Rc::new(Cell::new(0));
+
+    let (on_allocate, on_free) = (allocated.clone(), freed.clone());
+    let config = Config {
+        allocation_fn: Some(Box::new(move |old_size, new_size| {
+            if old_size == 0 {
+                on_allocate.set(on_allocate.get() + 1);
+            } else if new_size == 0 {
+                on_free.set(on_free.get() + 1);
+            }
+        })),
+        ..Config::default()
+    };
+    let mut vm = WrenVM::new(config);
+    let before = allocated.get();
+
+    let result = vm.interpret(
+        "main",
+        "for (i in 1..100) {\n  var s = \"item \" + i.toString\n}\n",
+    );
+    assert_eq!(result, InterpretResult::Success);
+    assert!(allocated.get() - before >= 100);
+
+    // The strings are garbage once the loop ends.
+    vm.collect_garbage();
+    assert!(freed.get() >= 100);
+}
+
+#[test]
+fn allocating_past_the_heap_size_collects_garbage() {
+    let freed = Rc::new(Cell::new(0));
+    let on_free = freed.clone();
+    let config = Config {
+        allocation_fn: Some(Box::new(move |_old_size, new_size| {
+            if new_size == 0 {
+                on_free.set(on_free.get() + 1);
+            }
+        })),
+        initial_heap_size: 64 * 1024,
+        min_heap_size: 64 * 1024,
+        ..Config::default()
+    };
+    let mut vm = WrenVM::new(config);
+
+    // Nothing calls System.gc(), so only the heap growing can free the strings.
+    let result = vm.interpret(
+        "main",
+        "var kept = \"kept\"\nvar i = 0\nwhile (i < 10000) {\n  var s = \"item \" + i.toString\n  i = i + 1\n}\nreturn kept\n",
+    );
+    assert_eq!(result, InterpretResult::Success);
+    assert!(freed.get() >= 10000, "only {} objects freed", freed.get());
+    assert_eq!(vm.last_value(), Some(WrenValue::String("kept".into())));
+}
+
+#[test]
+fn reset_user_modules_keeps_the_core_library() {
+    let (mut vm, output, errors) = common::vm_with(Config::default());
+    vm.collect_garbage();
+    let core_objects = vm.debug_object_count();
+
+    let result = vm.interpret("main", "var answer = [1, 2, 3].map {|n| n * 2 }.toList\n");
+    assert_eq!(result, InterpretResult::Success);
+    let answer = vm.get_variable("main", "answer").unwrap();
+    vm.release_handle(answer);
+
+    vm.reset_user_modules();
+    assert_eq!(vm.debug_object_count(), core_objects);
+    assert!(vm.get_variable("main", "answer").is_none());
+
+    let result = vm.interpret("main", "System.print(\"still here\")\n");
+    assert_eq!(result, InterpretResult::Success);
+    vm.flush();
+    assert_eq!(output.borrow().as_str(), "still here\n");
+    assert!(errors.borrow().is_empty());
+}
+
+fn checked_sqrt(vm: &mut WrenVM) {
+    let n = vm.get_slot_double(1);
+    if n < 0.0 {
+        vm.set_slot_string(0, "Cannot take the square root of a negative number.");
+        vm.abort_fiber(0);
+        return;
+    }
+    vm.set_slot_double(0, n.sqrt());
+}
+
+#[test]
+fn foreign_method_aborts_into_fiber_try() {
+    let config = Config {
+        bind_foreign_method_fn: Some(Box::new(
+            |_vm: &mut WrenVM, module: &str, class_name: &str, is_static: bool, signature: &str| {
+                match (module, class_name, is_static, signature) {
+                    ("main", "Math", true, "sqrt(_)") => Some(checked_sqrt as _),
+                    _ => None,
+                }
+            },
+        )),
+        ..Config::default()
+    };
+    let (mut vm, output, errors) = common::vm_with(config);
+    let source = r#"
+class Math {
+  foreign static sqrt(n)
+}
+
+System.print(Math.sqrt(9))
+var fiber = Fiber.new { Math.sqrt(-1) }
+System.print(fiber.try())
+System.print("after")
+"#;
+    let result = vm.interpret("main", source);
+    vm.flush();
+    assert_eq!(result, InterpretResult::Success);
+    assert_eq!(
+        output.borrow().as_str(),
+        "3\nCannot take the square root of a negative number.\nafter\n"
+    );
+    assert!(errors.borrow().is_empty());
+}
+
+fn invalid_utf8(vm: &mut WrenVM) {
+    vm.set_slot_bytes(0, b"a\xffb");
+}
+
+// Runs [source] with a foreign `Host.data` that returns invalid UTF-8 built
+// under [validation].
+fn run_with_host_data(validation: StringValidation, source: &str) -> String {
+    let config = Config {
+        string_validation: validation,
+        bind_foreign_method_fn: Some(Box::new(
+            |_vm: &mut WrenVM,
+             _module: &str,
+             class_name: &str,
+             _is_static: bool,
+             signature: &str| {
+                match (class_name, signature) {
+                    ("Host", "data") => Some(invalid_utf8 as _),
+                    _ => None,
+                }
+            },
+        )),
+        ..Config::default()
+    };
+    let (mut vm, output, errors) = common::vm_with(config);
+    let source = format!("class Host {{\n  foreign static data\n}}\n{}", source);
+    assert_eq!(vm.interpret("main", &source), InterpretResult::Success);
+    vm.flush();
+    assert!(errors.borrow().is_empty());
+    let output = output.borrow().clone();
+    output
+}
+
+#[test]
+fn set_slot_bytes_under_each_string_validation() {
+    let source = "System.print(Host.data.bytes.toList.join(\",\"))\n";
+    assert_eq!(
+        run_with_host_data(StringValidation::Bytes, source),
+        "97,255,98\n"
+    );
+    assert_eq!(
+        run_with_host_data(StringValidation::Lossy, source),
+        "97,239,191,189,98\n"
+    );
+
+    let source = "var fiber = Fiber.new { Host.data }\nSystem.print(fiber.try())\n";
+    assert_eq!(
+        run_with_host_data(StringValidation::Strict, source),
+        "String must be valid UTF-8.\n"
+    );
+}
+
+#[test]
+fn call_function_with_an_argument() {
+    let mut vm = WrenVM::new(Config::default());
+    let source = "var double = Fn.new {|n| n * 2 }\nvar fail = Fn.new {|n| Fiber.abort(\"Bad \" + n) }\nvar number = 1\n";
+    assert_eq!(vm.interpret("main", source), InterpretResult::Success);
+
+    let double = vm.get_variable("main", "double").unwrap();
+    assert_eq!(
+        vm.call_function(&double, &[WrenValue::Num(21.0)]),
+        Ok(WrenValue::Num(42.0))
+    );
+
+    // Extra arguments are dropped, missing ones are an error.
+    assert_eq!(
+        vm.call_function(&double, &[WrenValue::Num(1.0), WrenValue::Null]),
+        Ok(WrenValue::Num(2.0))
+    );
+    let error = vm.call_function(&double, &[]).unwrap_err();
+    assert_eq!(
+        error.error,
+        WrenValue::String("Function expects more arguments.".into())
+    );
+    assert!(error.stack_trace.is_empty());
+
+    let error = vm
+        .call_function(&double, &[WrenValue::Object("List".into())])
+        .unwrap_err();
+    assert_eq!(
+        error.error,
+        WrenValue::String("Cannot pass an object to a function.".into())
+    );
+
+    let number = vm.get_variable("main", "number").unwrap();
+    let error = vm.call_function(&number, &[]).unwrap_err();
+    assert_eq!(
+        error.error,
+        WrenValue::String("Handle must hold a function.".into())
+    );
+
+    // An error inside the function comes back with where it happened.
+    let fail = vm.get_variable("main", "fail").unwrap();
+    let error = vm
+        .call_function(&fail, &[WrenValue::String("input".into())])
+        .unwrap_err();
+    assert_eq!(error.error, WrenValue::String("Bad input".into()));
+    assert_eq!(error.stack_trace[0].line, 2);
+}
+
+#[test]
+fn maps_work_under_any_hash_seed() {
+    let source = r#"
+var map = {}
+for (i in 0...100) map["key" + i.toString] = i
+map["key5"] = "five"
+map.remove("key6")
+System.print(map.count)
+System.print(map["key5"])
+System.print(map["key99"])
+System.print(map.containsKey("key6"))
+"#;
+    for seed in [None, Some(1), Some(0x5eed_5eed_5eed_5eed)] {
+        let config = Config {
+            hash_seed: seed,
+            ..Config::default()
+        };
+        let run = common::run_with(config, source);
+        assert_eq!(run.result, InterpretResult::Success);
+        assert_eq!(run.output, "99\nfive\n99\nfalse\n");
+    }
+}
+
+#[test]
+fn buffered_output_waits_for_a_flush() {
+    // Each call to the writer is one flush of the buffer.
+    let flushes: Rc<RefCell<Vec<String>>> = Rc::default();
+    let written = flushes.clone();
+    let config = Config {
+        buffer_output: true,
+        write_fn: Some(Box::new(move |_vm: &mut WrenVM, text: &str| {
+            written.borrow_mut().push(text.to_string())
+        })),
+        ..Config::default()
+    };
+    let mut vm = WrenVM::new(config);
+
+    let source = "System.print(\"a\")\nSystem.write(\"b\")\nSystem.flush()\nSystem.print(\"c\")\n";
+    assert_eq!(vm.interpret("main", source), InterpretResult::Success);
+    assert_eq!(*flushes.borrow(), ["a\nb"]);
+
+    vm.flush();
+    assert_eq!(*flushes.borrow(), ["a\nb", "c\n"]);
+
+    // Nothing is written when there is nothing buffered.
+    vm.flush();
+    assert_eq!(flushes.borrow().len(), 2);
+
+    // Dropping the VM writes out the rest.
+    assert_eq!(
+        vm.interpret("main", "System.print(\"d\")\n"),
+        InterpretResult::Success
+    );
+    drop(vm);
+    assert_eq!(*flushes.borrow(), ["a\nb", "c\n", "d\n"]);
+}
+
+// Resolves an import starting with "./" against the directory of [importer].
+fn resolve_relative(_vm: &mut WrenVM, importer: &str, name: &str) -> Option<String> {
+    let Some(name) = name.strip_prefix("./") else {
+        return Some(name.to_string());
+    };
+    match importer.rsplit_once('/') {
+        Some((directory, _)) => Some(format!("{}/{}", directory, name)),
+        None => Some(name.to_string()),
+    }
+}
+
+#[test]
+fn relative_imports_resolve_against_the_importer() {
+    let loaded: Rc<RefCell<Vec<String>>> = Rc::default();
+    let requested = loaded.clone();
+    let config = Config {
+        resolve_module_fn: Some(Box::new(resolve_relative)),
+        load_module_fn: Some(Box::new(move |_vm: &mut WrenVM, name: &str| {
+            requested.borrow_mut().push(name.to_string());
+            let source = match name {
+                "app/lib/math" => {
+                    "import \"./consts\" for Two\nvar Double = Fn.new {|n| n * Two }\n"
+                }
+                "app/lib/consts" => "var Two = 2\n",
+                _ => return None,
+            };
+            Some(source.to_string())
+        })),
+        ..Config::default()
+    };
+    let (mut vm, output, errors) = common::vm_with(config);
+
+    let source = "import \"./lib/math\" for Double\nSystem.print(Double.call(21))\n";
+    assert_eq!(vm.interpret("app/main", source), InterpretResult::Success);
+    vm.flush();
+    assert_eq!(output.borrow().as_str(), "42\n");
+    assert!(errors.borrow().is_empty());
+    assert_eq!(*loaded.borrow(), ["app/lib/math", "app/lib/consts"]);
+}
+
+#[test]
+fn loaded_modules_can_be_listed() {
+    let mut vm = WrenVM::new(Config::default());
+    assert!(vm.module_names().is_empty());
+    assert!(!vm.has_module("main"));
+
+    assert_eq!(
+        vm.interpret("main", "var a = 1\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(
+        vm.interpret("extra", "var b = 2\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(vm.module_names(), ["extra", "main"]);
+    assert!(vm.has_module("main"));
+    assert!(vm.has_module("extra"));
+    assert!(!vm.has_module("missing"));
+}
+
+#[test]
+fn collect_garbage_frees_unreachable_objects() {
+    let mut vm = WrenVM::new(Config::default());
+    let result = vm.interpret(
+        "main",
+        "var kept = [1, 2, 3]\nfor (i in 1..100) {\n  var garbage = [i]\n}\n",
+    );
+    assert_eq!(result, InterpretResult::Success);
+
+    let before = vm.debug_object_count();
+    vm.collect_garbage();
+    let after = vm.debug_object_count();
+    assert!(after < before, "{} objects before, {} after", before, after);
+
+    // Collecting again finds nothing new, and what's reachable survives.
+    vm.collect_garbage();
+    assert_eq!(vm.debug_object_count(), after);
+    let result = vm.interpret("main", "return kept.count\n");
+    assert_eq!(result, InterpretResult::Success);
+    assert_eq!(vm.last_value(), Some(WrenValue::Num(3.0)));
+}
+
+#[test]
+fn eval_in_module_sees_earlier_variables() {
+    let (mut vm, output, errors) = common::vm_with(Config::default());
+    assert_eq!(
+        vm.interpret("main", "var x = 10\n"),
+        InterpretResult::Success
+    );
+    assert_eq!(
+        vm.eval_in_module("main", "System.print(x + 1)\n"),
+        InterpretResult::Success
+    );
+
+    // Nothing is compiled for a module that isn't loaded, so it isn't a compile
+    // error and has no line.
+    assert_eq!(
+        vm.eval_in_module("missing", "System.print(1)\n"),
+        InterpretResult::RuntimeError
+    );
+    vm.flush();
+    assert_eq!(output.borrow().as_str(), "11\n");
+    assert_eq!(
+        errors.borrow().as_slice(),
+        ["Module 'missing' is not loaded."]
+    );
+    assert_eq!(
+        vm.last_error_message().as_deref(),
+        Some("Module 'missing' is not loaded.")
+    );
+    assert!(vm.last_stack_trace().is_empty());
+}
+
+#[test]
+fn uncaught_error_is_kept_with_its_location() {
+    let mut vm = WrenVM::new(Config::default());
+    let result = vm.interpret("main", "var list = [1, 2]\n\nlist[5]\n");
+    assert_eq!(result, InterpretResult::RuntimeError);
+    assert_eq!(
+        vm.last_error_message().as_deref(),
+        Some("Subscript out of bounds.")
+    );
+    assert_eq!(
+        vm.last_stack_trace(),
+        [StackFrame {
+            module: "main".into(),
+            line: 3,
+            function: "(script)".into(),
+        }]
+    );
+
+    // Running code that succeeds clears them.
+    assert_eq!(vm.interpret("main", "list[0]\n"), InterpretResult::Success);
+    assert!(vm.last_error_message().is_none());
+    assert!(vm.last_stack_trace().is_empty());
+}
+
+#[test]
+fn stack_trace_lists_each_call_innermost_first() {
+    let mut vm = WrenVM::new(Config::default());
+    let source = r#"class Foo {
+  static outer() { inner(1) }
+  static inner(n) {
+    [n].map {|x| x.nope }.toList
+  }
+}
+Foo.outer()
+"#;
+    assert_eq!(vm.interpret("main", source), InterpretResult::RuntimeError);
+    assert_eq!(
+        vm.last_error_message().as_deref(),
+        Some("Num does not implement 'nope'.")
+    );
+
+    // Frames in the core library's own code are left out.
+    let frames: Vec<_> = vm
+        .last_stack_trace()
+        .into_iter()
+        .map(|frame| (frame.module, frame.line, frame.function))
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            ("main".into(), 4, "map(_) block argument".into()),
+            ("main".into(), 4, "inner(_)".into()),
+            ("main".into(), 2, "outer()".into()),
+            ("main".into(), 7, "(script)".into()),
+        ]
+    );
+}
+
+#[test]
+fn suspended_fiber_returns_to_the_host() {
+    let (mut vm, output, _errors) = common::vm_with(Config::default());
+    let source = r#"
+var worker = Fiber.new {
+  System.print("before")
+  System.print(Fiber.suspend())
+  System.print("after")
+  return "done"
+}
+System.print(worker.call())
+return "end"
+"#;
+    assert_eq!(vm.interpret("main", source), InterpretResult::Suspended);
+    assert!(vm.is_suspended());
+    assert!(vm.last_value().is_none());
+    vm.flush();
+    assert_eq!(output.borrow().as_str(), "before\n");
+
+    // The suspended fiber, and then the module that called it, carry on.
+    assert_eq!(vm.resume_suspended(), InterpretResult::Success);
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.last_value(), Some(WrenValue::String("end".into())));
+    vm.flush();
+    assert_eq!(output.borrow().as_str(), "before\nnull\nafter\ndone\n");
+
+    assert_eq!(vm.resume_suspended(), InterpretResult::RuntimeError);
+    assert_eq!(
+        vm.last_error_message().as_deref(),
+        Some("No fiber is suspended.")
+    );
+}
+
+#[test]
+fn print_fn_receives_each_piece_of_text() {
+    let printed = Rc::new(RefCell::new(Vec::new()));
+    let received = printed.clone();
+    let config = Config {
+        print_fn: Some(Box::new(move |text: &str| {
+            received.borrow_mut().push(text.to_string())
+        })),
+        buffer_output: true,
+        ..Config::default()
+    };
+    let (mut vm, output, _errors) = common::vm_with(config);
+
+    let result = vm.interpret("main", "System.print(\"hi\")\nSystem.write(1 + 2)\n");
+    assert_eq!(result, InterpretResult::Success);
+    vm.flush();
+    assert_eq!(printed.borrow().as_slice(), ["hi", "\n", "3"]);
+
+    // Nothing goes to the write callback or the buffer.
+    assert_eq!(output.borrow().as_str(), "");
+}
@@ -0,0 +1,102 @@
+// Helpers shared by the integration tests. Each test binary uses only some of
+// them.
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use starling::{Config, ErrorType, InterpretResult, WrenVM};
+
+// What running a script produced.
+pub struct Run {
+    pub result: InterpretResult,
+
+    // Everything the script printed.
+    pub output: String,
+
+    // Every error reported while compiling or running the script, each
+    // formatted the way the command line tool prints it.
+    pub errors: Vec<String>,
+}
+
+// The text a VM made by [vm_with] has printed and the errors it has reported.
+pub type Output = Rc<RefCell<String>>;
+pub type Errors = Rc<RefCell<Vec<String>>>;
+
+// Creates a VM from [config] whose printed text and errors are collected in the
+// returned buffers.
+pub fn vm_with(config: Config) -> (WrenVM, Output, Errors) {
+    let output = Output::default();
+    let errors = Errors::default();
+
+    let written = output.clone();
+    let reported = errors.clone();
+    let config = Config {
+        write_fn: Some(Box::new(move |_vm: &mut WrenVM, text: &str| {
+            written.borrow_mut().push_str(text)
+        })),
+        error_fn: Some(Box::new(
+            move |_vm: &mut WrenVM, ty: ErrorType, module: &str, line: i32, message: &str| {
+                reported
+                    .borrow_mut()
+                    .push(format_error(ty, module, line, message))
+            },
+        )),
+        ..config
+    };
+
+    (WrenVM::new(config), output, errors)
+}
+
+fn format_error(ty: ErrorType, module: &str, line: i32, message: &str) -> String {
+    match ty {
+        ErrorType::StackTrace => format!("[{} line {}] in {}", module, line, message),
+        ErrorType::Runtime | ErrorType::Snippet => message.to_string(),
+        _ => format!("[{} line {}] {}", module, line, message),
+    }
+}
+
+// Runs [source] as the module "main" in a new VM with the default
+// configuration.
+pub fn run(source: &str) -> Run {
+    run_with(Config::default(), source)
+}
+
+// Runs [source] as the module "main" in a new VM made from [config].
+pub fn run_with(config: Config, source: &str) -> Run {
+    let (mut vm, output, errors) = vm_with(config);
+    let result = vm.interpret("main", source);
+    vm.flush();
+
+    let output = output.borrow().clone();
+    let errors = errors.borrow().clone();
+    Run {
+        result,
+        output,
+        errors,
+    }
+}
+
+// Runs [source] and checks that it succeeds and prints [expected].
+pub fn expect_output(source: &str, expected: &str) {
+    let run = run(source);
+    assert_eq!(run.errors, Vec::<String>::new());
+    assert_eq!(run.result, InterpretResult::Success);
+    assert_eq!(run.output, expected);
+}
+
+// Runs [source] and checks that it aborts with the uncaught runtime error
+// [message].
+pub fn expect_runtime_error(source: &str, message: &str) {
+    let run = run(source);
+    assert_eq!(run.result, InterpretResult::RuntimeError);
+    assert_eq!(run.errors.first().map(String::as_str), Some(message));
+}
+
+// Compiles [source] and checks that it fails with exactly the compile errors
+// [expected].
+pub fn expect_compile_errors(source: &str, expected: &[&str]) {
+    let run = run(source);
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(run.errors, expected);
+}
@@ -0,0 +1,450 @@
+mod common;
+
+use common::{expect_output, run};
+use starling::{Config, InterpretResult};
+
+#[test]
+fn snippet_carets_align_with_the_bad_token() {
+    let run = run("var a = 1\nvar b = a +* 2\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 2] Error at '*': Expected expression.",
+            "var b = a +* 2\n           ^",
+        ]
+    );
+
+    let (line, carets) = run.errors[1].split_once('\n').unwrap();
+    assert_eq!(carets.find('^'), line.find('*'));
+}
+
+#[test]
+fn snippet_underlines_the_whole_token() {
+    let run = run("var a = 1 + class\n");
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 1] Error at 'class': Expected expression.",
+            "var a = 1 + class\n            ^^^^^",
+        ]
+    );
+}
+
+// Declares [count] locals in a block and reads the last one.
+fn many_locals(count: usize) -> String {
+    let mut source = String::from("{\n");
+    for i in 0..count {
+        source.push_str(&format!("  var a{} = {}\n", i, i));
+    }
+    source.push_str(&format!("  System.print(a{})\n}}\n", count - 1));
+    source
+}
+
+#[test]
+fn slot_zero_counts_toward_the_local_limit() {
+    let run255 = run(&many_locals(255));
+    assert_eq!(run255.errors, Vec::<String>::new());
+    assert_eq!(run255.output, "254\n");
+
+    let run256 = run(&many_locals(256));
+    assert_eq!(run256.result, InterpretResult::CompileError);
+    assert_eq!(
+        run256.errors[0],
+        "[main line 257] Error at 'a255': Cannot declare more than 256 variables in one scope."
+    );
+}
+
+#[test]
+fn out_of_range_number_literals() {
+    expect_output(
+        "System.print(1e400)\nSystem.print(-1e400)\nSystem.print(1e-400)\nSystem.print(1e400 == 1 / 0)\n",
+        "infinity\n-infinity\n0\ntrue\n",
+    );
+}
+
+#[test]
+fn unterminated_scientific_notation() {
+    let run = run("var a = 1e\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors[0],
+        "[main line 1] Error: Unterminated scientific notation."
+    );
+}
+
+#[test]
+fn method_chain_continues_on_a_line_starting_with_a_dot() {
+    expect_output(
+        "var y = [1, 2, 3]\n  .map {|n| n * 2 }\n  .where {|n| n > 2 }\nSystem.print(y.toList.count)\n",
+        "2\n",
+    );
+}
+
+#[test]
+fn any_expression_continues_on_a_line_starting_with_a_dot() {
+    expect_output(
+        "System.print(\"abc\"\n  .toList.count)\nSystem.print((1..3)\n  .toList.count)\nSystem.print(1\n  .toString)\nSystem.print([1, 2, 3]\n  .count)\n",
+        "3\n3\n1\n3\n",
+    );
+}
+
+#[test]
+fn stray_as_keyword() {
+    let run = run("var y = 2\nvar x = 1 as y\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 2] Error at 'as': Unexpected 'as' keyword.",
+            "var x = 1 as y\n          ^^",
+        ]
+    );
+}
+
+#[test]
+fn single_character_names_are_not_keywords() {
+    expect_output(
+        "var i = 1\nvar f = 2\nvar n = 3\nvar t = 4\nvar w = 5\nvar a = 6\nSystem.print(i + f + n + t + w + a)\n",
+        "21\n",
+    );
+}
+
+#[test]
+fn list_and_map_literals_allow_a_trailing_comma() {
+    expect_output(
+        "System.print([1, 2, 3].count)\nSystem.print([1, 2, 3,].count)\nSystem.print([\n  1,\n  2,\n].count)\n",
+        "3\n3\n2\n",
+    );
+    expect_output(
+        "System.print({\"a\": 1, \"b\": 2}.count)\nSystem.print({\"a\": 1, \"b\": 2,}.count)\nSystem.print({\n  \"a\": 1,\n}.count)\n",
+        "2\n2\n1\n",
+    );
+}
+
+#[test]
+fn list_literal_rejects_a_leading_comma() {
+    let run = run("var a = [,1]\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 1] Error at ',': Expected expression.",
+            "var a = [,1]\n         ^",
+        ]
+    );
+}
+
+#[test]
+fn list_literal_holds_its_elements_in_order() {
+    expect_output(
+        "var a = 2\nvar list = [1, a, a + 1, \"four\"]\nSystem.print(list.count)\nSystem.print(list.join(\",\"))\nSystem.print([].count)\n",
+        "4\n1,2,3,four\n0\n",
+    );
+}
+
+#[test]
+fn map_literal_holds_its_entries() {
+    expect_output(
+        "var key = \"b\"\nvar map = {\"a\": 1, key: 2, 3: \"three\"}\nSystem.print(map.count)\nSystem.print(map[\"a\"])\nSystem.print(map[\"b\"])\nSystem.print(map[3])\nSystem.print({}.count)\n",
+        "3\n1\n2\nthree\n0\n",
+    );
+}
+
+#[test]
+fn module_variable_can_be_used_before_its_definition() {
+    expect_output(
+        "var f = Fn.new { Later }\nclass A {\n  static get { Later }\n}\nvar Later = \"defined\"\nSystem.print(f.call())\nSystem.print(A.get)\n",
+        "defined\ndefined\n",
+    );
+}
+
+#[test]
+fn module_variable_that_is_never_defined() {
+    let run = run("var f = Fn.new { missing }\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors,
+        ["[main line 1] Error at 'missing': Variable is used but not defined."]
+    );
+
+    // A long name is cut short the way a long token is.
+    let name = "a".repeat(100);
+    let long = common::run(&format!("var f = Fn.new {{ {} }}\n", name));
+    assert_eq!(
+        long.errors,
+        [format!(
+            "[main line 1] Error at '{}...': Variable is used but not defined.",
+            &name[..64]
+        )]
+    );
+}
+
+#[test]
+fn code_after_a_top_level_return_is_unreachable() {
+    let run = run("System.print(1)\nreturn\nSystem.print(2)\n");
+    assert_eq!(run.result, InterpretResult::Success);
+    assert_eq!(run.output, "1\n");
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 3] Warning at 'System': Unreachable code after 'return'.",
+            "System.print(2)\n^^^^^^",
+        ]
+    );
+}
+
+#[test]
+fn code_after_a_conditional_return_is_reachable() {
+    expect_output(
+        "var f = Fn.new {|x|\n  if (x) return 1\n  return 2\n}\nSystem.print(f.call(false))\n",
+        "2\n",
+    );
+}
+
+#[test]
+fn snippet_expands_tabs_to_the_configured_width() {
+    let config = Config {
+        tab_width: 4,
+        ..Config::default()
+    };
+    let run = common::run_with(config, "{\n\tvar b = 1 +* 2\n}\n");
+    assert_eq!(run.result, InterpretResult::CompileError);
+    assert_eq!(
+        run.errors,
+        [
+            "[main line 2] Error at '*': Expected expression.",
+            "    var b = 1 +* 2\n               ^",
+        ]
+    );
+
+    // By default a tab counts as a single column.
+    let default = common::run("{\n\tvar b = 1 +* 2\n}\n");
+    assert_eq!(default.errors[1], " var b = 1 +* 2\n            ^");
+}
+
+#[test]
+fn raw_string_keeps_tabs_inside_its_lines() {
+    expect_output(
+        "var s = \"\"\"\n\t\tfirst\tline\n\t\t\tindented\n\t\tlast\n\t\t\"\"\"\nSystem.print(s)\n",
+        "first\tline\n\tindented\nlast\n",
+    );
+}
+
+#[test]
+fn invalid_assignment_targets() {
+    common::expect_compile_errors(
+        "class A {\n  foo { this = 1 }\n}\n",
+        &[
+            "[main line 2] Error at '=': Invalid assignment target.",
+            "  foo { this = 1 }\n             ^",
+        ],
+    );
+    common::expect_compile_errors(
+        "1 = 2\n",
+        &[
+            "[main line 1] Error at '=': Invalid assignment target.",
+            "1 = 2\n  ^",
+        ],
+    );
+    common::expect_compile_errors(
+        "var f = Fn.new {}\nf.call() = 3\n",
+        &[
+            "[main line 2] Error at '=': Invalid assignment target.",
+            "f.call() = 3\n         ^",
+        ],
+    );
+}
+
+#[test]
+fn fields_are_only_allowed_in_instance_methods() {
+    common::expect_compile_errors(
+        "class A {\n  static get { _x }\n}\n",
+        &[
+            "[main line 2] Error at '_x': Cannot reference a field in a static method.",
+            "  static get { _x }\n               ^^",
+        ],
+    );
+    expect_output(
+        "class A {\n  construct new() { _x = 1 }\n  get { _x }\n}\nSystem.print(A.new().get)\n",
+        "1\n",
+    );
+}
+
+#[test]
+fn local_cannot_be_read_in_its_own_initializer() {
+    common::expect_compile_errors(
+        "{\n  var x = x\n}\n",
+        &[
+            "[main line 2] Error at 'x': Cannot read local variable in its own initializer.",
+            "  var x = x\n          ^",
+        ],
+    );
+
+    // Even when it shadows an outer variable of the same name.
+    common::expect_compile_errors(
+        "var x = 1\n{\n  var x = x + 1\n}\n",
+        &[
+            "[main line 3] Error at 'x': Cannot read local variable in its own initializer.",
+            "  var x = x + 1\n          ^",
+        ],
+    );
+}
+
+// The first line of each error [run] reported, without the quoted source.
+fn error_lines(run: &common::Run) -> Vec<&str> {
+    run.errors
+        .iter()
+        .filter(|error| !error.contains('\n'))
+        .map(String::as_str)
+        .collect()
+}
+
+#[test]
+fn each_bad_statement_reports_one_error() {
+    let errors = run("var a = 1 +* 2\nvar b = 3\nvar c = b */ 4\nSystem.print(b)\n");
+    assert_eq!(
+        error_lines(&errors),
+        [
+            "[main line 1] Error at '*': Expected expression.",
+            "[main line 3] Error at '/': Expected expression.",
+        ]
+    );
+
+    // A block argument in a bad statement is skipped along with it, rather than
+    // its "}" being taken for the end of a block.
+    let errors = run(
+        "var list = [1, 2, 3]\nvar a = 1 +* list.map {|x| x }\nvar b = list.map {|x| x }.count *+ 2\nSystem.print(list.map {|x| x * }.count)\nvar c = 1 +* {\"key\": 1}\n",
+    );
+    assert_eq!(
+        error_lines(&errors),
+        [
+            "[main line 2] Error at '*': Expected expression.",
+            "[main line 3] Error at '+': Expected expression.",
+            "[main line 4] Error at '}': Expected expression.",
+            "[main line 5] Error at '*': Expected expression.",
+        ]
+    );
+
+    // Inside a block, recovery resumes at the block's own statements.
+    let errors = run(
+        "var f = Fn.new {|x|\n  var y = x +* list.map {|n| n }\n  var z = x */ 2\n  return x\n}\nvar g = 1 +* 2\n",
+    );
+    assert_eq!(
+        error_lines(&errors),
+        [
+            "[main line 2] Error at '*': Expected expression.",
+            "[main line 3] Error at '/': Expected expression.",
+            "[main line 6] Error at '*': Expected expression.",
+        ]
+    );
+
+    // A bad statement that consumes the block's "}" closes the block.
+    let errors = run("var f = Fn.new {|x|\n  var y = x +\n}\nvar g = 1 +* 2\n");
+    assert_eq!(
+        error_lines(&errors),
+        [
+            "[main line 3] Error at '}': Expected expression.",
+            "[main line 4] Error at '*': Expected expression.",
+        ]
+    );
+}
+
+// A comma-separated list of [count] names or numbers, like "a1, a2, a3".
+fn numbered(prefix: &str, count: usize) -> String {
+    (1..=count)
+        .map(|i| format!("{}{}", prefix, i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[test]
+fn methods_take_at_most_sixteen_parameters() {
+    expect_output(
+        &format!(
+            "class Foo {{\n  static m({}) {{ a16 }}\n}}\nSystem.print(Foo.m({}))\n",
+            numbered("a", 16),
+            numbered("", 16)
+        ),
+        "16\n",
+    );
+
+    let errors = run(&format!(
+        "class Foo {{\n  static m({}) {{ a17 }}\n}}\n",
+        numbered("a", 17)
+    ));
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 2] Error at ',': Methods cannot have more than 16 parameters."]
+    );
+
+    let errors = run(&format!("System.print({})\n", numbered("", 17)));
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 1] Error at ',': Methods cannot have more than 16 parameters."]
+    );
+}
+
+#[test]
+fn block_comments() {
+    expect_output(
+        "System.print(1 /*/ */ + 2)\nSystem.print(1 /**/ + 2)\nSystem.print(1 /*/**/*/ + 2)\n",
+        "3\n3\n3\n",
+    );
+
+    // The error is reported where the file ends.
+    let errors = run("System.print(1)\n/* open\nstill open\n");
+    assert_eq!(errors.result, InterpretResult::CompileError);
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 4] Error: Unterminated block comment."]
+    );
+}
+
+#[test]
+fn unterminated_interpolation() {
+    let errors = run("var b = 1\nSystem.print(\"a %(b");
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 2] Error: Unterminated string."]
+    );
+
+    let errors = run("System.print(\"a %(1 2) c\")\n");
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 1] Error at '2': Expect end of string interpolation."]
+    );
+}
+
+#[test]
+fn configured_limits_on_source_size_and_nesting() {
+    let config = Config {
+        max_source_length: Some(10),
+        ..Config::default()
+    };
+    let errors = common::run_with(config, "var a = 1 + 2\n");
+    assert_eq!(errors.result, InterpretResult::CompileError);
+    assert_eq!(errors.errors, ["[main line 1] Error: Source too large."]);
+
+    let source = format!("var a = {}1{}\n", "(".repeat(20), ")".repeat(20));
+    let config = Config {
+        max_nesting_depth: 10,
+        ..Config::default()
+    };
+    let errors = common::run_with(config, &source);
+    assert_eq!(
+        error_lines(&errors),
+        ["[main line 1] Error at '(': Expression nesting too deep."]
+    );
+
+    // Source within both limits runs.
+    let config = Config {
+        max_source_length: Some(100),
+        max_nesting_depth: 30,
+        ..Config::default()
+    };
+    assert_eq!(
+        common::run_with(config, &source).result,
+        InterpretResult::Success
+    );
+}
@@ -0,0 +1,542 @@
+mod common;
+
+use common::{expect_output, expect_runtime_error, run};
+use starling::InterpretResult;
+
+#[test]
+fn list_range_subscripts() {
+    expect_output(
+        r#"
+var list = [1, 2, 3, 4, 5]
+System.print(list[1..3].join(","))
+System.print(list[1...3].join(","))
+System.print(list[3..1].join(","))
+System.print(list[-2..-1].join(","))
+System.print(list[2...2].count)
+"#,
+        "2,3,4\n2,3\n4,3,2\n4,5\n0\n",
+    );
+    expect_runtime_error("[1, 2, 3][1..3]", "Range end out of bounds.");
+    expect_runtime_error("[1, 2, 3][5..1]", "Range start out of bounds.");
+}
+
+#[test]
+fn string_range_subscripts() {
+    expect_output(
+        r#"
+System.print("hello"[1..3])
+System.print("hello"[1...3])
+System.print("hello"[3..1])
+// String indices count bytes, and "é" takes two.
+System.print("héllo"[0..2])
+"#,
+        "ell\nel\nlle\nhé\n",
+    );
+    expect_runtime_error("\"abc\"[1..3]", "Range end out of bounds.");
+}
+
+#[test]
+fn num_from_string() {
+    expect_output(
+        r#"
+System.print(Num.fromString("3.14"))
+System.print(Num.fromString("-12"))
+System.print(Num.fromString("0xff"))
+System.print(Num.fromString("2.5e3"))
+System.print(Num.fromString("1e-2"))
+System.print(Num.fromString("  42  "))
+System.print(Num.fromString("\t7\n"))
+System.print(Num.fromString("3x"))
+System.print(Num.fromString("x3"))
+System.print(Num.fromString("1e"))
+System.print(Num.fromString(""))
+System.print(Num.fromString("   "))
+"#,
+        "3.14\n-12\n255\n2500\n0.01\n42\n7\nnull\nnull\nnull\nnull\nnull\n",
+    );
+}
+
+#[test]
+fn string_concatenation() {
+    expect_output(
+        "System.print(\"a\" + \"b\")\nSystem.print(\"\" + \"x\" + \"\")\n",
+        "ab\nx\n",
+    );
+    expect_runtime_error("\"a\" + 1", "Right operand must be a string.");
+}
+
+#[test]
+fn negative_zero() {
+    expect_output(
+        r#"
+System.print(-0 == 0)
+System.print(0 == -0)
+System.print(1 / -0 == -1 / 0)
+System.print(1 / -0)
+System.print(1 / 0)
+System.print(-0)
+System.print((-0).toString)
+var map = {}
+map[0] = "zero"
+map[-0] = "negative zero"
+System.print(map.count)
+System.print(map[0])
+"#,
+        "true\ntrue\ntrue\n-infinity\ninfinity\n-0\n-0\n1\nnegative zero\n",
+    );
+}
+
+#[test]
+fn system_clock_increases() {
+    expect_output(
+        r#"
+var start = System.clock
+System.print(start is Num)
+System.print(start >= 0)
+var i = 0
+while (i < 100000) i = i + 1
+System.print(System.clock > start)
+"#,
+        "true\ntrue\ntrue\n",
+    );
+}
+
+#[test]
+fn system_gc_runs() {
+    expect_output(
+        r#"
+var garbage = null
+for (i in 1..1000) garbage = [i, i.toString]
+System.print(System.gc())
+System.print(garbage[1])
+"#,
+        "null\n1000\n",
+    );
+}
+
+#[test]
+fn string_repeat() {
+    expect_output(
+        "System.print(\"ab\" * 3)\nSystem.print(\"ab\" * 1)\nSystem.print((\"ab\" * 0) == \"\")\n",
+        "ababab\nab\ntrue\n",
+    );
+    expect_runtime_error("\"ab\" * -1", "Count must be a non-negative integer.");
+    expect_runtime_error("\"ab\" * 1.5", "Count must be a non-negative integer.");
+    expect_runtime_error("\"ab\" * 1e20", "Count too large.");
+    expect_runtime_error("\"ab\" * 1e9", "Count too large.");
+    expect_output("System.print((\"\" * 1e20).count)\n", "0\n");
+}
+
+#[test]
+fn num_to_string_matches_c_formatting() {
+    expect_output(
+        "System.print(0.1)\nSystem.print(100)\nSystem.print(1e21)\nSystem.print(0 / 0)\nSystem.print(1 / 3)\nSystem.print(123456789012345678)\nSystem.print(1e-7)\n",
+        "0.1\n100\n1e+21\nnan\n0.33333333333333\n1.2345678901235e+17\n1e-07\n",
+    );
+}
+
+#[test]
+fn descending_ranges_iterate_downward() {
+    expect_output(
+        "System.print((5..1).toList.join(\",\"))\nSystem.print((5...1).toList.join(\",\"))\nSystem.print((1...1).toList.count)\n",
+        "5,4,3,2,1\n5,4,3,2\n0\n",
+    );
+}
+
+#[test]
+fn system_write_adds_no_newline() {
+    expect_output(
+        "System.write(\"a\")\nSystem.write(\"b\")\nfor (i in 1..3) System.write(i)\nSystem.print()\nSystem.print(\"c\")\n",
+        "ab123\nc\n",
+    );
+}
+
+#[test]
+fn object_same_compares_identity() {
+    expect_output(
+        "var a = \"ab\" + \"c\"\nvar b = \"abc\"\nSystem.print(a == b)\nSystem.print(Object.same(a, b))\nSystem.print(Object.same(a, a))\nSystem.print(Object.same(1, 1))\n",
+        "true\nfalse\ntrue\ntrue\n",
+    );
+}
+
+#[test]
+fn list_sort() {
+    expect_output(
+        r#"
+System.print([3, 1, 2].sort().join(","))
+System.print([3, 1, 2].sort {|a, b| a > b }.join(","))
+
+class Pair {
+  construct new(key, name) {
+    _key = key
+    _name = name
+  }
+  key { _key }
+  name { _name }
+}
+
+// Elements that compare equal keep their order.
+var pairs = [Pair.new(2, "a"), Pair.new(1, "b"), Pair.new(2, "c"), Pair.new(1, "d")]
+System.print(pairs.sort {|a, b| a.key < b.key }.map {|pair| pair.name }.join(","))
+"#,
+        "1,2,3\n3,2,1\nb,d,a,c\n",
+    );
+    expect_runtime_error(
+        "class A {\n  construct new() {}\n}\n[A.new(), A.new()].sort()\n",
+        "A does not implement '<(_)'.",
+    );
+}
+
+#[test]
+fn bytes_append_and_convert() {
+    expect_output(
+        "var bytes = Bytes.new()\nbytes.add(104)\nbytes.add(105)\nSystem.print(bytes.count)\nSystem.print(bytes[1])\nSystem.print(bytes)\nbytes[0] = 72\nSystem.print(bytes)\nSystem.print(Bytes.fromString(\"h\u{e9}\").count)\n",
+        "2\n105\nhi\nHi\n3\n",
+    );
+    expect_runtime_error("Bytes.new().add(256)", "Byte must be between 0 and 255.");
+    expect_runtime_error("Bytes.new().add(-1)", "Byte must be between 0 and 255.");
+    expect_runtime_error(
+        "var bytes = Bytes.fromString(\"a\")\nbytes[0] = 300",
+        "Byte must be between 0 and 255.",
+    );
+    expect_runtime_error("Bytes.new().add(1.5)", "Byte must be an integer.");
+}
+
+#[test]
+fn string_iterates_by_code_point() {
+    expect_output(
+        r#"
+var word = "héllo"
+System.print(word.count)
+System.print(word.bytes.count)
+System.print(word.toList.map {|c| c.bytes.count }.join(","))
+System.print(word.bytes.toList.join(","))
+
+// A byte that doesn't start a valid sequence is a character of its own.
+System.print("a\xffb".toList.map {|c| c.bytes.count }.join(","))
+"#,
+        "5\n6\n1,2,1,1,1\n104,195,169,108,108,111\n1,1,1\n",
+    );
+}
+
+#[test]
+fn range_bounds() {
+    expect_output(
+        "var r = 5..1\nSystem.print(r.from)\nSystem.print(r.to)\nSystem.print(r.min)\nSystem.print(r.max)\nSystem.print(r.isInclusive)\nSystem.print((1...3).isInclusive)\nSystem.print((1...3).max)\n",
+        "5\n1\n1\n5\ntrue\nfalse\n3\n",
+    );
+}
+
+#[test]
+fn list_swap() {
+    expect_output(
+        "var list = [1, 2, 3, 4]\nlist.swap(0, 3)\nSystem.print(list.join(\",\"))\nlist.swap(-1, -2)\nSystem.print(list.join(\",\"))\n",
+        "4,2,3,1\n4,2,1,3\n",
+    );
+    expect_runtime_error("[1, 2].swap(0, 2)", "Index 1 out of bounds.");
+    expect_runtime_error("[1, 2].swap(-3, 0)", "Index 0 out of bounds.");
+}
+
+#[test]
+fn list_insert_all() {
+    expect_output(
+        "System.print([1, 4].insertAll(1, [2, 3]).join(\",\"))\nSystem.print([1, 2].insertAll(-1, 3..4).join(\",\"))\nvar list = [1, 2]\nSystem.print(list.insertAll(0, list).join(\",\"))\n",
+        "1,2,3,4\n1,2,3,4\n1,2,1,2\n",
+    );
+}
+
+#[test]
+fn fn_arity_and_to_string() {
+    expect_output(
+        "System.print(Fn.new {|a, b| a + b }.arity)\nSystem.print(Fn.new { 1 }.arity)\nSystem.print(Fn.new {|a| a })\n",
+        "2\n0\n<fn>\n",
+    );
+}
+
+#[test]
+fn list_reversed_and_reverse() {
+    expect_output(
+        "var list = [1, 2, 3]\nSystem.print(list.reversed.join(\",\"))\nSystem.print(list.join(\",\"))\nSystem.print(list.reverse == list)\nSystem.print(list.join(\",\"))\nSystem.print([].reversed.count)\nSystem.print([].reverse.count)\n",
+        "3,2,1\n1,2,3\ntrue\n3,2,1\n0\n0\n",
+    );
+}
+
+#[test]
+fn map_add_all_and_clear() {
+    expect_output(
+        "var map = {\"a\": 1, \"b\": 2}\nmap.addAll({\"b\": 3, \"c\": 4})\nSystem.print(map.count)\nSystem.print(map[\"a\"])\nSystem.print(map[\"b\"])\nSystem.print(map[\"c\"])\nmap.clear()\nSystem.print(map.count)\nSystem.print(map.containsKey(\"a\"))\n",
+        "3\n1\n3\n4\n0\nfalse\n",
+    );
+}
+
+#[test]
+fn num_to_string_in_a_base() {
+    expect_output(
+        "System.print(255.toString(16))\nSystem.print(5.toString(2))\nSystem.print((-10).toString(2))\nSystem.print(35.toString(36))\nSystem.print(255.toString)\n",
+        "ff\n101\n-1010\nz\n255\n",
+    );
+    expect_runtime_error("1.toString(37)", "Base must be between 2 and 36.");
+    expect_runtime_error("1.toString(1)", "Base must be between 2 and 36.");
+    expect_runtime_error("1.5.toString(2)", "Receiver must be an integer.");
+}
+
+#[test]
+fn range_contains() {
+    expect_output(
+        r#"
+System.print((1..10).contains(10))
+System.print((1...10).contains(10))
+System.print((1..10).contains(0))
+System.print((1..10).contains(2.5))
+System.print((10..1).contains(5))
+System.print((10...1).contains(10))
+System.print((10...1).contains(1))
+System.print((1..10).contains("a"))
+System.print(5 in 1..10)
+"#,
+        "true\nfalse\nfalse\ntrue\ntrue\ntrue\nfalse\nfalse\ntrue\n",
+    );
+}
+
+#[test]
+fn list_count_with_a_predicate() {
+    expect_output(
+        "var list = [1, \"two\", 3, null, 4.5, [6]]\nSystem.print(list.count {|x| x is Num })\nSystem.print(list.count {|x| x is String })\nSystem.print(list.count)\nSystem.print([].count {|x| true })\n",
+        "3\n1\n6\n0\n",
+    );
+}
+
+#[test]
+fn string_trim_one_side() {
+    expect_output(
+        r#"
+System.print("[" + "  hi  ".trimStart() + "]")
+System.print("[" + "  hi  ".trimEnd() + "]")
+System.print("[" + "\t\r\n hi".trimStart() + "]")
+System.print("[" + "xxhixx".trimStart("x") + "]")
+System.print("[" + "xxhixx".trimEnd("x") + "]")
+System.print("[" + "éaéhiéa".trimStart("éa") + "]")
+System.print("[" + "hiéaé".trimEnd("aé") + "]")
+
+// The set is made of whole characters, not bytes.
+System.print("[" + "hé".trimEnd("\xa9") + "]")
+"#,
+        "[hi  ]\n[  hi]\n[hi]\n[hixx]\n[xxhi]\n[hiéa]\n[hi]\n[hé]\n",
+    );
+    expect_runtime_error("\"a\".trimStart(1)", "Characters must be a string.");
+}
+
+#[test]
+fn nan_comparisons_are_false() {
+    expect_output(
+        "var nan = 0 / 0\nSystem.print(nan < 1)\nSystem.print(nan > 1)\nSystem.print(nan <= 1)\nSystem.print(nan >= 1)\nSystem.print(nan == nan)\nSystem.print(nan != nan)\n",
+        "false\nfalse\nfalse\nfalse\nfalse\ntrue\n",
+    );
+}
+
+#[test]
+fn fiber_abort() {
+    expect_output(
+        "Fiber.abort(null)\nSystem.print(\"continued\")\nvar fiber = Fiber.new { Fiber.abort(\"oops\") }\nSystem.print(fiber.try())\n",
+        "continued\noops\n",
+    );
+
+    // An error nothing catches aborts the script.
+    let run = run("var fiber = Fiber.new { Fiber.abort(\"inner\") }\nfiber.call()\nSystem.print(\"unreachable\")\n");
+    assert_eq!(run.result, InterpretResult::RuntimeError);
+    assert_eq!(run.output, "");
+    assert_eq!(run.errors[0], "inner");
+}
+
+#[test]
+fn string_subscript() {
+    expect_output(
+        "System.print(\"hello\"[1])\nSystem.print(\"hello\"[-1])\nSystem.print(\"héllo\"[1])\nSystem.print(\"héllo\"[3])\n\n// An index inside a sequence gives that one byte.\nSystem.print(\"héllo\"[2].bytes.toList.join(\",\"))\n",
+        "e\no\né\nl\n169\n",
+    );
+    expect_runtime_error("\"abc\"[3]", "Subscript out of bounds.");
+    expect_runtime_error("\"abc\"[-4]", "Subscript out of bounds.");
+    expect_runtime_error("\"abc\"[1.5]", "Subscript must be an integer.");
+}
+
+#[test]
+fn list_remove() {
+    expect_output(
+        "var list = [1, 2, 3, 4]\nSystem.print(list.removeAt(-1))\nSystem.print(list.join(\",\"))\nSystem.print(list.remove(2))\nSystem.print(list.join(\",\"))\nSystem.print(list.remove(9))\nSystem.print(list.join(\",\"))\n",
+        "4\n1,2,3\n2\n1,3\nnull\n1,3\n",
+    );
+    expect_runtime_error("[1, 2].removeAt(2)", "Index out of bounds.");
+}
+
+#[test]
+fn map_stored_null_is_not_a_missing_key() {
+    expect_output(
+        "var map = {\"a\": null}\nSystem.print(map[\"a\"])\nSystem.print(map[\"b\"])\nSystem.print(map.containsKey(\"a\"))\nSystem.print(map.containsKey(\"b\"))\nSystem.print(map.count)\n",
+        "null\nnull\ntrue\nfalse\n1\n",
+    );
+}
+
+#[test]
+fn to_string_of_core_objects() {
+    expect_output(
+        "System.print(Fiber.new {})\nSystem.print(1..3)\nSystem.print(1...3)\nSystem.print(1.5..2)\nSystem.print({\"a\": 1})\nSystem.print({})\n",
+        "<fiber>\n1..3\n1...3\n1.5..2\n{a: 1}\n{}\n",
+    );
+}
+
+#[test]
+fn list_add_and_add_all() {
+    expect_output(
+        "var list = [0]\nSystem.print(list.add(5))\nSystem.print(list.addAll(1..3) == list)\nSystem.print(list.join(\",\"))\n",
+        "5\ntrue\n0,5,1,2,3\n",
+    );
+}
+
+#[test]
+fn string_split() {
+    expect_output(
+        "System.print(\"a,,b\".split(\",\").join(\"|\"))\nSystem.print(\",a,\".split(\",\").count)\nSystem.print(\"abc\".split(\",\").join(\"|\"))\n\n// Even an empty string has one piece.\nvar pieces = \"\".split(\",\")\nSystem.print(pieces.count)\nSystem.print(pieces[0] == \"\")\n",
+        "a||b\n3\nabc\n1\ntrue\n",
+    );
+    expect_runtime_error("\"a\".split(\"\")", "Delimiter must be a non-empty string.");
+}
+
+#[test]
+fn map_and_where_are_lazy() {
+    // Nothing past the element the loop stops at is transformed. Each element
+    // that passes the filter is transformed twice: once to test it and again
+    // when the loop reads it.
+    expect_output(
+        "var calls = 0\nvar doubled = (1..10).map {|x|\n  calls = calls + 1\n  return x * 2\n}.where {|x| x > 4 }\nSystem.print(calls)\nfor (x in doubled) {\n  System.print(x)\n  if (x == 8) break\n}\nSystem.print(calls)\n",
+        "0\n6\n8\n6\n",
+    );
+}
+
+#[test]
+fn skip_and_take() {
+    expect_output(
+        "System.print((1..100).skip(2).take(3).toList.join(\",\"))\nSystem.print([1, 2].skip(5).count)\nSystem.print([1, 2].take(0).count)\n",
+        "3,4,5\n0\n0\n",
+    );
+    expect_runtime_error("(1..3).skip(-1)", "Count must be a non-negative integer.");
+    expect_runtime_error("(1..3).take(-1)", "Count must be a non-negative integer.");
+}
+
+#[test]
+fn skip_and_take_are_lazy_over_an_infinite_sequence() {
+    // Each element comes from resuming a fiber that never finishes, so this only
+    // terminates if skip and take ask for no more elements than they need.
+    expect_output(
+        r#"class Naturals is Sequence {
+  construct new() {
+    _produced = 0
+    _generator = Fiber.new {
+      var n = 0
+      while (true) {
+        n = n + 1
+        _produced = n
+        Fiber.yield(n)
+      }
+    }
+  }
+
+  produced { _produced }
+
+  iterate(iterator) { _generator.call() }
+  iteratorValue(iterator) { iterator }
+}
+
+var naturals = Naturals.new()
+var window = naturals.skip(2).take(3)
+System.print(naturals.produced)
+System.print(window.toList.join(","))
+System.print(naturals.produced)
+"#,
+        "0\n3,4,5\n5\n",
+    );
+}
+
+#[test]
+fn classes_are_instances_of_class() {
+    expect_output(
+        "class Foo {\n  construct new() {}\n}\nSystem.print(Num is Class)\nSystem.print(Foo is Class)\nSystem.print(Class is Class)\nSystem.print(1 is Class)\nSystem.print(Foo.new() is Class)\n",
+        "true\ntrue\ntrue\nfalse\nfalse\n",
+    );
+}
+
+#[test]
+fn string_join_puts_itself_between_elements() {
+    expect_output(
+        "System.print(\", \".join([1, 2, 3]))\nSystem.print(\"-\".join(1..3))\nSystem.print(\", \".join([\"a\"]))\nSystem.print(\", \".join([]) == \"\")\n",
+        "1, 2, 3\n1-2-3\na\ntrue\n",
+    );
+}
+
+#[test]
+fn num_clamp() {
+    expect_output(
+        "System.print(1.clamp(2, 5))\nSystem.print(3.clamp(2, 5))\nSystem.print(9.clamp(2, 5))\nSystem.print(2.clamp(2, 2))\n",
+        "2\n3\n5\n2\n",
+    );
+    expect_runtime_error(
+        "3.clamp(5, 2)",
+        "Min value must not be greater than max value.",
+    );
+    expect_runtime_error("3.clamp(\"a\", 2)", "Min value must be a number.");
+}
+
+#[test]
+fn list_clear_returns_null() {
+    expect_output(
+        "var list = [1, 2, 3]\nSystem.print(list.count)\nSystem.print(list.clear())\nSystem.print(list.count)\n\n// The list can be filled again.\nlist.add(4)\nSystem.print(list.join(\",\"))\n",
+        "3\nnull\n0\n4\n",
+    );
+}
+
+#[test]
+fn string_index_of() {
+    expect_output(
+        "System.print(\"hello\".indexOf(\"l\"))\nSystem.print(\"hello\".indexOf(\"lo\"))\nSystem.print(\"hello\".indexOf(\"z\"))\nSystem.print(\"hello\".indexOf(\"\"))\nSystem.print(\"\".indexOf(\"\"))\n\n// The index counts bytes, not code points.\nSystem.print(\"héllo\".indexOf(\"l\"))\n",
+        "2\n3\n-1\n0\n0\n3\n",
+    );
+}
+
+#[test]
+fn string_contains() {
+    expect_output(
+        "System.print(\"hello\".contains(\"ell\"))\nSystem.print(\"hello\".contains(\"z\"))\nSystem.print(\"hello\".contains(\"\"))\nSystem.print(\"\".contains(\"\"))\n\n// A multibyte character matches only itself.\nSystem.print(\"héllo\".contains(\"é\"))\nSystem.print(\"héllo\".contains(\"e\"))\n",
+        "true\nfalse\ntrue\ntrue\ntrue\nfalse\n",
+    );
+}
+
+#[test]
+fn num_integer_parts() {
+    expect_output(
+        "System.print((1 / 0).isInteger)\nSystem.print((0 / 0).isInteger)\nSystem.print(3.isInteger)\nSystem.print((-2.5).truncate)\nSystem.print((-2.5).fraction)\nSystem.print(2.5.truncate)\nSystem.print((-1 / 0).truncate)\n",
+        "false\nfalse\ntrue\n-2\n-0.5\n2\n-infinity\n",
+    );
+}
+
+#[test]
+fn list_search_uses_equality() {
+    expect_output(
+        r#"class Point {
+  construct new(x) { _x = x }
+  x { _x }
+  ==(other) {
+    if (!(other is Point)) return false
+    return _x == other.x
+  }
+}
+var list = [1, 2.0, Point.new(3)]
+System.print(list.indexOf(2))
+System.print(list.contains(1.0))
+System.print(list.indexOf(5))
+
+// A different object with an equal value is found.
+System.print(list.indexOf(Point.new(3)))
+System.print(list.contains(Point.new(4)))
+"#,
+        "1\ntrue\n-1\n2\nfalse\n",
+    );
+}
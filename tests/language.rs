@@ -0,0 +1,392 @@
+mod common;
+
+use common::{expect_output, run_with};
+use starling::{Config, InterpretResult};
+
+#[test]
+fn fields_start_null_and_can_be_assigned() {
+    expect_output(
+        r#"
+class Point {
+  construct new() {}
+  x { _x }
+  y { _y }
+  setX(value) { _x = value }
+}
+
+var point = Point.new()
+System.print(point.x)
+System.print(point.y)
+point.setX(3)
+System.print(point.x)
+System.print(point.y)
+"#,
+        "null\nnull\n3\nnull\n",
+    );
+}
+
+#[test]
+fn constructor_initializes_the_new_instance() {
+    expect_output(
+        r#"
+class Point {
+  construct new(x, y) {
+    _x = x
+    _y = y
+  }
+  x { _x }
+  y { _y }
+}
+
+var point = Point.new(1, 2)
+System.print(point is Point)
+System.print(point.x)
+System.print(point.y)
+System.print(Point.new(3, 4).x)
+"#,
+        "true\n1\n2\n3\n",
+    );
+}
+
+#[test]
+fn getter_and_setter_are_separate_methods() {
+    expect_output(
+        r#"
+class Box {
+  construct new() { _value = 0 }
+  value { _value }
+  value=(v) {
+    System.print("set " + v.toString)
+    _value = v
+  }
+}
+
+var box = Box.new()
+System.print(box.value)
+System.print(box.value = 5)
+System.print(box.value)
+"#,
+        "0\nset 5\nnull\n5\n",
+    );
+}
+
+#[test]
+fn in_calls_contains() {
+    expect_output(
+        r#"
+System.print(2 in [1, 2, 3])
+System.print(4 in [1, 2, 3])
+System.print(2 in 1..3)
+System.print(3 in 1...3)
+System.print("a" in "cat")
+"#,
+        "true\nfalse\ntrue\nfalse\ntrue\n",
+    );
+}
+
+#[test]
+fn runaway_recursion_is_a_catchable_stack_overflow() {
+    let config = Config {
+        max_call_depth: 100,
+        ..Config::default()
+    };
+    let run = run_with(
+        config,
+        r#"
+class Forever {
+  static recurse(n) { recurse(n + 1) }
+}
+
+var fiber = Fiber.new { Forever.recurse(0) }
+System.print(fiber.try())
+System.print(fiber.isDone)
+System.print("still running")
+"#,
+    );
+    assert_eq!(run.errors, Vec::<String>::new());
+    assert_eq!(run.result, InterpretResult::Success);
+    assert_eq!(run.output, "Stack overflow.\ntrue\nstill running\n");
+}
+
+#[test]
+fn subclass_inherits_superclass_methods() {
+    expect_output(
+        r#"
+class Animal {
+  construct new() {}
+  name { "animal" }
+  describe { "I am an " + name }
+}
+
+class Dog is Animal {
+  construct new() {}
+  name { "dog" }
+}
+
+class Puppy is Dog {
+  construct new() {}
+}
+
+System.print(Animal.new().describe)
+System.print(Dog.new().describe)
+System.print(Puppy.new().describe)
+System.print(Puppy.new() is Animal)
+"#,
+        "I am an animal\nI am an dog\nI am an dog\ntrue\n",
+    );
+}
+
+#[test]
+fn class_reflection() {
+    expect_output(
+        r#"
+class Parent {}
+class Child is Parent {}
+
+System.print(Parent.name)
+System.print(Child.name is String)
+System.print(Child.supertype == Parent)
+System.print(Child.supertype)
+System.print(Parent.supertype)
+System.print(Object.supertype == null)
+System.print(Child)
+System.print(Child.toString == Child.name)
+"#,
+        "Parent\ntrue\ntrue\nParent\nObject\ntrue\nChild\ntrue\n",
+    );
+}
+
+#[test]
+fn missing_method_names_its_full_signature() {
+    common::expect_runtime_error(
+        "class A {\n  construct new() {}\n}\nA.new().bar(1, 2)\n",
+        "A does not implement 'bar(_,_)'.",
+    );
+}
+
+#[test]
+fn compound_assignment_on_a_local() {
+    expect_output(
+        "{\n  var x = 10\n  x += 5\n  x -= 3\n  x *= 2\n  x /= 4\n  System.print(x)\n}\n",
+        "6\n",
+    );
+}
+
+#[test]
+fn compound_assignment_on_a_field() {
+    expect_output(
+        r#"
+class Counter {
+  construct new() { _count = 0 }
+  add(n) { _count += n }
+  count { _count }
+}
+
+var counter = Counter.new()
+counter.add(2)
+counter.add(3)
+System.print(counter.count)
+"#,
+        "5\n",
+    );
+}
+
+#[test]
+fn compound_assignment_on_a_subscript_evaluates_the_index_once() {
+    expect_output(
+        r#"
+class Index {
+  construct new() { _calls = 0 }
+  next {
+    _calls = _calls + 1
+    return 1
+  }
+  calls { _calls }
+}
+
+var index = Index.new()
+var list = [10, 20, 30]
+list[index.next] += 1
+System.print(list[1])
+System.print(index.calls)
+"#,
+        "21\n1\n",
+    );
+}
+
+#[test]
+fn bare_name_in_a_method_calls_a_method_on_this() {
+    expect_output(
+        r#"
+class Greeter {
+  construct new(name) { _name = name }
+  name { _name }
+  greet(greeting) { greeting + ", " + name }
+  hello { greet("hello") }
+  shadowed {
+    var name = "local"
+    return name
+  }
+}
+
+System.print(Greeter.new("wren").hello)
+System.print(Greeter.new("wren").shadowed)
+"#,
+        "hello, wren\nlocal\n",
+    );
+}
+
+#[test]
+fn closures_capture_each_iteration_across_continue_and_break() {
+    expect_output(
+        r#"
+var fns = []
+for (i in 1..5) {
+  var value = i * 10
+  fns.add(Fn.new { value })
+  if (i == 2) continue
+  if (i == 4) break
+}
+System.print(fns.map {|f| f.call() }.join(","))
+
+fns = []
+var i = 0
+while (i < 5) {
+  i = i + 1
+  var value = i
+  fns.add(Fn.new { value })
+  if (i % 2 == 0) continue
+}
+System.print(fns.map {|f| f.call() }.join(","))
+"#,
+        "10,20,30,40\n1,2,3,4,5\n",
+    );
+}
+
+#[test]
+fn blocks_nested_three_deep_read_the_outermost_local() {
+    expect_output(
+        r#"
+var outer = Fn.new {
+  var value = "outermost"
+  return Fn.new {
+    return Fn.new {
+      return Fn.new { value }
+    }
+  }
+}
+System.print(outer.call().call().call().call())
+"#,
+        "outermost\n",
+    );
+}
+
+#[test]
+fn block_in_a_method_captures_this() {
+    expect_output(
+        r#"
+class Box {
+  construct new(value) { _value = value }
+  value { _value }
+  viaThis { Fn.new { this.value } }
+  viaField { Fn.new { _value } }
+}
+
+var invoke = Fn.new {|f| f.call() }
+var box = Box.new(42)
+System.print(invoke.call(box.viaThis))
+System.print(invoke.call(box.viaField))
+"#,
+        "42\n42\n",
+    );
+}
+
+#[test]
+fn return_in_a_block_returns_from_the_block() {
+    expect_output(
+        r#"
+class Classifier {
+  construct new() {}
+  classify(list) {
+    var labels = list.map {|n|
+      if (n < 0) {
+        return "negative"
+      }
+      return "positive"
+    }.toList
+    System.print("still in the method")
+    return labels.join(",")
+  }
+}
+
+System.print(Classifier.new().classify([1, -2, 3]))
+"#,
+        "still in the method\npositive,negative,positive\n",
+    );
+}
+
+#[test]
+fn safe_call_skips_the_rest_of_the_chain_on_null() {
+    expect_output(
+        r#"
+class Node {
+  construct new() {}
+  foo {
+    System.print("foo called")
+    return this
+  }
+  bar { "bar" }
+  list { [1, 2] }
+}
+
+var missing = null
+System.print(missing?.foo)
+System.print(missing?.foo.bar)
+System.print(missing?.list[0])
+System.print(missing?.foo == null)
+
+var node = Node.new()
+System.print(node?.foo.bar)
+System.print(node?.list[1])
+"#,
+        "null\nnull\nnull\ntrue\nfoo called\nbar\n2\n",
+    );
+}
+
+#[test]
+fn calling_a_value_that_is_not_a_function() {
+    common::expect_runtime_error("42()", "Num does not implement 'call()'.");
+    common::expect_runtime_error("\"x\"(1)", "String does not implement 'call(_)'.");
+}
+
+#[test]
+fn assignment_is_an_expression() {
+    expect_output(
+        "var a\nvar b = (a = 7)\nSystem.print(a)\nSystem.print(b)\n\n// Assignment is right-associative.\nSystem.print(a = b = 3)\nSystem.print(a + b)\n",
+        "7\n7\n3\n6\n",
+    );
+}
+
+#[test]
+fn string_interpolation() {
+    expect_output(
+        r#"var b = 2
+System.print("a %(b) c %(b * 3)")
+System.print("%(b)")
+System.print("%("in" + "ner %(b + 1)")!")
+System.print("%([1, 2].join(","))")
+System.print("%(
+  b
+)")
+"#,
+        "a 2 c 6\n2\ninner 3!\n1,2\n2\n",
+    );
+}
+
+#[test]
+fn range_operators_bind_looser_than_arithmetic() {
+    expect_output(
+        "System.print(1 + 2 .. 3)\nSystem.print(1..2 + 3)\nSystem.print(1..3 == 1..3)\nSystem.print(1..3 == 1...3)\n",
+        "3..3\n1..5\ntrue\nfalse\n",
+    );
+}